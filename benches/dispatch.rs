@@ -0,0 +1,89 @@
+// 比较不同虚拟机、不同分派方式执行同一程序时分派的指令条数和耗时
+// cargo bench --bench dispatch
+
+use std::time::{Duration, Instant};
+
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::peephole;
+use cilly::bytecode_translation::register::translate_register;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::vm::register::RegVM;
+use cilly::vm::VM;
+
+const FACT: &str = r#"
+fn fact(n: i32) -> i32 {
+    if(n == 0) return 1;
+    return n * fact(n - 1);
+}
+
+fn main() {
+    var i: i32 = 0;
+    var res: i32 = 0;
+    while(i < 20000) {
+        res = fact(12);
+        i = i + 1;
+    }
+    print(res);
+}
+"#;
+
+const FEB: &str = r#"
+fn feb(n: i32) -> i32 {
+    if(n < 2) {
+        return 1;
+    } else {
+        return feb(n - 1) + feb(n - 2);
+    }
+}
+
+fn main() {
+    print(feb(24));
+}
+"#;
+
+// 重复执行的次数，取最短耗时
+const ROUNDS: usize = 5;
+
+fn measure(mut run: impl FnMut() -> u64) -> (u64, Duration) {
+    let mut best = Duration::MAX;
+    let mut steps = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        steps = run();
+        best = best.min(start.elapsed());
+    }
+    (steps, best)
+}
+
+fn main() {
+    println!("{:<8} {:<10} {:>14} {:>12}", "program", "vm", "dispatches", "time(ms)");
+    for (name, source) in [("fact", FACT), ("feb", FEB)] {
+        let ast = CompUnitParser::new().parse(source).unwrap();
+
+        let code = translate_module(&mut ast.clone(), &mut Environment::new()).unwrap();
+        let optimized = peephole::optimize(code.clone());
+        for (label, code, threaded) in [
+            ("stack", &code, false),
+            ("threaded", &code, true),
+            ("peephole", &optimized, false),
+            ("peep+thr", &optimized, true),
+        ] {
+            let (steps, time) = measure(|| {
+                let mut vm = VM::new(code.clone());
+                vm.set_threaded(threaded);
+                vm.run().unwrap();
+                vm.steps()
+            });
+            println!("{:<8} {:<10} {:>14} {:>12.3}", name, label, steps, time.as_secs_f64() * 1000.0);
+        }
+
+        let program = translate_register(&ast).unwrap();
+        let (steps, time) = measure(|| {
+            let mut vm = RegVM::new(program.clone());
+            vm.run().unwrap();
+            vm.steps()
+        });
+        println!("{:<8} {:<10} {:>14} {:>12.3}", name, "register", steps, time.as_secs_f64() * 1000.0);
+    }
+}
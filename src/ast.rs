@@ -4,10 +4,17 @@ pub struct CompUnit {
     pub globaldefs: Vec<GlobalDef>,
 }
 
+// 源代码中的位置，lo 和 hi 为字节偏移
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum GlobalDef {
     FuncDef(FuncDef),
-    Decl(Decl),
+    Decl(Decl, Span),
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +48,7 @@ pub struct FuncDef {
     pub btype: Option<BType>,
     pub funcfparams: Option<FuncFParams>,
     pub block: Block,
+    pub span: Span,     // 函数头（不含函数体）
 }
 
 #[derive(Debug, Clone)]
@@ -72,8 +80,8 @@ pub struct Block {
 
 #[derive(Debug, Clone)]
 pub enum BlockItem {
-    Decl(Decl),
-    Stmt(Stmt),
+    Decl(Decl, Span),
+    Stmt(Stmt, Span),
 }
 
 #[derive(Debug, Clone)]
@@ -87,6 +95,8 @@ pub enum Stmt {
     FuncDef(FuncDef),
     Continue,
     Break,
+    Throw(Exp),
+    Try { body: Block, ident: String, handler: Block },
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,517 @@
+/*!
+ * 字节码的汇编形式 (.cas)：disassemble 把模块转为可读的汇编，assemble 把汇编还原为模块。
+ *
+ *   ; 注释到行尾
+ *   .global n                     全局变量，按出现的顺序编号
+ *   .func fact 1 2 int fact       函数：名字 参数个数 栈帧槽位个数 返回类型(int/float/bool/any/void) 入口
+ *   .local 0 x fact L12           局部变量：槽位 名字 有效范围的开始 结束
+ *   .init_end fact                目标模块：全局变量初始化代码的结束地址
+ *   .export func fact             目标模块导出的函数或全局变量（名字或编号）
+ *   .import func helper 2         目标模块导入的函数（名字 参数个数）或全局变量（名字），编号接在本模块定义的之后
+ *   fact:                         标号，值为下一条指令的地址
+ *       .file "fact.cil"          下一条指令开始属于这个源文件
+ *       .line 3                   下一条指令开始属于源代码第 3 行
+ *       EnterFrame 2
+ *       CmpJmp Gt L12             跳转目标、入口和范围可以写标号或地址
+ *       Call fact 1               Call 的函数、LoadGlobal/StoreGlobal 的变量可以写名字或编号
+ *
+ * 有 .file、.line 或 .local 时模块带调试信息，有 .init_end、.export 或 .import 时为目标模块。
+ * 函数按 .func 出现的顺序编号。名字不是标识符时写成带引号的字符串。
+ * 反汇编输出的每一项都能原样汇编回来，两次转换得到的模块与原来相同。
+ */
+
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::native::NativeType;
+use crate::vm::module::{DebugInfo, Export, Function, Import, LinkInfo, LocalVar, Module, SymbolKind};
+use crate::vm::OpCode;
+
+use super::translate::{translate_from, translate_to};
+
+// 指令的操作数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    Num,        // 无符号数（槽位、个数等）
+    Imm,        // 有符号的立即数
+    Target,     // 跳转地址
+    Func,       // 函数表中的编号
+    Global,     // 全局变量编号
+    Cmp,        // CmpJmp 的比较方式
+}
+
+// 助记符、文本形式中的操作码、操作数
+const OPS: &[(&str, usize, &[Arg])] = &[
+    ("LoadConst", 1, &[Arg::Imm]),
+    ("LoadTrue", 2, &[]),
+    ("LoadFalse", 3, &[]),
+    ("LoadNull", 4, &[]),
+    ("LoadGlobal", 5, &[Arg::Global]),
+    ("StoreGlobal", 6, &[Arg::Global]),
+    ("Jmp", 10, &[Arg::Target]),
+    ("JmpTrue", 11, &[Arg::Target]),
+    ("JmpFalse", 12, &[Arg::Target]),
+    ("PrintItem", 13, &[]),
+    ("PrintNewline", 14, &[]),
+    ("GetInt", 15, &[]),
+    ("Pop", 16, &[]),
+    ("UniOpNot", 17, &[]),
+    ("UniOpNeg", 18, &[]),
+    ("StorePC", 19, &[]),
+    ("LoadPC", 20, &[]),
+    ("StoreVar", 21, &[Arg::Num]),
+    ("LoadVar", 22, &[Arg::Num]),
+    ("EnterFrame", 23, &[Arg::Num]),
+    ("MakeClosure", 25, &[]),
+    ("Call", 26, &[Arg::Func, Arg::Num]),
+    ("Ret", 27, &[]),
+    ("NewArray", 28, &[Arg::Num]),
+    ("ArrayGet", 29, &[]),
+    ("ArraySet", 30, &[]),
+    ("ArrayLen", 31, &[]),
+    ("NewStruct", 32, &[Arg::Num]),
+    ("GetField", 33, &[Arg::Num]),
+    ("SetField", 34, &[Arg::Num]),
+    ("AddConst", 35, &[Arg::Imm]),
+    ("CmpJmp", 36, &[Arg::Cmp, Arg::Target]),
+    ("IncVar", 37, &[Arg::Num, Arg::Imm]),
+    ("TeeVar", 38, &[Arg::Num]),
+    ("CallNative", 39, &[Arg::Num, Arg::Num]),
+    ("PushHandler", 40, &[Arg::Target]),
+    ("PopHandler", 41, &[]),
+    ("Throw", 42, &[]),
+    ("BinOpAdd", 100, &[]),
+    ("BinOpSub", 101, &[]),
+    ("BinOpMul", 102, &[]),
+    ("BinOpDiv", 103, &[]),
+    ("BinOpGt", 104, &[]),
+    ("BinOpGe", 105, &[]),
+    ("BinOpLt", 106, &[]),
+    ("BinOpLe", 107, &[]),
+    ("BinOpEq", 108, &[]),
+    ("BinOpNe", 109, &[]),
+    ("BinOpOr", 110, &[]),
+    ("BinOpAnd", 111, &[]),
+    ("BinOpMod", 112, &[]),
+];
+
+// CmpJmp 的比较方式，编码与对应的 BinOp 指令相同
+const CMPS: &[(&str, usize)] = &[("Gt", 104), ("Ge", 105), ("Lt", 106), ("Le", 107), ("Eq", 108), ("Ne", 109)];
+
+const RETS: &[(&str, Option<NativeType>)] = &[
+    ("void", None),
+    ("int", Some(NativeType::Int)),
+    ("float", Some(NativeType::Float)),
+    ("bool", Some(NativeType::Bool)),
+    ("any", Some(NativeType::Any)),
+];
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// 反汇编生成的标号形如 L12，函数名与之相同时不能作为标号
+fn is_pc_label(s: &str) -> bool {
+    s.len() > 1 && s.starts_with('L') && s[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+// 名字是标识符时原样输出，否则输出带引号的字符串
+fn name(s: &str) -> String {
+    if is_ident(s) {
+        s.to_string()
+    } else {
+        format!("{:?}", s)
+    }
+}
+
+pub fn disassemble(module: &Module) -> String {
+    let len = module.code.len();
+    let unique = |names: &[&str], i: usize| names.iter().filter(|n| **n == names[i]).count() == 1;
+    // 目标模块导入的符号接在本模块定义的之后
+    let imports = |kind: SymbolKind| module.link.iter().flat_map(move |link| link.imports_of(kind)).map(|i| i.name.as_str());
+    let func_names: Vec<&str> = module.funcs.iter().map(|f| f.name.as_str()).chain(imports(SymbolKind::Func)).collect();
+    let global_names: Vec<&str> = module.globals.iter().map(|g| g.as_str()).chain(imports(SymbolKind::Global)).collect();
+
+    // 每个被引用的地址一个标号：函数入口用函数名，其余用 L 加地址
+    let mut labels: HashMap<usize, String> = HashMap::new();
+    for (i, f) in module.funcs.iter().enumerate() {
+        if f.entry <= len && is_ident(&f.name) && !is_pc_label(&f.name) && unique(&func_names, i) {
+            labels.entry(f.entry).or_insert(f.name.clone());
+        }
+    }
+    let mut addrs: Vec<usize> = module.funcs.iter().map(|f| f.entry).collect();
+    addrs.extend(module.code.iter().filter_map(target));
+    addrs.extend(module.link.iter().map(|link| link.init_len));
+    if let Some(debug) = &module.debug {
+        addrs.extend(debug.locals.iter().flat_map(|v| [v.start, v.end]));
+    }
+    for addr in addrs {
+        if addr <= len {
+            labels.entry(addr).or_insert(format!("L{}", addr));
+        }
+    }
+    // 超出代码范围的地址没有标号，直接输出数值
+    let addr = |pc: usize| labels.get(&pc).cloned().unwrap_or(pc.to_string());
+
+    let mut res = String::new();
+    for g in &module.globals {
+        res.push_str(&format!(".global {}\n", name(g)));
+    }
+    for f in &module.funcs {
+        let ret = RETS.iter().find(|(_, ret)| *ret == f.ret).unwrap().0;
+        res.push_str(&format!(".func {} {} {} {} {}\n", name(&f.name), f.arity, f.nlocals, ret, addr(f.entry)));
+    }
+    if let Some(debug) = &module.debug {
+        for v in &debug.locals {
+            res.push_str(&format!(".local {} {} {} {}\n", v.slot, name(&v.name), addr(v.start), addr(v.end)));
+        }
+    }
+    if let Some(link) = &module.link {
+        res.push_str(&format!(".init_end {}\n", addr(link.init_len)));
+        let kind = |kind: SymbolKind| match kind {
+            SymbolKind::Func => "func",
+            SymbolKind::Global => "global",
+        };
+        for e in &link.exports {
+            let names = if e.kind == SymbolKind::Func { &func_names } else { &global_names };
+            // 名字有歧义时写编号
+            let symbol = match names.get(e.index) {
+                Some(n) if *n == e.name && is_ident(n) && unique(names, e.index) => n.to_string(),
+                _ => e.index.to_string(),
+            };
+            res.push_str(&format!(".export {} {}{}\n", kind(e.kind), symbol, if symbol == e.name { String::new() } else { format!(" {}", name(&e.name)) }));
+        }
+        for i in &link.imports {
+            match i.kind {
+                SymbolKind::Func => res.push_str(&format!(".import func {} {}\n", name(&i.name), i.arity)),
+                SymbolKind::Global => res.push_str(&format!(".import global {}\n", name(&i.name))),
+            }
+        }
+    }
+
+    let mut files = module.debug.iter().flat_map(|debug| debug.files.iter()).peekable();
+    let mut lines = module.debug.iter().flat_map(|debug| debug.lines.iter()).peekable();
+    for pc in 0..=len {
+        for f in module.funcs.iter().filter(|f| f.entry == pc) {
+            let ret = RETS.iter().find(|(_, ret)| *ret == f.ret).unwrap().0;
+            res.push_str(&format!("\n; 函数 {}，{} 个参数，{} 个槽位，返回 {}\n", f.name, f.arity, f.nlocals, ret));
+        }
+        if let Some(label) = labels.get(&pc) {
+            res.push_str(&format!("{}:\n", label));
+        }
+        while let Some((_, file)) = files.next_if(|(start, _)| *start <= pc) {
+            res.push_str(&format!("    .file {:?}\n", file));
+        }
+        while let Some((_, line)) = lines.next_if(|(start, _)| *start <= pc) {
+            res.push_str(&format!("    .line {}\n", line));
+        }
+        let Some(op) = module.code.get(pc) else {
+            break;
+        };
+        let words = translate_to(vec![*op]);
+        let (mnemonic, _, args) = OPS.iter().find(|(_, code, _)| *code == words[0]).unwrap();
+        let mut ins = mnemonic.to_string();
+        for (arg, word) in args.iter().zip(&words[1..]) {
+            let operand = match arg {
+                Arg::Num => word.to_string(),
+                Arg::Imm => (*word as i32).to_string(),
+                Arg::Target => addr(*word),
+                Arg::Func if *word < func_names.len() && is_ident(func_names[*word]) && unique(&func_names, *word) => {
+                    func_names[*word].to_string()
+                },
+                Arg::Global if *word < global_names.len() && is_ident(global_names[*word]) && unique(&global_names, *word) => {
+                    global_names[*word].to_string()
+                },
+                Arg::Func | Arg::Global => word.to_string(),
+                Arg::Cmp => CMPS.iter().find(|(_, code)| code == word).unwrap().0.to_string(),
+            };
+            ins.push(' ');
+            ins.push_str(&operand);
+        }
+        // 注释中给出地址和局部变量名
+        let mut comment = pc.to_string();
+        if let OpCode::LoadVar(slot) | OpCode::StoreVar(slot) | OpCode::TeeVar(slot) | OpCode::IncVar(slot, _) = op {
+            if let Some(var) = module.debug.as_ref().and_then(|debug| debug.local(*slot, pc)) {
+                comment.push(' ');
+                comment.push_str(var);
+            }
+        }
+        res.push_str(&format!("    {:<28} ; {}\n", ins, comment));
+    }
+    // 地址在代码末尾之后的项
+    for (_, file) in files {
+        res.push_str(&format!("    .file {:?}\n", file));
+    }
+    for (_, line) in lines {
+        res.push_str(&format!("    .line {}\n", line));
+    }
+    res
+}
+
+fn target(op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::Jmp(t) | OpCode::JmpTrue(t) | OpCode::JmpFalse(t) | OpCode::CmpJmp(_, t) | OpCode::PushHandler(t) => Some(*t),
+        _ => None,
+    }
+}
+
+// 一个词：quoted 表示带引号的字符串
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+// 按空白分词，引号内的字符串支持 Rust 的常见转义；分号之后为注释
+fn tokenize(line: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut res = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err(String::from("字符串缺少结束的引号")),
+                    Some('"') => break,
+                    Some('\\') => text.push(match chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some(c @ ('\\' | '"' | '\'')) => c,
+                        Some('u') => {
+                            let mut hex = String::new();
+                            if chars.next() != Some('{') {
+                                return Err(String::from("非法的转义"));
+                            }
+                            for c in chars.by_ref() {
+                                if c == '}' {
+                                    break;
+                                }
+                                hex.push(c);
+                            }
+                            u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or(format!("非法的转义: \\u{{{}}}", hex))?
+                        },
+                        c => return Err(format!("非法的转义: \\{}", c.map_or(String::new(), String::from))),
+                    }),
+                    Some(c) => text.push(c),
+                }
+            }
+            res.push(Token { text, quoted: true });
+        } else {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' || c == '"' {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+            res.push(Token { text, quoted: false });
+        }
+    }
+    Ok(res)
+}
+
+// 第一遍扫描后待解析的一条指令
+struct Ins<'a> {
+    line: usize,
+    mnemonic: &'a str,
+    code: usize,
+    args: &'a [Arg],
+    operands: Vec<Token>,
+}
+
+// 尚未解析的地址（标号或数值）
+struct Addr {
+    line: usize,
+    token: Token,
+}
+
+pub fn assemble(text: &str) -> Result<Module> {
+    let err = |line: usize, msg: String| Error::BytecodeError(format!("第 {} 行: {}", line, msg));
+    let mut module = Module::default();
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut entries: Vec<Addr> = Vec::new();
+    let mut locals: Vec<(usize, String, Addr, Addr)> = Vec::new();
+    let mut debug: Option<DebugInfo> = None;
+    let mut link: Option<LinkInfo> = None;
+    let mut init_end: Option<Addr> = None;
+    // (种类, 名字或编号, 导出的名字)，函数表和全局变量表完整之后再解析
+    let mut exports: Vec<(usize, SymbolKind, Token, Option<Token>)> = Vec::new();
+    let mut ins: Vec<Ins> = Vec::new();
+
+    // 第一遍：记录标号的地址和各项声明
+    for (i, line) in text.lines().enumerate() {
+        let lineno = i + 1;
+        let mut tokens = tokenize(line).map_err(|e| err(lineno, e))?;
+        if let Some(label) = tokens.first().filter(|t| !t.quoted && t.text.ends_with(':')) {
+            let label = label.text.trim_end_matches(':').to_string();
+            if !is_ident(&label) {
+                return Err(err(lineno, format!("非法的标号: {}", label)));
+            }
+            if labels.insert(label.clone(), ins.len()).is_some() {
+                return Err(err(lineno, format!("标号重复定义: {}", label)));
+            }
+            tokens.remove(0);
+        }
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+        let num = |t: &Token| t.text.parse::<usize>().ok().filter(|_| !t.quoted).ok_or(err(lineno, format!("需要一个数: {}", t.text)));
+        let arity = |n: usize| if tokens.len() == n + 1 {
+            Ok(())
+        } else {
+            Err(err(lineno, format!("{} 需要 {} 个操作数", first.text, n)))
+        };
+        match first.text.as_str() {
+            _ if first.quoted => return Err(err(lineno, format!("不是指令: {:?}", first.text))),
+            ".file" => {
+                arity(1)?;
+                debug.get_or_insert_with(DebugInfo::default).files.push((ins.len(), tokens[1].text.clone()));
+            },
+            ".init_end" => {
+                arity(1)?;
+                link.get_or_insert_with(LinkInfo::default);
+                init_end = Some(Addr { line: lineno, token: tokens.pop().unwrap() });
+            },
+            ".export" => {
+                if tokens.len() != 3 && tokens.len() != 4 {
+                    return Err(err(lineno, String::from(".export 需要种类、名字或编号，以及可选的导出名")));
+                }
+                let kind = symbol_kind(&tokens[1]).ok_or(err(lineno, format!("未知的符号种类: {}", tokens[1].text)))?;
+                link.get_or_insert_with(LinkInfo::default);
+                let alias = if tokens.len() == 4 { tokens.pop() } else { None };
+                exports.push((lineno, kind, tokens.pop().unwrap(), alias));
+            },
+            ".import" => {
+                let kind = tokens.get(1).and_then(symbol_kind).ok_or(err(lineno, String::from(".import 需要符号的种类 func 或 global")))?;
+                let arity = match kind {
+                    SymbolKind::Func => {
+                        arity(3)?;
+                        num(&tokens[3])?
+                    },
+                    SymbolKind::Global => {
+                        arity(2)?;
+                        0
+                    },
+                };
+                link.get_or_insert_with(LinkInfo::default).imports.push(Import { kind, name: tokens[2].text.clone(), arity });
+            },
+            ".global" => {
+                arity(1)?;
+                module.globals.push(tokens[1].text.clone());
+            },
+            ".func" => {
+                arity(5)?;
+                let ret = RETS.iter().find(|(name, _)| *name == tokens[4].text)
+                    .ok_or(err(lineno, format!("未知的返回类型: {}", tokens[4].text)))?.1;
+                module.funcs.push(Function {
+                    name: tokens[1].text.clone(),
+                    entry: 0,
+                    arity: num(&tokens[2])?,
+                    nlocals: num(&tokens[3])?,
+                    ret,
+                });
+                entries.push(Addr { line: lineno, token: tokens.pop().unwrap() });
+            },
+            ".local" => {
+                arity(4)?;
+                let slot = num(&tokens[1])?;
+                let end = Addr { line: lineno, token: tokens.pop().unwrap() };
+                let start = Addr { line: lineno, token: tokens.pop().unwrap() };
+                locals.push((slot, tokens[2].text.clone(), start, end));
+            },
+            ".line" => {
+                arity(1)?;
+                let line = num(&tokens[1])?;
+                debug.get_or_insert_with(DebugInfo::default).lines.push((ins.len(), line));
+            },
+            mnemonic => {
+                let Some((mnemonic, code, args)) = OPS.iter().find(|(name, _, _)| *name == mnemonic) else {
+                    return Err(err(lineno, format!("未知的指令: {}", mnemonic)));
+                };
+                arity(args.len())?;
+                tokens.remove(0);
+                ins.push(Ins { line: lineno, mnemonic, code: *code, args, operands: tokens });
+            },
+        }
+    }
+
+    // 第二遍：解析标号和名字，生成文本形式的指令再还原
+    let addr = |a: &Addr| -> Result<usize> {
+        if !a.token.quoted {
+            if let Some(pc) = labels.get(&a.token.text) {
+                return Ok(*pc);
+            }
+            if let Ok(pc) = a.token.text.parse() {
+                return Ok(pc);
+            }
+        }
+        Err(err(a.line, format!("未定义的标号: {}", a.token.text)))
+    };
+    for (func, entry) in module.funcs.iter_mut().zip(&entries) {
+        func.entry = addr(entry)?;
+    }
+    if !locals.is_empty() {
+        let debug = debug.get_or_insert_with(DebugInfo::default);
+        for (slot, name, start, end) in locals {
+            debug.locals.push(LocalVar { slot, name, start: addr(&start)?, end: addr(&end)? });
+        }
+    }
+    // 导入的符号接在本模块定义的之后
+    let imports = |kind: SymbolKind| link.iter().flat_map(move |link| link.imports_of(kind)).map(|i| i.name.clone());
+    let func_names: Vec<String> = module.funcs.iter().map(|f| f.name.clone()).chain(imports(SymbolKind::Func)).collect();
+    let global_names: Vec<String> = module.globals.iter().cloned().chain(imports(SymbolKind::Global)).collect();
+    if let Some(link) = &mut link {
+        link.init_len = match &init_end {
+            Some(a) => addr(a)?,
+            None => return Err(Error::BytecodeError(String::from("目标模块缺少 .init_end"))),
+        };
+        for (line, kind, symbol, alias) in exports {
+            let names = if kind == SymbolKind::Func { &func_names } else { &global_names };
+            let index = lookup(names, &symbol).ok_or(err(line, format!("未定义的符号: {}", symbol.text)))?;
+            let name = alias.map_or(names.get(index).cloned().unwrap_or_default(), |alias| alias.text);
+            link.exports.push(Export { kind, name, index });
+        }
+    }
+    let mut words = Vec::new();
+    for i in ins {
+        words.push(i.code);
+        for (arg, t) in i.args.iter().zip(i.operands) {
+            let bad = || err(i.line, format!("{} 的操作数非法: {}", i.mnemonic, t.text));
+            let word = match arg {
+                Arg::Num => t.text.parse().ok().filter(|_| !t.quoted).ok_or_else(bad)?,
+                Arg::Imm => t.text.parse::<i32>().ok().filter(|_| !t.quoted).ok_or_else(bad)? as usize,
+                Arg::Target => addr(&Addr { line: i.line, token: t })?,
+                Arg::Func => lookup(&func_names, &t).ok_or_else(|| err(i.line, format!("未定义的函数: {}", t.text)))?,
+                Arg::Global => lookup(&global_names, &t).ok_or_else(|| err(i.line, format!("未定义的全局变量: {}", t.text)))?,
+                Arg::Cmp => CMPS.iter().find(|(name, _)| *name == t.text).ok_or_else(bad)?.1,
+            };
+            words.push(word);
+        }
+    }
+    module.code = translate_from(words)?;
+    module.debug = debug;
+    module.link = link;
+    Ok(module)
+}
+
+// 带引号的是名字，否则是编号或名字
+fn lookup(names: &[String], t: &Token) -> Option<usize> {
+    let position = || names.iter().position(|n| *n == t.text);
+    if t.quoted { position() } else { t.text.parse().ok().or_else(position) }
+}
+
+fn symbol_kind(t: &Token) -> Option<SymbolKind> {
+    match t.text.as_str() {
+        "func" => Some(SymbolKind::Func),
+        "global" => Some(SymbolKind::Global),
+        _ => None,
+    }
+}
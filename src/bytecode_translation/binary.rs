@@ -0,0 +1,419 @@
+/*!
+ * 二进制字节码文件 (.cby)：
+ *
+ *   魔数 "CILLYBC\0" | 版本 u16 | 数据长度 u32 | 数据 | CRC-32 u32
+ *
+ * 整数均为小端序。数据依次为常量池、函数表、全局变量表、代码、可选的调试信息和可选的链接信息，每段为
+ *
+ *   段编号 u8 | 段长度 | 段内容
+ *
+ * 长度、个数和操作数都是 LEB128 编码，有符号的数先做 zigzag 编码，字符串为长度加 UTF-8 字节。
+ *   常量池：常量个数，之后每个常量
+ *   函数表：函数个数，之后每个函数的名字、入口地址、参数个数、栈帧槽位个数、返回类型（一个字节，0 表示没有返回值）
+ *   全局变量表：全局变量个数，之后每个全局变量的名字
+ *   代码：每条指令为一个字节的操作码（编号与文本形式相同）和它的操作数，LoadConst 的操作数是常量池中的编号
+ *   调试信息：源文件表的项数，之后每项为与上一项的 pc 之差和文件名；行号表的项数，之后每项为与上一项的 pc 之差和行号；
+ *             局部变量个数，之后每个变量的槽位、名字和有效的指令范围 [start, end)
+ *   链接信息：全局变量初始化代码的长度；导出符号的个数，之后每个符号的种类（一个字节，0 为函数，1 为全局变量）、名字和编号；
+ *             导入符号的个数，之后每个符号的种类、名字和参数个数
+ * 文本形式由 translate::module_to_text / module_from_text 提供，用于调试。
+ */
+
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::vm::module::{ret_from_tag, ret_tag, DebugInfo, Export, Function, Import, LinkInfo, LocalVar, Module, SymbolKind};
+use crate::vm::OpCode;
+
+use super::translate::{translate_from, translate_to};
+
+pub const MAGIC: &[u8; 8] = b"CILLYBC\0";
+// 格式改变时递增，不兼容的版本拒绝加载
+pub const VERSION: u16 = 3;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+// 段编号，文件中按此顺序出现
+const SECTION_CONSTS: u8 = 1;
+const SECTION_FUNCS: u8 = 2;
+const SECTION_GLOBALS: u8 = 3;
+const SECTION_CODE: u8 = 4;
+const SECTION_DEBUG: u8 = 5;
+const SECTION_LINK: u8 = 6;
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Unsigned,
+    Signed,
+}
+
+// 各操作码的操作数，None 表示未知的操作码
+fn operands(code: u8) -> Option<&'static [Operand]> {
+    use Operand::*;
+    let res: &[Operand] = match code {
+        35 => &[Signed],
+        1 | 5 | 6 | 10 | 11 | 12 | 21 | 22 | 23 | 28 | 32 | 33 | 34 | 38 | 40 => &[Unsigned],
+        26 | 36 | 39 => &[Unsigned, Unsigned],
+        37 => &[Unsigned, Signed],
+        2 | 3 | 4 | 13..=20 | 25 | 27 | 29 | 30 | 31 | 41 | 42 | 100..=112 => &[],
+        _ => return None,
+    };
+    Some(res)
+}
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(module: &Module) -> Vec<u8> {
+    // 代码段中的常量替换为常量池的编号，相同的常量只保存一次
+    let mut consts = Vec::new();
+    let mut const_index = HashMap::new();
+    let mut code = Vec::new();
+    let mut words = translate_to(module.code.clone()).into_iter();
+    while let Some(op) = words.next() {
+        code.push(op as u8);
+        for kind in operands(op as u8).unwrap() {
+            let mut word = words.next().unwrap();
+            if op == 1 {
+                word = *const_index.entry(word as i32).or_insert_with(|| {
+                    consts.push(word as i32);
+                    consts.len() - 1
+                });
+            }
+            match kind {
+                Operand::Unsigned => write_uleb(&mut code, word as u64),
+                Operand::Signed => write_uleb(&mut code, zigzag(word as i64)),
+            }
+        }
+    }
+
+    let mut payload = Vec::new();
+    let mut section = Vec::new();
+    write_uleb(&mut section, consts.len() as u64);
+    for k in &consts {
+        write_uleb(&mut section, zigzag(*k as i64));
+    }
+    write_section(&mut payload, SECTION_CONSTS, &section);
+
+    section.clear();
+    write_uleb(&mut section, module.funcs.len() as u64);
+    for func in &module.funcs {
+        write_str(&mut section, &func.name);
+        write_uleb(&mut section, func.entry as u64);
+        write_uleb(&mut section, func.arity as u64);
+        write_uleb(&mut section, func.nlocals as u64);
+        section.push(ret_tag(func.ret));
+    }
+    write_section(&mut payload, SECTION_FUNCS, &section);
+
+    section.clear();
+    write_uleb(&mut section, module.globals.len() as u64);
+    for name in &module.globals {
+        write_str(&mut section, name);
+    }
+    write_section(&mut payload, SECTION_GLOBALS, &section);
+    write_section(&mut payload, SECTION_CODE, &code);
+
+    if let Some(debug) = &module.debug {
+        section.clear();
+        write_uleb(&mut section, debug.files.len() as u64);
+        let mut last = 0;
+        for (pc, file) in &debug.files {
+            write_uleb(&mut section, (pc - last) as u64);
+            write_str(&mut section, file);
+            last = *pc;
+        }
+        write_uleb(&mut section, debug.lines.len() as u64);
+        let mut last = 0;
+        for (pc, line) in &debug.lines {
+            write_uleb(&mut section, (pc - last) as u64);
+            write_uleb(&mut section, *line as u64);
+            last = *pc;
+        }
+        write_uleb(&mut section, debug.locals.len() as u64);
+        for v in &debug.locals {
+            write_uleb(&mut section, v.slot as u64);
+            write_str(&mut section, &v.name);
+            write_uleb(&mut section, v.start as u64);
+            write_uleb(&mut section, v.end as u64);
+        }
+        write_section(&mut payload, SECTION_DEBUG, &section);
+    }
+
+    if let Some(link) = &module.link {
+        section.clear();
+        write_uleb(&mut section, link.init_len as u64);
+        write_uleb(&mut section, link.exports.len() as u64);
+        for export in &link.exports {
+            section.push(kind_tag(export.kind));
+            write_str(&mut section, &export.name);
+            write_uleb(&mut section, export.index as u64);
+        }
+        write_uleb(&mut section, link.imports.len() as u64);
+        for import in &link.imports {
+            section.push(kind_tag(import.kind));
+            write_str(&mut section, &import.name);
+            write_uleb(&mut section, import.arity as u64);
+        }
+        write_section(&mut payload, SECTION_LINK, &section);
+    }
+
+    let mut res = Vec::with_capacity(HEADER_LEN + payload.len() + 4);
+    res.extend(MAGIC);
+    res.extend(VERSION.to_le_bytes());
+    res.extend((payload.len() as u32).to_le_bytes());
+    res.extend(&payload);
+    res.extend(crc32(&payload).to_le_bytes());
+    res
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Module> {
+    if !is_binary(bytes) {
+        return Err(Error::BytecodeError(String::from("不是字节码文件")));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::BytecodeError(String::from("文件头不完整")));
+    }
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+    if version != VERSION {
+        return Err(Error::BytecodeError(format!("不支持的字节码版本 {}（当前为 {}）", version, VERSION)));
+    }
+    let len = u32::from_le_bytes(bytes[10..14].try_into().unwrap()) as usize;
+    let rest = &bytes[HEADER_LEN..];
+    if rest.len() < len + 4 {
+        return Err(Error::BytecodeError(format!("文件不完整：数据应为 {} 字节，实际为 {} 字节", len, rest.len().saturating_sub(4))));
+    }
+    if rest.len() > len + 4 {
+        return Err(Error::BytecodeError(String::from("文件末尾有多余的数据")));
+    }
+    let payload = &rest[..len];
+    let checksum = u32::from_le_bytes(rest[len..].try_into().unwrap());
+    if crc32(payload) != checksum {
+        return Err(Error::BytecodeError(String::from("校验和不匹配，文件已损坏")));
+    }
+
+    let mut pos = 0;
+    let section = read_section(payload, &mut pos, SECTION_CONSTS)?;
+    let mut p = 0;
+    let mut consts = Vec::new();
+    for _ in 0..read_uleb(section, &mut p)? {
+        let k = unzigzag(read_uleb(section, &mut p)?);
+        consts.push(i32::try_from(k).map_err(|_| Error::BytecodeError(format!("常量超出范围: {}", k)))?);
+    }
+    end_section(section, p, "常量池")?;
+
+    let section = read_section(payload, &mut pos, SECTION_FUNCS)?;
+    let mut p = 0;
+    let mut funcs = Vec::new();
+    for _ in 0..read_uleb(section, &mut p)? {
+        let name = read_str(section, &mut p)?;
+        let entry = read_usize(section, &mut p)?;
+        let arity = read_usize(section, &mut p)?;
+        let nlocals = read_usize(section, &mut p)?;
+        let tag = *section.get(p).ok_or(Error::BytecodeError(String::from("函数表不完整")))?;
+        p += 1;
+        let ret = ret_from_tag(tag).ok_or(Error::BytecodeError(format!("未知的返回类型: {}", tag)))?;
+        funcs.push(Function { name, entry, arity, nlocals, ret });
+    }
+    end_section(section, p, "函数表")?;
+
+    let section = read_section(payload, &mut pos, SECTION_GLOBALS)?;
+    let mut p = 0;
+    let mut globals = Vec::new();
+    for _ in 0..read_uleb(section, &mut p)? {
+        globals.push(read_str(section, &mut p)?);
+    }
+    end_section(section, p, "全局变量表")?;
+
+    let code_section = read_section(payload, &mut pos, SECTION_CODE)?;
+    let debug = if payload.get(pos) == Some(&SECTION_DEBUG) {
+        let section = read_section(payload, &mut pos, SECTION_DEBUG)?;
+        let mut p = 0;
+        let mut debug = DebugInfo::default();
+        let mut pc = 0usize;
+        for _ in 0..read_uleb(section, &mut p)? {
+            pc = pc.checked_add(read_usize(section, &mut p)?).ok_or(Error::BytecodeError(String::from("源文件表的地址超出范围")))?;
+            debug.files.push((pc, read_str(section, &mut p)?));
+        }
+        let mut pc = 0usize;
+        for _ in 0..read_uleb(section, &mut p)? {
+            pc = pc.checked_add(read_usize(section, &mut p)?).ok_or(Error::BytecodeError(String::from("行号表的地址超出范围")))?;
+            debug.lines.push((pc, read_usize(section, &mut p)?));
+        }
+        for _ in 0..read_uleb(section, &mut p)? {
+            debug.locals.push(LocalVar {
+                slot: read_usize(section, &mut p)?,
+                name: read_str(section, &mut p)?,
+                start: read_usize(section, &mut p)?,
+                end: read_usize(section, &mut p)?,
+            });
+        }
+        end_section(section, p, "调试信息")?;
+        Some(debug)
+    } else {
+        None
+    };
+    let link = if pos < payload.len() {
+        let section = read_section(payload, &mut pos, SECTION_LINK)?;
+        let mut p = 0;
+        let mut link = LinkInfo { init_len: read_usize(section, &mut p)?, ..Default::default() };
+        for _ in 0..read_uleb(section, &mut p)? {
+            let kind = read_kind(section, &mut p)?;
+            link.exports.push(Export { kind, name: read_str(section, &mut p)?, index: read_usize(section, &mut p)? });
+        }
+        for _ in 0..read_uleb(section, &mut p)? {
+            let kind = read_kind(section, &mut p)?;
+            link.imports.push(Import { kind, name: read_str(section, &mut p)?, arity: read_usize(section, &mut p)? });
+        }
+        end_section(section, p, "链接信息")?;
+        Some(link)
+    } else {
+        None
+    };
+    if pos != payload.len() {
+        return Err(Error::BytecodeError(String::from("最后一段之后有多余的数据")));
+    }
+    let section = code_section;
+    let mut words = Vec::new();
+    let mut p = 0;
+    while p < section.len() {
+        let op = section[p];
+        p += 1;
+        let kinds = operands(op).ok_or(Error::BytecodeError(format!("未知的操作码: {} (偏移 {})", op, p - 1)))?;
+        words.push(op as usize);
+        for kind in kinds {
+            let v = read_uleb(section, &mut p)?;
+            let word = match kind {
+                Operand::Unsigned => usize::try_from(v).map_err(|_| Error::BytecodeError(format!("操作数超出范围: {}", v)))?,
+                Operand::Signed => unzigzag(v) as usize,
+            };
+            let word = match op {
+                1 => *consts.get(word).ok_or(Error::BytecodeError(format!("常量编号超出范围: {}", word)))? as usize,
+                _ => word,
+            };
+            words.push(word);
+        }
+    }
+    let code = translate_from(words)?;
+    // 目标模块中导入的函数编号接在函数表之后
+    let nfuncs = funcs.len() + link.as_ref().map_or(0, |link| link.imports_of(SymbolKind::Func).count());
+    for op in &code {
+        if let OpCode::Call(func, _) = op {
+            if *func >= nfuncs {
+                return Err(Error::BytecodeError(format!("函数编号超出范围: {}", func)));
+            }
+        }
+    }
+    Ok(Module { funcs, globals, code, debug, link })
+}
+
+fn kind_tag(kind: SymbolKind) -> u8 {
+    match kind {
+        SymbolKind::Func => 0,
+        SymbolKind::Global => 1,
+    }
+}
+
+fn read_kind(bytes: &[u8], pos: &mut usize) -> Result<SymbolKind> {
+    let tag = *bytes.get(*pos).ok_or(Error::BytecodeError(String::from("符号表不完整")))?;
+    *pos += 1;
+    match tag {
+        0 => Ok(SymbolKind::Func),
+        1 => Ok(SymbolKind::Global),
+        _ => Err(Error::BytecodeError(format!("未知的符号种类: {}", tag))),
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+    out.push(id);
+    write_uleb(out, content.len() as u64);
+    out.extend(content);
+}
+
+// 读出编号为 id 的段的内容
+fn read_section<'a>(payload: &'a [u8], pos: &mut usize, id: u8) -> Result<&'a [u8]> {
+    match payload.get(*pos) {
+        Some(v) if *v == id => *pos += 1,
+        Some(v) => return Err(Error::BytecodeError(format!("段编号应为 {}，实际为 {}", id, v))),
+        None => return Err(Error::BytecodeError(format!("缺少编号为 {} 的段", id))),
+    }
+    let len = read_usize(payload, pos)?;
+    let section = payload.get(*pos..pos.saturating_add(len)).ok_or(Error::BytecodeError(format!("编号为 {} 的段不完整", id)))?;
+    *pos += len;
+    Ok(section)
+}
+
+fn end_section(section: &[u8], pos: usize, name: &str) -> Result<()> {
+    if pos != section.len() {
+        return Err(Error::BytecodeError(format!("{}末尾有多余的数据", name)));
+    }
+    Ok(())
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_uleb(out, s.len() as u64);
+    out.extend(s.as_bytes());
+}
+
+fn read_str(bytes: &[u8], pos: &mut usize) -> Result<String> {
+    let len = read_usize(bytes, pos)?;
+    let s = bytes.get(*pos..pos.saturating_add(len)).ok_or(Error::BytecodeError(String::from("字符串不完整")))?;
+    *pos += len;
+    String::from_utf8(s.to_vec()).map_err(|_| Error::BytecodeError(String::from("字符串不是合法的 UTF-8")))
+}
+
+fn read_usize(bytes: &[u8], pos: &mut usize) -> Result<usize> {
+    let v = read_uleb(bytes, pos)?;
+    usize::try_from(v).map_err(|_| Error::BytecodeError(format!("数值超出范围: {}", v)))
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn write_uleb(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_uleb(bytes: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut res = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos).ok_or(Error::BytecodeError(String::from("操作数不完整")))?;
+        *pos += 1;
+        if shift >= 64 || (shift == 63 && byte > 1) {
+            return Err(Error::BytecodeError(String::from("操作数过长")));
+        }
+        res |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(res);
+        }
+        shift += 7;
+    }
+}
+
+// CRC-32 (IEEE 802.3)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
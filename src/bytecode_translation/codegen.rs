@@ -0,0 +1,213 @@
+/*!
+ * 代码生成的上下文：翻译时指令直接写入这里，跳转目标用标号表示，全部生成完之后再回填地址。
+ *
+ * 代码分为两段：全局变量的初始化代码（之后是调用 main 的三条指令）和函数。全局变量的声明和函数在源代码中可以交替出现，
+ * 各自写入所在的段，最后初始化代码在前、函数在后拼接。标号记录所在的段和段内的偏移，拼接之后才得到地址，
+ * 所以程序的大小没有限制，嵌套的语句也不需要重定位。
+ * 调试信息（行号和局部变量的有效范围）同样以标号记录。
+ *
+ * 另外记录正在翻译的循环和 try 块，break 和 continue 跳出 try 块时先弹出其异常处理器。
+ */
+
+use crate::error::{Error, Result};
+use crate::vm::module::LocalVar;
+use crate::vm::OpCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Init,
+    Funcs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+// 局部变量的位置，用于离开作用域时结束之后声明的变量
+#[derive(Debug, Clone, Copy)]
+pub struct LocalsMark(usize);
+
+// 循环的 continue 和 break 目标，以及进入循环时的 try 块层数
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    head: Label,
+    end: Label,
+    handlers: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct CodeGen {
+    init: Vec<OpCode>,
+    funcs: Vec<OpCode>,
+    section: Option<Section>,
+    // 每个标号所在的段和偏移，None 表示尚未放置
+    labels: Vec<Option<(Section, usize)>>,
+    // 需要回填跳转地址的指令
+    patches: Vec<(Section, usize, Label)>,
+    // 函数编号和入口
+    entries: Vec<(usize, Label)>,
+    debug: bool,
+    lines: Vec<(Label, usize)>,
+    // 槽位、变量名、开始，结束为 None 表示作用域尚未结束
+    locals: Vec<(usize, String, Label, Option<Label>)>,
+    loops: Vec<Loop>,
+    // 当前所在的 try 块层数，即运行时已压入的异常处理器个数
+    handlers: usize,
+}
+
+// 生成完的代码，地址都已确定
+#[derive(Debug, Default)]
+pub struct Output {
+    pub code: Vec<OpCode>,
+    pub init_len: usize,        // 初始化代码的长度，函数从这里开始
+    pub entries: Vec<(usize, usize)>,
+    pub lines: Vec<(usize, usize)>,
+    pub locals: Vec<LocalVar>,
+}
+
+impl CodeGen {
+    // debug 为 true 时记录调试信息
+    pub fn new(debug: bool) -> Self {
+        Self { debug, ..Default::default() }
+    }
+    // 之后的指令写入 section
+    pub fn set_section(&mut self, section: Section) {
+        self.section = Some(section);
+    }
+    pub fn in_global(&self) -> bool {
+        self.section == Some(Section::Init)
+    }
+    fn code(&mut self) -> &mut Vec<OpCode> {
+        match self.section.expect("未选择代码段") {
+            Section::Init => &mut self.init,
+            Section::Funcs => &mut self.funcs,
+        }
+    }
+    pub fn emit(&mut self, op: OpCode) {
+        self.code().push(op);
+    }
+    // 当前段中最后一条指令
+    pub fn last(&self) -> Option<&OpCode> {
+        match self.section? {
+            Section::Init => self.init.last(),
+            Section::Funcs => self.funcs.last(),
+        }
+    }
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+    // 把标号放在下一条指令处
+    pub fn place(&mut self, label: Label) {
+        let section = self.section.expect("未选择代码段");
+        let offset = self.code().len();
+        self.labels[label.0] = Some((section, offset));
+    }
+    pub fn here(&mut self) -> Label {
+        let label = self.new_label();
+        self.place(label);
+        label
+    }
+    // 生成跳转到 label 的指令，地址在 finish 时回填
+    pub fn emit_jump(&mut self, op: fn(usize) -> OpCode, label: Label) {
+        let section = self.section.expect("未选择代码段");
+        let offset = self.code().len();
+        self.patches.push((section, offset, label));
+        self.emit(op(usize::MAX));
+    }
+    // 下一条指令在当前段中的偏移
+    pub fn offset(&mut self) -> usize {
+        self.code().len()
+    }
+    // 把前面生成的指令替换为 op，用于回填栈帧大小等生成时还不知道的操作数
+    pub fn replace(&mut self, offset: usize, op: OpCode) {
+        self.code()[offset] = op;
+    }
+    // 进入 try 块，异常时跳到 catch
+    pub fn push_handler(&mut self, catch: Label) {
+        self.emit_jump(OpCode::PushHandler, catch);
+        self.handlers += 1;
+    }
+    pub fn pop_handler(&mut self) {
+        self.emit(OpCode::PopHandler);
+        self.handlers -= 1;
+    }
+    // 进入循环：continue 跳到 head，break 跳到 end
+    pub fn push_loop(&mut self, head: Label, end: Label) {
+        self.loops.push(Loop { head, end, handlers: self.handlers });
+    }
+    pub fn pop_loop(&mut self) {
+        self.loops.pop();
+    }
+    // 跳出最内层的循环（brk 为 true）或跳到下一次迭代，之间的 try 块的异常处理器先弹出
+    pub fn emit_loop_jump(&mut self, brk: bool) -> Result<()> {
+        let name = if brk { "break" } else { "continue" };
+        let lp = *self.loops.last().ok_or(Error::TranslateError(format!("{} 不在循环中", name)))?;
+        for _ in lp.handlers..self.handlers {
+            self.emit(OpCode::PopHandler);
+        }
+        self.emit_jump(OpCode::Jmp, if brk { lp.end } else { lp.head });
+        Ok(())
+    }
+    pub fn func_entry(&mut self, index: usize, label: Label) {
+        self.entries.push((index, label));
+    }
+    // 下一条指令开始属于源代码第 line 行
+    pub fn line(&mut self, line: usize) {
+        if self.debug {
+            let label = self.here();
+            self.lines.push((label, line));
+        }
+    }
+    pub fn locals_mark(&self) -> LocalsMark {
+        LocalsMark(self.locals.len())
+    }
+    // 槽位 slot 从下一条指令开始保存变量 name
+    pub fn local(&mut self, slot: usize, name: &str) {
+        if self.debug {
+            let label = self.here();
+            self.locals.push((slot, name.to_string(), label, None));
+        }
+    }
+    // mark 之后声明、作用域尚未结束的局部变量在下一条指令处结束
+    pub fn close_locals(&mut self, mark: LocalsMark) {
+        if self.debug {
+            let label = self.here();
+            for (_, _, _, end) in &mut self.locals[mark.0..] {
+                end.get_or_insert(label);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Output {
+        let init_len = self.init.len();
+        let addr = |label: Label| match self.labels[label.0].expect("标号没有放置") {
+            (Section::Init, offset) => offset,
+            (Section::Funcs, offset) => init_len + offset,
+        };
+        let mut code = self.init.clone();
+        code.extend(&self.funcs);
+        for (section, offset, label) in &self.patches {
+            let pc = if *section == Section::Init { *offset } else { init_len + offset };
+            let t = addr(*label);
+            code[pc] = match code[pc] {
+                OpCode::Jmp(_) => OpCode::Jmp(t),
+                OpCode::JmpTrue(_) => OpCode::JmpTrue(t),
+                OpCode::JmpFalse(_) => OpCode::JmpFalse(t),
+                OpCode::CmpJmp(cmp, _) => OpCode::CmpJmp(cmp, t),
+                OpCode::PushHandler(_) => OpCode::PushHandler(t),
+                op => unreachable!("{:?} 不是跳转指令", op),
+            };
+        }
+        let mut lines: Vec<(usize, usize)> = self.lines.iter().map(|(label, line)| (addr(*label), *line)).collect();
+        // 同一地址有多项时，后记录的（内层的）在后
+        lines.sort_by_key(|(pc, _)| *pc);
+        let locals = self.locals.iter().map(|(slot, name, start, end)| LocalVar {
+            slot: *slot,
+            name: name.clone(),
+            start: addr(*start),
+            end: end.map_or(code.len(), addr),
+        }).collect();
+        let entries = self.entries.iter().map(|(index, label)| (*index, addr(*label))).collect();
+        Output { code, init_len, entries, lines, locals }
+    }
+}
@@ -1,45 +1,142 @@
 use std::collections::HashMap;
 use crate::error::{Result, Error};
+use crate::native::{NativeType, Registry};
+use crate::ast::Span;
+use crate::vm::module::{Function, Import, SymbolKind};
+
+// 翻译目标模块时，导入的函数和全局变量先使用从这里开始的临时编号，翻译结束后换成接在本模块定义的之后的编号
+pub const IMPORTED: usize = 1 << (usize::BITS - 1);
+
 #[derive(Debug, Clone)]
 pub struct Environment {
+    // values[0] 为全局变量（位置即全局变量编号），其余为函数内的作用域（位置即栈帧中的槽位）
     values: Vec<HashMap<String, usize>>,
+    // 全局变量名，位置即全局变量编号；重复定义的全局变量使用新的编号，之后的代码使用新的定义
+    globals: Vec<String>,
+    // 函数名到 (函数表中的编号, 参数名)
     func_entry_addr: HashMap<String, (usize, Vec<String>)>,
-    pc_stack: Vec<usize>,
+    funcs: Vec<Function>,
+    // 当前函数下一个空闲的槽位，以及每个作用域进入时的值（离开作用域后槽位可以复用）
+    next_slot: usize,
+    slot_marks: Vec<usize>,
+    frame_size: usize,
+    // 原生函数的 (编号, 参数个数)
+    natives: HashMap<String, (usize, usize)>,
+    // 源文件名和每行的起始偏移，没有时不生成调试信息
+    source: Option<(String, Vec<usize>)>,
+    // 是否生成目标模块：找不到的函数和全局变量作为导入的符号，由链接器解析
+    object: bool,
+    imports: Vec<Import>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             values: vec![HashMap::new()],
+            globals: Vec::new(),
             func_entry_addr: HashMap::new(),
-            pc_stack: Vec::new(),
+            funcs: Vec::new(),
+            next_slot: 0,
+            slot_marks: Vec::new(),
+            frame_size: 0,
+            natives: HashMap::new(),
+            source: None,
+            object: false,
+            imports: Vec::new(),
+        }.with_natives(&Registry::with_defaults())
+    }
+    // 使用指定的原生函数注册表翻译
+    pub fn with_natives(mut self, natives: &Registry) -> Self {
+        self.natives = natives.iter().map(|(i, native)| (native.name.clone(), (i, native.arity()))).collect();
+        self
+    }
+    // 记录源代码，翻译时生成调试信息
+    pub fn with_source(mut self, file: &str, text: &str) -> Self {
+        let starts = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        self.source = Some((file.to_string(), starts));
+        self
+    }
+    // 生成可重定位的目标模块
+    pub fn as_object(mut self) -> Self {
+        self.object = true;
+        self
+    }
+    pub fn is_object(&self) -> bool {
+        self.object
+    }
+    pub fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+    // 导入的符号的临时编号：同一种类中的第 k 个为 IMPORTED + k
+    fn import(&mut self, kind: SymbolKind, name: &str, arity: usize) -> Result<usize> {
+        let same: Vec<&Import> = self.imports.iter().filter(|i| i.kind == kind).collect();
+        if let Some(k) = same.iter().position(|i| i.name == name) {
+            if same[k].arity != arity {
+                return Err(Error::CallError(format!("in function: {}", name)));
+            }
+            return Ok(IMPORTED + k);
         }
+        let k = same.len();
+        self.imports.push(Import { kind, name: name.to_string(), arity });
+        Ok(IMPORTED + k)
     }
-    pub fn push_pc(&mut self, pc: usize) {
-        self.pc_stack.push(pc);
+    // 目标模块中调用找不到的函数，返回临时的函数编号
+    pub fn import_func(&mut self, name: &str, arity: usize) -> Result<usize> {
+        self.import(SymbolKind::Func, name, arity)
     }
-    pub fn pc_stack_top(&self) -> usize {
-        self.pc_stack.last().unwrap().clone()
+    // 查找变量；目标模块中找不到的变量作为导入的全局变量
+    pub fn get_val_or_import(&mut self, id: String) -> Result<(usize, usize)> {
+        match self.get_val(id.clone()) {
+            Err(_) if self.object => Ok((self.get_dep(), self.import(SymbolKind::Global, &id, 0)?)),
+            res => res,
+        }
+    }
+    pub fn source_file(&self) -> Option<&str> {
+        self.source.as_ref().map(|(file, _)| file.as_str())
     }
-    pub fn pc_stack_empty(&self) -> bool {
-        self.pc_stack.is_empty()
+    // span 开始处在源代码中的行号
+    pub fn source_line(&self, span: Span) -> Option<usize> {
+        let (_, starts) = self.source.as_ref()?;
+        Some(starts.partition_point(|start| *start <= span.lo))
     }
-    pub fn pop_pc(&mut self) -> usize {
-        self.pc_stack.pop().unwrap()
+    pub fn get_native(&self, ident: &str) -> Option<(usize, usize)> {
+        self.natives.get(ident).copied()
     }
     pub fn new_scope(&mut self) {
         self.values.push(HashMap::new());
+        self.slot_marks.push(self.next_slot);
     }
     pub fn leave_scope(&mut self) {
         self.values.pop();
+        if let Some(mark) = self.slot_marks.pop() {
+            self.next_slot = mark;
+        }
+    }
+    // 开始翻译一个新的函数，重新分配栈帧槽位
+    pub fn new_frame(&mut self) {
+        self.next_slot = 0;
+        self.frame_size = 0;
+    }
+    // 当前函数栈帧需要的槽位个数
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
     }
     pub fn is_bottom(&self, dep: usize) -> bool {
         dep + 1 == self.values.len()
     }
-    pub fn new_val(&mut self, id: String, dep: usize) {
+    // 返回变量的位置：全局变量编号或栈帧中的槽位
+    pub fn new_val(&mut self, id: String, dep: usize) -> usize {
         let dep = self.values.len() - 1 - dep;
-        let x = self.values[dep].len();
+        let x = if dep == 0 {
+            self.globals.push(id.clone());
+            self.globals.len() - 1
+        } else {
+            self.next_slot += 1;
+            self.frame_size = self.frame_size.max(self.next_slot);
+            self.next_slot - 1
+        };
         self.values[dep].insert(id, x);
+        x
     }
     // (dep, pos) 
     pub fn get_val(&self, id: String) -> Result<(usize, usize)> {
@@ -54,12 +151,22 @@ impl Environment {
         }
         Err(Error::TranslateError(format!("val: {} is not existed !", id)))
     }
-    pub fn get_values_count(&self, dep: usize) -> usize {
-        let dep = self.values.len() - 1 - dep;
-        self.values[dep].len()
+    // 登记函数并返回它在函数表中的编号，栈帧大小在翻译完函数体后由 end_func 回填，入口地址在生成完全部代码后确定
+    pub fn new_func(&mut self, id: String, args: Vec<String>, ret: Option<NativeType>) -> usize {
+        let index = self.funcs.len();
+        self.funcs.push(Function { name: id.clone(), entry: 0, arity: args.len(), nlocals: 0, ret });
+        self.func_entry_addr.insert(id, (index, args));
+        index
+    }
+    pub fn end_func(&mut self, index: usize) {
+        self.funcs[index].nlocals = self.frame_size;
+    }
+    pub fn funcs(&self) -> &[Function] {
+        &self.funcs
     }
-    pub fn new_func(&mut self, id: String, addr: usize, args: Vec<String>) {
-        self.func_entry_addr.insert(id, (addr, args));
+    // 全局变量名，位置即全局变量编号
+    pub fn global_names(&self) -> Vec<String> {
+        self.globals.clone()
     }
     pub fn get_func_addr(&self, id: String) -> Result<(usize, Vec<String>)> {
         if let Some(addr_and_args) = self.func_entry_addr.get(&id) {
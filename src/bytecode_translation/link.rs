@@ -0,0 +1,210 @@
+/*!
+ * 链接器：把分别翻译的目标模块合并为一个可执行的模块。
+ *
+ * 合并后的代码依次为各模块的全局变量初始化代码、调用 main 并结束程序的三条指令、各模块的函数。
+ * 各模块的函数表和全局变量表按顺序拼接，导入的符号按名字解析为其他模块导出的符号。
+ * 指令中的跳转地址、函数编号和全局变量编号，以及函数入口和调试信息中的地址都会重定位。
+ * 符号重复定义、找不到、种类或参数个数不一致以及缺少 main 时报告 LinkError。
+ */
+
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::vm::module::{DebugInfo, Function, LinkInfo, LocalVar, Module, SymbolKind};
+use crate::vm::OpCode;
+
+// 一个目标模块在合并后的位置
+struct Layout {
+    init_base: usize,   // 初始化代码的起始地址
+    func_base: usize,   // 函数代码的起始地址
+    funcs: Vec<usize>,  // 模块中的函数编号（含导入的）到合并后的编号
+    globals: Vec<usize>,
+}
+
+impl Layout {
+    // 目标模块中的地址：在初始化代码中的（含其结束地址）和在函数中的分别重定位
+    fn addr(&self, link: &LinkInfo, pc: usize, in_init: bool) -> usize {
+        if in_init {
+            self.init_base + pc
+        } else {
+            self.func_base + pc - link.init_len
+        }
+    }
+}
+
+// objects 为 (文件名, 目标模块)，文件名用于报告错误
+pub fn link(objects: &[(String, Module)]) -> Result<Module> {
+    let mut errors = Vec::new();
+    let mut links = Vec::new();
+    for (file, module) in objects {
+        let Some(link) = &module.link else {
+            return Err(Error::LinkError(vec![format!("{}: 不是目标模块", file)]));
+        };
+        if let Err(e) = check(module, link) {
+            return Err(Error::LinkError(vec![format!("{}: {}", file, e)]));
+        }
+        links.push(link);
+    }
+
+    // 导出的符号到 (模块, 模块中的编号)
+    let mut symbols: HashMap<(SymbolKind, &str), (usize, usize)> = HashMap::new();
+    for (i, ((file, module), link)) in objects.iter().zip(&links).enumerate() {
+        for export in &link.exports {
+            let defined = match export.kind {
+                SymbolKind::Func => module.funcs.len(),
+                SymbolKind::Global => module.globals.len(),
+            };
+            if export.index >= defined {
+                errors.push(format!("{}: 导出的符号 {} 的编号 {} 超出范围", file, export.name, export.index));
+            } else if let Some((j, _)) = symbols.insert((export.kind, &export.name), (i, export.index)) {
+                errors.push(format!("符号 {} 重复定义: {} 和 {}", export.name, objects[j].0, file));
+            }
+        }
+    }
+    let main = symbols.get(&(SymbolKind::Func, "main")).copied();
+    if main.is_none() {
+        errors.push(String::from("缺少 main 函数"));
+    }
+
+    // 只记录了编号在范围内的符号，有上面的错误时仍然解析导入的符号，一起报告
+    // 先确定每个模块定义的符号在合并后的位置，再解析导入的符号
+    let total_init: usize = objects.iter().zip(&links).map(|(_, link)| link.init_len).sum();
+    let (mut init_base, mut func_base, mut nfuncs, mut nglobals) = (0, total_init + 3, 0, 0);
+    let mut layouts = Vec::new();
+    for ((_, module), link) in objects.iter().zip(&links) {
+        layouts.push(Layout {
+            init_base,
+            func_base,
+            funcs: (nfuncs..nfuncs + module.funcs.len()).collect(),
+            globals: (nglobals..nglobals + module.globals.len()).collect(),
+        });
+        init_base += link.init_len;
+        func_base += module.code.len() - link.init_len;
+        nfuncs += module.funcs.len();
+        nglobals += module.globals.len();
+    }
+    for (i, (file, _)) in objects.iter().enumerate() {
+        for import in &links[i].imports {
+            let Some((j, index)) = symbols.get(&(import.kind, import.name.as_str())).copied() else {
+                errors.push(format!("{}: 未定义的符号 {}", file, import.name));
+                continue;
+            };
+            let resolved = match import.kind {
+                SymbolKind::Func => {
+                    let callee = &objects[j].1.funcs[index];
+                    if callee.arity != import.arity {
+                        errors.push(format!("{}: {} 需要 {} 个参数，实际为 {} 个", file, import.name, callee.arity, import.arity));
+                    }
+                    layouts[j].funcs[index]
+                },
+                SymbolKind::Global => layouts[j].globals[index],
+            };
+            match import.kind {
+                SymbolKind::Func => layouts[i].funcs.push(resolved),
+                SymbolKind::Global => layouts[i].globals.push(resolved),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::LinkError(errors));
+    }
+
+    let (main_module, main_index) = main.unwrap();
+    let mut res = Module::default();
+    let mut funcs_code = Vec::new();
+    res.code.reserve(func_base);
+    let with_debug = objects.iter().all(|(_, module)| module.debug.is_some());
+    let mut debug = DebugInfo::default();
+    let mut func_debug = DebugInfo::default();
+    for (((file, module), link), layout) in objects.iter().zip(&links).zip(&layouts) {
+        for (pc, op) in module.code.iter().enumerate() {
+            let in_init = pc < link.init_len;
+            let op = match *op {
+                OpCode::Jmp(t) => OpCode::Jmp(layout.addr(link, t, in_init)),
+                OpCode::JmpTrue(t) => OpCode::JmpTrue(layout.addr(link, t, in_init)),
+                OpCode::JmpFalse(t) => OpCode::JmpFalse(layout.addr(link, t, in_init)),
+                OpCode::CmpJmp(cmp, t) => OpCode::CmpJmp(cmp, layout.addr(link, t, in_init)),
+                OpCode::PushHandler(t) => OpCode::PushHandler(layout.addr(link, t, in_init)),
+                OpCode::Call(func, argc) => OpCode::Call(remap(&layout.funcs, func, file, "函数")?, argc),
+                OpCode::LoadGlobal(pos) => OpCode::LoadGlobal(remap(&layout.globals, pos, file, "全局变量")?),
+                OpCode::StoreGlobal(pos) => OpCode::StoreGlobal(remap(&layout.globals, pos, file, "全局变量")?),
+                op => op,
+            };
+            if in_init {
+                res.code.push(op);
+            } else {
+                funcs_code.push(op);
+            }
+        }
+        for f in &module.funcs {
+            res.funcs.push(Function { entry: layout.addr(link, f.entry, false), ..f.clone() });
+        }
+        res.globals.extend(module.globals.iter().cloned());
+
+        // 初始化代码和函数的调试信息分别重定位，各自从所在的源文件开始
+        let Some(d) = module.debug.as_ref().filter(|_| with_debug) else {
+            continue;
+        };
+        let init_len = link.init_len;
+        for (out, in_init, start, end) in [(&mut debug, true, 0, init_len), (&mut func_debug, false, init_len, module.code.len())] {
+            if start == end {
+                continue;
+            }
+            let inside = |pc: usize| if in_init { pc < init_len } else { pc >= init_len };
+            if let Some(file) = d.file(start) {
+                out.files.push((layout.addr(link, start, in_init), file.to_string()));
+            }
+            out.files.extend(d.files.iter().filter(|(pc, _)| *pc > start && inside(*pc)).map(|(pc, file)| (layout.addr(link, *pc, in_init), file.clone())));
+            out.lines.extend(d.lines.iter().filter(|(pc, _)| inside(*pc)).map(|(pc, line)| (layout.addr(link, *pc, in_init), *line)));
+            out.locals.extend(d.locals.iter().filter(|v| inside(v.start) && v.start <= v.end).map(|v| LocalVar {
+                start: layout.addr(link, v.start, in_init),
+                end: layout.addr(link, v.end, in_init),
+                ..v.clone()
+            }));
+        }
+    }
+
+    // main 返回后丢弃返回值，跳到代码末尾结束程序；这三条指令属于 main 的函数头所在的文件和行
+    let end = func_base;
+    if let Some(d) = objects[main_module].1.debug.as_ref().filter(|_| with_debug) {
+        let entry = objects[main_module].1.funcs[main_index].entry;
+        if let (Some(file), Some(line)) = (d.file(entry), d.line(entry)) {
+            debug.files.push((res.code.len(), file.to_string()));
+            debug.lines.push((res.code.len(), line));
+        }
+    }
+    res.code.push(OpCode::Call(layouts[main_module].funcs[main_index], 0));
+    res.code.push(OpCode::Pop);
+    res.code.push(OpCode::Jmp(end));
+    res.code.extend(funcs_code);
+    if with_debug {
+        debug.files.extend(func_debug.files);
+        debug.lines.extend(func_debug.lines);
+        debug.locals.extend(func_debug.locals);
+        res.debug = Some(debug);
+    }
+    Ok(res)
+}
+
+// 初始化代码和函数之间不能互相跳转，重定位时两部分的地址才不会混淆
+fn check(module: &Module, link: &LinkInfo) -> std::result::Result<(), String> {
+    if link.init_len > module.code.len() {
+        return Err(format!("初始化代码的长度 {} 超出代码长度", link.init_len));
+    }
+    if let Some(f) = module.funcs.iter().find(|f| f.entry < link.init_len || f.entry > module.code.len()) {
+        return Err(format!("函数 {} 的入口地址 {} 不在函数代码中", f.name, f.entry));
+    }
+    for (pc, op) in module.code.iter().enumerate() {
+        if let OpCode::Jmp(t) | OpCode::JmpTrue(t) | OpCode::JmpFalse(t) | OpCode::CmpJmp(_, t) | OpCode::PushHandler(t) = *op {
+            let ok = if pc < link.init_len { t <= link.init_len } else { t >= link.init_len && t <= module.code.len() };
+            if !ok {
+                return Err(format!("pc {} 的跳转目标 {} 超出所在的部分", pc, t));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn remap(map: &[usize], index: usize, file: &str, what: &str) -> Result<usize> {
+    map.get(index).copied().ok_or(Error::LinkError(vec![format!("{}: {}编号 {} 超出范围", file, what, index)]))
+}
@@ -1,11 +1,17 @@
 pub mod translate;
+pub mod codegen;
 pub mod environment;
+pub mod register;
+pub mod peephole;
+pub mod binary;
+pub mod asm;
+pub mod link;
 
+use codegen::CodeGen;
 use environment::Environment;
-use crate::vm::OpCode;
 use crate::error::Result;
 
 pub trait TransByteCode {
-    // extension 扩展 (第16位记录PC位置)
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>>;
+    // 把指令写入 ctx，跳转目标使用标号
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()>;
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+/*!
+ * 字节码的窥孔优化：把翻译器生成的常见指令序列合并为超级指令，并删除多余的跳转。
+ * 指令条数变化后，所有跳转的目标地址、函数表中的入口地址、调试信息中的地址和目标模块初始化代码的长度都会重新映射。
+ */
+
+use crate::vm::module::Module;
+use crate::vm::{Cmp, OpCode};
+
+// 跳转目标重定向时最多跟随的 Jmp 个数，避免死循环
+const MAX_THREAD: usize = 16;
+
+pub fn optimize(mut module: Module) -> Module {
+    let mut entries: Vec<usize> = module.funcs.iter().map(|f| f.entry).collect();
+    // 初始化代码和函数的分界与函数入口相同，不能合并跨过它的指令
+    entries.extend(module.link.iter().map(|link| link.init_len));
+    // 调试信息中的地址只需要重新映射，不阻止指令合并
+    let mut addrs = Vec::new();
+    if let Some(debug) = &module.debug {
+        addrs.extend(debug.files.iter().map(|(pc, _)| *pc));
+        addrs.extend(debug.lines.iter().map(|(pc, _)| *pc));
+        addrs.extend(debug.locals.iter().flat_map(|v| [v.start, v.end]));
+    }
+    loop {
+        let len = module.code.len();
+        thread_jumps(&mut module.code);
+        module.code = fuse(module.code, &mut entries, &mut addrs);
+        if module.code.len() == len {
+            break;
+        }
+    }
+    let mut entries = entries.into_iter();
+    for (func, entry) in module.funcs.iter_mut().zip(entries.by_ref()) {
+        func.entry = entry;
+    }
+    if let Some(link) = &mut module.link {
+        link.init_len = entries.next().unwrap();
+    }
+    if let Some(debug) = &mut module.debug {
+        let mut addrs = addrs.into_iter();
+        for (pc, _) in debug.files.iter_mut() {
+            *pc = addrs.next().unwrap();
+        }
+        for (pc, _) in debug.lines.iter_mut() {
+            *pc = addrs.next().unwrap();
+        }
+        for v in debug.locals.iter_mut() {
+            v.start = addrs.next().unwrap();
+            v.end = addrs.next().unwrap();
+        }
+    }
+    module
+}
+
+fn target(op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::Jmp(t) | OpCode::JmpTrue(t) | OpCode::JmpFalse(t) | OpCode::CmpJmp(_, t) | OpCode::PushHandler(t) => Some(*t),
+        _ => None,
+    }
+}
+
+fn retarget(op: OpCode, t: usize) -> OpCode {
+    match op {
+        OpCode::Jmp(_) => OpCode::Jmp(t),
+        OpCode::JmpTrue(_) => OpCode::JmpTrue(t),
+        OpCode::JmpFalse(_) => OpCode::JmpFalse(t),
+        OpCode::CmpJmp(cmp, _) => OpCode::CmpJmp(cmp, t),
+        OpCode::PushHandler(_) => OpCode::PushHandler(t),
+        op => op,
+    }
+}
+
+// 跳转到无条件跳转的，直接跳到最终目标
+fn thread_jumps(code: &mut [OpCode]) {
+    for pc in 0..code.len() {
+        if let Some(mut t) = target(&code[pc]) {
+            for _ in 0..MAX_THREAD {
+                match code.get(t) {
+                    Some(OpCode::Jmp(next)) if *next != t => t = *next,
+                    _ => break,
+                }
+            }
+            code[pc] = retarget(code[pc], t);
+        }
+    }
+}
+
+// 从 pc 开始匹配一个可以合并的序列，返回合并后的指令和被替换的指令条数
+fn match_at(code: &[OpCode], pc: usize) -> Option<(Vec<OpCode>, usize)> {
+    let window = &code[pc..];
+    match window {
+        // x = x + k
+        [OpCode::LoadVar(a), OpCode::LoadConst(k), OpCode::BinOpAdd, OpCode::StoreVar(b), ..] if a == b => {
+            Some((vec![OpCode::IncVar(*a, *k)], 4))
+        },
+        [OpCode::LoadVar(a), OpCode::LoadConst(k), OpCode::BinOpSub, OpCode::StoreVar(b), ..] if a == b && *k != i32::MIN => {
+            Some((vec![OpCode::IncVar(*a, -k)], 4))
+        },
+        [OpCode::LoadConst(k), OpCode::BinOpAdd, ..] => Some((vec![OpCode::AddConst(*k)], 2)),
+        [OpCode::LoadConst(k), OpCode::BinOpSub, ..] if *k != i32::MIN => Some((vec![OpCode::AddConst(-k)], 2)),
+        [op, OpCode::JmpFalse(t), ..] if Cmp::from_opcode(op).is_some() => {
+            Some((vec![OpCode::CmpJmp(Cmp::from_opcode(op).unwrap(), *t)], 2))
+        },
+        // 存入后立即读出同一个变量
+        [OpCode::StoreVar(a), OpCode::LoadVar(b), ..] if a == b => Some((vec![OpCode::TeeVar(*a)], 2)),
+        // 跳转到下一条指令
+        [OpCode::Jmp(t), ..] if *t == pc + 1 => Some((vec![], 1)),
+        // 压入后立即弹出，例如表达式语句中 print 留下的 null
+        [OpCode::LoadConst(_) | OpCode::LoadTrue | OpCode::LoadFalse | OpCode::LoadNull | OpCode::LoadVar(_) | OpCode::LoadGlobal(_), OpCode::Pop, ..] => {
+            Some((vec![], 2))
+        },
+        _ => None,
+    }
+}
+
+// entries 为函数的入口地址，和跳转目标一起重新映射；addrs 中的地址也重新映射
+fn fuse(code: Vec<OpCode>, entries: &mut [usize], addrs: &mut [usize]) -> Vec<OpCode> {
+    let mut is_target = vec![false; code.len() + 1];
+    for t in code.iter().filter_map(target).chain(entries.iter().copied()) {
+        if t <= code.len() {
+            is_target[t] = true;
+        }
+    }
+
+    let mut res = Vec::with_capacity(code.len());
+    // 旧地址到新地址的映射
+    let mut map = vec![0; code.len() + 1];
+    let mut pc = 0;
+    while pc < code.len() {
+        let fused = match_at(&code, pc).filter(|(_, n)| {
+            // 序列中间的指令不能是跳转目标
+            (pc + 1..pc + n).all(|i| !is_target[i])
+        });
+        match fused {
+            Some((ops, n)) => {
+                map[pc..pc + n].fill(res.len());
+                res.extend(ops);
+                pc += n;
+            },
+            None => {
+                map[pc] = res.len();
+                res.push(code[pc]);
+                pc += 1;
+            },
+        }
+    }
+    map[code.len()] = res.len();
+
+    for op in res.iter_mut() {
+        if let Some(t) = target(op) {
+            if t <= code.len() {
+                *op = retarget(*op, map[t]);
+            }
+        }
+    }
+    for entry in entries.iter_mut().chain(addrs.iter_mut()) {
+        if *entry <= code.len() {
+            *entry = map[*entry];
+        }
+    }
+    res
+}
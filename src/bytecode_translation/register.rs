@@ -0,0 +1,457 @@
+/*!
+ * 将 AST 翻译为寄存器虚拟机的指令。
+ * 参数和局部变量在函数内各自占用固定的寄存器，表达式的中间结果使用临时寄存器，
+ * 每条语句结束后临时寄存器即可复用，函数的寄存器个数取其使用过的最大值。
+ */
+
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::error::{Error, Result};
+use crate::native::Registry;
+use crate::vm::register::{RegFunc, RegOp, RegProgram};
+
+type BinCtor = fn(usize, usize, usize) -> RegOp;
+
+#[derive(Debug, Default)]
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+struct RegGen {
+    code: Vec<RegOp>,
+    funcs: Vec<RegFunc>,
+    func_ids: HashMap<String, usize>,
+    globals: HashMap<String, usize>,
+    scopes: Vec<HashMap<String, usize>>,
+    next: usize,
+    max: usize,
+    loops: Vec<Loop>,
+    // 原生函数的 (编号, 参数个数)
+    natives: HashMap<String, (usize, usize)>,
+}
+
+pub fn translate_register(unit: &CompUnit) -> Result<RegProgram> {
+    let mut gen = RegGen {
+        natives: Registry::with_defaults().iter().map(|(i, native)| (native.name.clone(), (i, native.arity()))).collect(),
+        ..Default::default()
+    };
+    gen.funcs.push(RegFunc { name: String::from("<global>"), entry: 0, arity: 0, nregs: 0 });
+    // 预先登记所有函数，调用时与定义的先后顺序无关
+    for global_def in &unit.globaldefs {
+        if let GlobalDef::FuncDef(funcdef) = global_def {
+            if gen.func_ids.contains_key(&funcdef.ident) {
+                return Err(Error::TranslateError(format!("func {} is defined twice !", funcdef.ident)));
+            }
+            let arity = funcdef.funcfparams.as_ref().map_or(0, |params| params.params.len());
+            gen.func_ids.insert(funcdef.ident.clone(), gen.funcs.len());
+            gen.funcs.push(RegFunc { name: funcdef.ident.clone(), entry: 0, arity, nregs: 0 });
+        }
+    }
+
+    // 全局变量初始化，然后调用 main
+    for global_def in &unit.globaldefs {
+        if let GlobalDef::Decl(decl, _) = global_def {
+            let (ident, initval) = match decl {
+                Decl::VarDecl(decl) => (&decl.ident, &decl.initval),
+                Decl::ValDecl(decl) => (&decl.ident, &decl.initval),
+            };
+            let mark = gen.next;
+            let r = gen.exp(&initval.exp, None)?;
+            // 重复定义的全局变量使用原来的编号
+            let len = gen.globals.len();
+            let g = *gen.globals.entry(ident.clone()).or_insert(len);
+            gen.emit(RegOp::StoreGlobal(g, r));
+            gen.next = mark;
+        }
+    }
+    if let Some(main) = gen.func_ids.get("main").copied() {
+        let dst = gen.alloc();
+        gen.emit(RegOp::Call(main, 0, 0, dst));
+    }
+    gen.emit(RegOp::Halt);
+    gen.funcs[0].nregs = gen.max;
+
+    for global_def in &unit.globaldefs {
+        if let GlobalDef::FuncDef(funcdef) = global_def {
+            gen.func(funcdef)?;
+        }
+    }
+    Ok(RegProgram {
+        code: gen.code,
+        funcs: gen.funcs,
+        nglobals: gen.globals.len(),
+        entry: 0,
+    })
+}
+
+impl RegGen {
+    fn emit(&mut self, op: RegOp) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+    fn alloc(&mut self) -> usize {
+        let r = self.next;
+        self.next += 1;
+        self.max = self.max.max(self.next);
+        r
+    }
+    fn dst(&mut self, dst: Option<usize>) -> usize {
+        match dst {
+            Some(dst) => dst,
+            None => self.alloc(),
+        }
+    }
+    fn patch(&mut self, at: usize, target: usize) {
+        self.code[at] = match self.code[at] {
+            RegOp::Jmp(_) => RegOp::Jmp(target),
+            RegOp::JmpTrue(cond, _) => RegOp::JmpTrue(cond, target),
+            RegOp::JmpFalse(cond, _) => RegOp::JmpFalse(cond, target),
+            op => op,
+        };
+    }
+    fn local(&self, ident: &str) -> Option<usize> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(ident).copied())
+    }
+
+    fn func(&mut self, funcdef: &FuncDef) -> Result<()> {
+        let id = self.func_ids[&funcdef.ident];
+        self.funcs[id].entry = self.code.len();
+        self.scopes = vec![HashMap::new()];
+        self.next = 0;
+        self.max = 0;
+        if let Some(params) = &funcdef.funcfparams {
+            for param in &params.params {
+                let r = self.alloc();
+                self.scopes[0].insert(param.ident.clone(), r);
+            }
+        }
+        self.block(&funcdef.block)?;
+        self.emit(RegOp::RetVoid);
+        self.funcs[id].nregs = self.max;
+        self.scopes.clear();
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block) -> Result<()> {
+        let mark = self.next;
+        self.scopes.push(HashMap::new());
+        for item in &block.items {
+            match item {
+                BlockItem::Decl(decl, _) => self.decl(decl)?,
+                BlockItem::Stmt(stmt, _) => self.stmt(stmt)?,
+            }
+        }
+        self.scopes.pop();
+        self.next = mark;
+        Ok(())
+    }
+
+    fn decl(&mut self, decl: &Decl) -> Result<()> {
+        let (ident, initval) = match decl {
+            Decl::VarDecl(decl) => (&decl.ident, &decl.initval),
+            Decl::ValDecl(decl) => (&decl.ident, &decl.initval),
+        };
+        let r = self.alloc();
+        self.exp(&initval.exp, Some(r))?;
+        self.next = r + 1;
+        self.scopes.last_mut().unwrap().insert(ident.clone(), r);
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        let mark = self.next;
+        match stmt {
+            Stmt::Assign(lval, exp) => {
+                if let Some(r) = self.local(&lval.ident) {
+                    self.exp(exp, Some(r))?;
+                } else if let Some(g) = self.globals.get(&lval.ident).copied() {
+                    let r = self.exp(exp, None)?;
+                    self.emit(RegOp::StoreGlobal(g, r));
+                } else {
+                    return Err(Error::TranslateError(format!("val: {} is not existed !", lval.ident)));
+                }
+            },
+            Stmt::Block(block) => self.block(block)?,
+            Stmt::Exp(exp) => {
+                if let Some(exp) = exp {
+                    self.exp(exp, None)?;
+                }
+            },
+            Stmt::Ret(exp) => {
+                match exp {
+                    Some(exp) => {
+                        let r = self.exp(exp, None)?;
+                        self.emit(RegOp::Ret(r));
+                    },
+                    None => {
+                        self.emit(RegOp::RetVoid);
+                    },
+                }
+            },
+            Stmt::If { condition, then_branch, else_branch } => {
+                let cond = self.exp(condition, None)?;
+                self.next = mark;
+                let jmp_else = self.emit(RegOp::JmpFalse(cond, 0));
+                self.stmt(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => {
+                        let jmp_end = self.emit(RegOp::Jmp(0));
+                        self.patch(jmp_else, self.code.len());
+                        self.stmt(else_branch)?;
+                        self.patch(jmp_end, self.code.len());
+                    },
+                    None => self.patch(jmp_else, self.code.len()),
+                }
+            },
+            Stmt::While { condition, loopbody } => {
+                let start = self.code.len();
+                let cond = self.exp(condition, None)?;
+                self.next = mark;
+                let jmp_end = self.emit(RegOp::JmpFalse(cond, 0));
+                self.loops.push(Loop { start, breaks: vec![jmp_end] });
+                self.stmt(loopbody)?;
+                self.emit(RegOp::Jmp(start));
+                let end = self.code.len();
+                for at in self.loops.pop().unwrap().breaks {
+                    self.patch(at, end);
+                }
+            },
+            Stmt::FuncDef(funcdef) => {
+                return Err(Error::TranslateError(format!("不支持嵌套的函数定义 {}", funcdef.ident)));
+            },
+            Stmt::Throw(_) | Stmt::Try { .. } => {
+                return Err(Error::TranslateError(String::from("寄存器虚拟机不支持异常")));
+            },
+            Stmt::Continue => {
+                let start = self.loops.last().ok_or(Error::TranslateError(String::from("continue 不在循环中")))?.start;
+                self.emit(RegOp::Jmp(start));
+            },
+            Stmt::Break => {
+                let at = self.emit(RegOp::Jmp(0));
+                self.loops.last_mut().ok_or(Error::TranslateError(String::from("break 不在循环中")))?.breaks.push(at);
+            },
+        }
+        self.next = mark;
+        Ok(())
+    }
+
+    // 计算表达式，返回保存结果的寄存器；给出 dst 时结果一定写入 dst
+    fn exp(&mut self, exp: &Exp, dst: Option<usize>) -> Result<usize> {
+        self.lor(&exp.lor_exp, dst)
+    }
+
+    fn binary(&mut self, ctor: BinCtor, a: usize, b: usize, dst: Option<usize>) -> usize {
+        let dst = self.dst(dst);
+        self.emit(ctor(dst, a, b));
+        dst
+    }
+
+    // 短路求值：左操作数 a 已经决定结果时（|| 为真、&& 为假）不再计算右操作数。
+    // 结果先写入临时寄存器，dst 可能是右操作数要读取的变量
+    fn short_circuit(&mut self, ctor: BinCtor, jmp: fn(usize, usize) -> RegOp, a: usize,
+                     rhs: impl FnOnce(&mut Self) -> Result<usize>, dst: Option<usize>) -> Result<usize> {
+        let t = self.alloc();
+        // ctor(t, a, a) 把 a 转换为布尔值
+        self.emit(ctor(t, a, a));
+        let at = self.emit(jmp(t, 0));
+        let b = rhs(self)?;
+        self.emit(ctor(t, b, b));
+        self.patch(at, self.code.len());
+        Ok(match dst {
+            Some(dst) => {
+                self.emit(RegOp::Move(dst, t));
+                dst
+            },
+            None => t,
+        })
+    }
+
+    fn lor(&mut self, exp: &LOrExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            LOrExp::And(and) => self.land(and, dst),
+            LOrExp::Or(lhs, rhs) => {
+                let a = self.lor(lhs, None)?;
+                self.short_circuit(RegOp::Or, RegOp::JmpTrue, a, |gen| gen.land(rhs, None), dst)
+            },
+        }
+    }
+
+    fn land(&mut self, exp: &LAndExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            LAndExp::Eq(eq) => self.eq(eq, dst),
+            LAndExp::And(lhs, rhs) => {
+                let a = self.land(lhs, None)?;
+                self.short_circuit(RegOp::And, RegOp::JmpFalse, a, |gen| gen.eq(rhs, None), dst)
+            },
+        }
+    }
+
+    fn eq(&mut self, exp: &EqExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            EqExp::Rel(rel) => self.rel(rel, dst),
+            EqExp::Eq(lhs, op, rhs) => {
+                let a = self.eq(lhs, None)?;
+                let b = self.rel(rhs, None)?;
+                let ctor: BinCtor = match op {
+                    BinaryOp::Eq => RegOp::Eq,
+                    BinaryOp::Neq => RegOp::Ne,
+                    _ => return Err(Error::TranslateError(String::from("EqExp Error"))),
+                };
+                Ok(self.binary(ctor, a, b, dst))
+            },
+        }
+    }
+
+    fn rel(&mut self, exp: &RelExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            RelExp::Add(add) => self.add(add, dst),
+            RelExp::Rel(lhs, op, rhs) => {
+                let a = self.rel(lhs, None)?;
+                let b = self.add(rhs, None)?;
+                let ctor: BinCtor = match op {
+                    BinaryOp::Lt => RegOp::Lt,
+                    BinaryOp::Leq => RegOp::Le,
+                    BinaryOp::Gt => RegOp::Gt,
+                    BinaryOp::Geq => RegOp::Ge,
+                    _ => return Err(Error::TranslateError(String::from("RelExp Error"))),
+                };
+                Ok(self.binary(ctor, a, b, dst))
+            },
+        }
+    }
+
+    fn add(&mut self, exp: &AddExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            AddExp::Mul(mul) => self.mul(mul, dst),
+            AddExp::Add(lhs, op, rhs) => {
+                let a = self.add(lhs, None)?;
+                // 右操作数是常数时使用立即数指令
+                if let MulExp::Unary(UnaryExp::Pri(PrimaryExp::Number(k))) = rhs {
+                    let dst = self.dst(dst);
+                    match op {
+                        BinaryOp::Add => self.emit(RegOp::AddI(dst, a, *k)),
+                        BinaryOp::Sub => self.emit(RegOp::SubI(dst, a, *k)),
+                        _ => return Err(Error::TranslateError(String::from("AddExp Error"))),
+                    };
+                    return Ok(dst);
+                }
+                let b = self.mul(rhs, None)?;
+                let ctor: BinCtor = match op {
+                    BinaryOp::Add => RegOp::Add,
+                    BinaryOp::Sub => RegOp::Sub,
+                    _ => return Err(Error::TranslateError(String::from("AddExp Error"))),
+                };
+                Ok(self.binary(ctor, a, b, dst))
+            },
+        }
+    }
+
+    fn mul(&mut self, exp: &MulExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            MulExp::Unary(unary) => self.unary(unary, dst),
+            MulExp::Mul(lhs, op, rhs) => {
+                let a = self.mul(lhs, None)?;
+                let b = self.unary(rhs, None)?;
+                let ctor: BinCtor = match op {
+                    BinaryOp::Mul => RegOp::Mul,
+                    BinaryOp::Div => RegOp::Div,
+                    BinaryOp::Mod => RegOp::Mod,
+                    _ => return Err(Error::TranslateError(String::from("MulExp Error"))),
+                };
+                Ok(self.binary(ctor, a, b, dst))
+            },
+        }
+    }
+
+    fn unary(&mut self, exp: &UnaryExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            UnaryExp::Pri(pri) => self.primary(pri, dst),
+            UnaryExp::Unary(op, unary) => {
+                let a = self.unary(unary, None)?;
+                let dst = self.dst(dst);
+                match op {
+                    UnaryOp::Neg => self.emit(RegOp::Neg(dst, a)),
+                    UnaryOp::Not => self.emit(RegOp::Not(dst, a)),
+                };
+                Ok(dst)
+            },
+            UnaryExp::FuncCall { ident, funcrparams } => {
+                let exps: &[Exp] = match funcrparams {
+                    Some(params) => &params.exps,
+                    None => &[],
+                };
+                match ident.as_str() {
+                    "print" => {
+                        for exp in exps {
+                            let r = self.exp(exp, None)?;
+                            self.emit(RegOp::Print(r));
+                        }
+                        Ok(self.dst(dst))
+                    },
+                    "getint" => {
+                        let dst = self.dst(dst);
+                        self.emit(RegOp::GetInt(dst));
+                        Ok(dst)
+                    },
+                    _ => {
+                        let (native, arity) = match self.func_ids.get(ident) {
+                            Some(id) => (None, self.funcs[*id].arity),
+                            None => match self.natives.get(ident) {
+                                Some((index, arity)) => (Some(*index), *arity),
+                                None => return Err(Error::TranslateError(format!("func {} is not existed !", ident))),
+                            },
+                        };
+                        if arity != exps.len() {
+                            return Err(Error::CallError(format!("in function: {}", ident)));
+                        }
+                        // 参数放在连续的寄存器中
+                        let args = self.next;
+                        for _ in exps {
+                            self.alloc();
+                        }
+                        for (i, exp) in exps.iter().enumerate() {
+                            self.exp(exp, Some(args + i))?;
+                        }
+                        let dst = self.dst(dst);
+                        match native {
+                            Some(index) => self.emit(RegOp::CallNative(index, args, exps.len(), dst)),
+                            None => self.emit(RegOp::Call(self.func_ids[ident], args, exps.len(), dst)),
+                        };
+                        Ok(dst)
+                    },
+                }
+            },
+        }
+    }
+
+    fn primary(&mut self, exp: &PrimaryExp, dst: Option<usize>) -> Result<usize> {
+        match exp {
+            PrimaryExp::Exp(exp) => self.exp(exp, dst),
+            PrimaryExp::Number(num) => {
+                let dst = self.dst(dst);
+                self.emit(RegOp::LoadK(dst, *num));
+                Ok(dst)
+            },
+            PrimaryExp::LVal(lval) => {
+                if let Some(r) = self.local(&lval.ident) {
+                    match dst {
+                        Some(dst) if dst != r => {
+                            self.emit(RegOp::Move(dst, r));
+                            Ok(dst)
+                        },
+                        _ => Ok(r),
+                    }
+                } else if let Some(g) = self.globals.get(&lval.ident).copied() {
+                    let dst = self.dst(dst);
+                    self.emit(RegOp::LoadGlobal(dst, g));
+                    Ok(dst)
+                } else {
+                    Err(Error::TranslateError(format!("val: {} is not existed !", lval.ident)))
+                }
+            },
+        }
+    }
+}
@@ -1,297 +1,372 @@
 use crate::ast::*;
 use crate::error::{Result, Error};
-use crate::vm::OpCode;
+use crate::native::NativeType;
+use crate::vm::module::{ret_from_tag, ret_tag, DebugInfo, Export, Function, LinkInfo, Module, SymbolKind};
+use crate::vm::{Cmp, OpCode};
 
-use super::environment::Environment;
+use super::codegen::{CodeGen, Section};
+use super::environment::{Environment, IMPORTED};
 use super::TransByteCode;
 
-const PCMASK: usize = 0x0000FFFF;
-const SHIFT:  usize = 16;
-
-
-pub fn get_ext(dep: usize, in_global: usize, totle_ins: usize) -> usize{
-    (dep << (SHIFT + 1)) | (in_global << SHIFT) | totle_ins
+// 在当前作用域声明局部变量，返回栈帧中的槽位
+fn new_local(env: &mut Environment, ctx: &mut CodeGen, id: &str) -> usize {
+    let pos = env.new_val(id.to_string(), 0);
+    ctx.local(pos, id);
+    pos
 }
 
-pub fn split(extension: usize) -> (usize, usize, usize) {
-    (extension >> (SHIFT + 1), (extension >> SHIFT) & 1, extension & PCMASK)
+// 之后的指令从 span 所在的行开始
+fn line(env: &Environment, ctx: &mut CodeGen, span: Span) {
+    if let Some(line) = env.source_line(span) {
+        ctx.line(line);
+    }
 }
 
 impl TransByteCode for CompUnit {
-    fn translate_byte(
-        &mut self,
-        env: &mut Environment,
-        extension: usize,
-    ) -> Result<Vec<OpCode>> {
-        let mut func_def_code = Vec::new();
-        let mut global_decl_code = Vec::new();
-        let mut result_code = Vec::new();
-        let (mut dep, mut in_global, mut totle_ins) = split(extension);
-
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
+        // 先登记所有函数的签名，调用时与定义的先后顺序无关，函数也可以互相递归
+        let mut main_span = None;
+        for global_def in &self.globaldefs {
+            if let GlobalDef::FuncDef(funcdef) = global_def {
+                if funcdef.ident == "main" {
+                    main_span = Some(funcdef.span);
+                }
+                if env.get_func_addr(funcdef.ident.clone()).is_ok() {
+                    return Err(Error::TranslateError(format!("func {} is defined twice !", funcdef.ident)));
+                }
+                let args = funcdef.funcfparams.as_ref().map_or(Vec::new(), |params| params.params.iter().map(|arg| arg.ident.clone()).collect());
+                let ret = funcdef.btype.as_ref().map(|_| NativeType::Int);
+                env.new_func(funcdef.ident.clone(), args, ret);
+            }
+        }
         for global_def in self.globaldefs.clone() {
             match global_def {
                 GlobalDef::FuncDef(mut funcdef) => {
-                    in_global = 0;
-                    dep = 1;
-                    if func_def_code.is_empty() { // main 入口
-                        func_def_code.push(OpCode::Call(usize::MAX, 0));
-                        totle_ins += 1;
-                    }
-                    if funcdef.ident == "main" {
-                        func_def_code[0] = OpCode::Call(totle_ins, 0);
-                        env.new_scope();
-                    }
-                    func_def_code.extend(funcdef.translate_byte(env, get_ext(dep, in_global, totle_ins))?);
-                    if funcdef.ident == "main"{
-                        env.leave_scope();
-                    }
+                    ctx.set_section(Section::Funcs);
+                    funcdef.translate_byte(env, ctx)?;
                 }
-                GlobalDef::Decl(mut decl) => {
-                    in_global = 1;
-                    dep = 0;
-                    global_decl_code.extend(decl.translate_byte(env, get_ext(dep, in_global, totle_ins))?);
+                GlobalDef::Decl(mut decl, span) => {
+                    ctx.set_section(Section::Init);
+                    line(env, ctx, span);
+                    decl.translate_byte(env, ctx)?;
                 }
             }
-            totle_ins = global_decl_code.len() + func_def_code.len();
         }
-        result_code.extend(global_decl_code);
-        result_code.extend(func_def_code);
-        // println!("{:?}", env);
-        Ok(result_code)
+        // 初始化之后调用 main，丢弃返回值后跳到代码末尾结束程序；目标模块由链接器生成这三条指令
+        if !env.funcs().is_empty() && !env.is_object() {
+            let (main, _) = env.get_func_addr(String::from("main"))
+                .map_err(|_| Error::TranslateError(String::from("缺少 main 函数")))?;
+            ctx.set_section(Section::Init);
+            // 调用 main 的指令属于 main 的函数头所在的行
+            if let Some(span) = main_span {
+                line(env, ctx, span);
+            }
+            ctx.emit(OpCode::Call(main, 0));
+            ctx.emit(OpCode::Pop);
+            let end = ctx.new_label();
+            ctx.emit_jump(OpCode::Jmp, end);
+            ctx.set_section(Section::Funcs);
+            ctx.place(end);
+        }
+        Ok(())
+    }
+}
+
+// 翻译整个编译单元，得到带函数表和全局变量表的模块；目标模块还带有链接信息
+pub fn translate_module(unit: &mut CompUnit, env: &mut Environment) -> Result<Module> {
+    let mut ctx = CodeGen::new(env.source_file().is_some());
+    unit.translate_byte(env, &mut ctx)?;
+    let out = ctx.finish();
+    let mut funcs = env.funcs().to_vec();
+    for (index, entry) in out.entries {
+        funcs[index].entry = entry;
+    }
+    let debug = env.source_file().map(|file| DebugInfo { files: vec![(0, file.to_string())], lines: out.lines, locals: out.locals });
+    let mut module = Module { funcs, globals: env.global_names(), code: out.code, debug, link: None };
+    if env.is_object() {
+        // 导入的符号换成接在本模块定义的之后的编号
+        let (nfuncs, nglobals) = (module.funcs.len(), module.globals.len());
+        for op in module.code.iter_mut() {
+            *op = match *op {
+                OpCode::Call(func, argc) if func >= IMPORTED => OpCode::Call(nfuncs + func - IMPORTED, argc),
+                OpCode::LoadGlobal(pos) if pos >= IMPORTED => OpCode::LoadGlobal(nglobals + pos - IMPORTED),
+                OpCode::StoreGlobal(pos) if pos >= IMPORTED => OpCode::StoreGlobal(nglobals + pos - IMPORTED),
+                op => op,
+            };
+        }
+        let funcs = module.funcs.iter().enumerate().map(|(index, f)| Export { kind: SymbolKind::Func, name: f.name.clone(), index });
+        // 重复定义的全局变量只导出最后一个定义
+        let globals = module.globals.iter().enumerate()
+            .filter(|(index, g)| !module.globals[index + 1..].contains(g))
+            .map(|(index, g)| Export { kind: SymbolKind::Global, name: g.clone(), index });
+        module.link = Some(LinkInfo {
+            init_len: out.init_len,
+            exports: funcs.chain(globals).collect(),
+            imports: env.imports().to_vec(),
+        });
     }
+    Ok(module)
 }
 
 impl TransByteCode for FuncDef {
-    fn translate_byte(&mut self, env: &mut Environment, mut extension: usize) -> Result<Vec<OpCode>> {
-        let (mut dep, mut in_global, mut addr) = split(extension);
-        let mut res = Vec::new();
-        let id = self.ident.clone();
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         env.new_scope(); // args scope
-        dep = 0;
-        let mut args = vec![];
+        env.new_frame();
+        let scope = ctx.locals_mark();
+        let entry = ctx.here();
+        line(env, ctx, self.span);
         if let Some(params) = &self.funcfparams {
-            for arg in params.params.clone() {
-                args.push(arg.ident.clone());
-                env.new_val(arg.ident.clone(), dep)
+            for arg in &params.params {
+                new_local(env, ctx, &arg.ident);
             }
         }
-        dep = 1;
-        env.new_func(id, addr, args);
-        let temp = self.block.translate_byte(env, get_ext(dep, in_global, addr))?;
+        // 签名已由 CompUnit 登记
+        let (index, _) = env.get_func_addr(self.ident.clone())?;
+        ctx.func_entry(index, entry);
+        // 函数入口，栈帧大小在翻译完函数体后回填
+        let frame = ctx.offset();
+        ctx.emit(OpCode::EnterFrame(0));
+        self.block.translate_byte(env, ctx)?;
+        // 函数之后可能是任何其他函数，执行到末尾时返回 null：没有返回值的函数如此，
+        // 有返回值但某些路径没有 return 的函数与解释器、寄存器虚拟机一致
+        ctx.emit(OpCode::LoadNull);
+        ctx.emit(OpCode::Ret);
+        env.end_func(index);
+        ctx.replace(frame, OpCode::EnterFrame(env.frame_size()));
+        ctx.close_locals(scope);
         env.leave_scope();
-        res.extend(temp);
-        Ok(res)
+        Ok(())
     }
 }
 
 impl TransByteCode for Block {
-    fn translate_byte(&mut self, env: &mut Environment, mut extension: usize) -> Result<Vec<OpCode>> {
-        let (mut dep, mut in_global, mut addr) = split(extension);
-        let mut res = Vec::new();
-        if dep == 1 {
-            res.push(OpCode::EnterScope(0));
-            env.new_scope();
-        }
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
+        env.new_scope();
+        let scope = ctx.locals_mark();
         for mut item in self.items.clone() {
-            let temp = item.translate_byte(env, get_ext(0, in_global, addr))?;
-            addr += temp.len();
-            res.extend(temp)
-        }
-        if dep == 1 {
-            res.push(OpCode::LeaveScope);
-            env.leave_scope();
+            item.translate_byte(env, ctx)?;
         }
-        Ok(res)
+        ctx.close_locals(scope);
+        env.leave_scope();
+        Ok(())
     }
 }
 
 impl TransByteCode for BlockItem {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            BlockItem::Decl(decl) => decl.translate_byte(env, extension),
-            BlockItem::Stmt(stmt) => stmt.translate_byte(env, extension),
+            BlockItem::Decl(decl, span) => {
+                line(env, ctx, *span);
+                decl.translate_byte(env, ctx)
+            },
+            BlockItem::Stmt(stmt, span) => {
+                line(env, ctx, *span);
+                stmt.translate_byte(env, ctx)
+            },
         }
     }
 }
 
 impl TransByteCode for Stmt {
-    fn translate_byte(&mut self, env: &mut Environment, mut extension: usize) -> Result<Vec<OpCode>> {
-        
-        let mut res = Vec::new();
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
             Stmt::Assign(lval, exp) => {
-                res.extend(exp.translate_byte(env, extension)?);
-                let (dep, pos) = env.get_val(lval.ident.clone())?;
+                exp.translate_byte(env, ctx)?;
+                let (dep, pos) = env.get_val_or_import(lval.ident.clone())?;
                 if env.is_bottom(dep) {
-                    res.push(OpCode::StoreGlobal(pos));
+                    ctx.emit(OpCode::StoreGlobal(pos));
                 } else {
-                    res.push(OpCode::StoreVar(dep, pos));
+                    ctx.emit(OpCode::StoreVar(pos));
                 }
             },
             Stmt::Block(block) => {
-                res.extend(block.translate_byte(env, extension)?);
+                block.translate_byte(env, ctx)?;
             },
+            // 表达式语句丢弃表达式的值，语句前后栈高度不变
             Stmt::Exp(exp) => {
                 if let Some(exp) = exp {
-                    res.extend(exp.translate_byte(env, extension)?);
+                    exp.translate_byte(env, ctx)?;
+                    ctx.emit(OpCode::Pop);
                 }
             },
+            // 返回时栈中恰好是返回值，没有返回值时为 null
             Stmt::Ret(ret) => {
-                if let Some(exp) = ret {
-                    res.extend(exp.translate_byte(env, extension)?);
+                match ret {
+                    Some(exp) => exp.translate_byte(env, ctx)?,
+                    None => ctx.emit(OpCode::LoadNull),
                 }
-                res.extend(vec![OpCode::Ret]);
+                ctx.emit(OpCode::Ret);
             },
+            // condition; JmpFalse(else); then; Jmp(end); else: else_branch; end:
             Stmt::If { condition, then_branch, else_branch } => {
-                res.extend(condition.translate_byte(env, extension)?);
-                let temp = then_branch.translate_byte(env, extension)?;
-                res.push(OpCode::JmpFalse(0));
-                extension += temp.len() + res.len() + 1;
-                let iter = res.len() - 1;
-                res.extend(temp);
-                let jumpto = extension & PCMASK;
-                res[iter] = OpCode::JmpFalse(jumpto);
+                condition.translate_byte(env, ctx)?;
+                let otherwise = ctx.new_label();
+                ctx.emit_jump(OpCode::JmpFalse, otherwise);
+                then_branch.translate_byte(env, ctx)?;
                 if let Some(else_branch) = else_branch {
-                    res.extend(else_branch.translate_byte(env, extension)?);
+                    let end = ctx.new_label();
+                    ctx.emit_jump(OpCode::Jmp, end);
+                    ctx.place(otherwise);
+                    else_branch.translate_byte(env, ctx)?;
+                    ctx.place(end);
+                } else {
+                    ctx.place(otherwise);
                 }
             },
+            // top: condition; JmpFalse(end); loopbody; Jmp(top); end:
+            // 循环体中 continue 跳到 top，break 跳到 end
             Stmt::While { condition, loopbody } => {
-                env.push_pc(extension & PCMASK);
-                res.extend(condition.translate_byte(env, extension)?);
-                let temp = loopbody.translate_byte(env, extension)?;
-                res.push(OpCode::JmpFalse(0));
-                extension += temp.len() + res.len() + 2;
-                let iter = res.len() - 1;
-                res.extend(temp);
-                let jumpto = extension & PCMASK;
-                res[iter] = OpCode::JmpFalse(jumpto);
-                res.push(OpCode::Jmp(env.pc_stack_top() + 1));
-                env.pop_pc();
+                let top = ctx.here();
+                condition.translate_byte(env, ctx)?;
+                let end = ctx.new_label();
+                ctx.emit_jump(OpCode::JmpFalse, end);
+                ctx.push_loop(top, end);
+                loopbody.translate_byte(env, ctx)?;
+                ctx.pop_loop();
+                ctx.emit_jump(OpCode::Jmp, top);
+                ctx.place(end);
+            },
+            Stmt::FuncDef(funcdef) => {
+                return Err(Error::TranslateError(format!("不支持嵌套的函数定义 {}", funcdef.ident)));
             },
-            Stmt::FuncDef(_) => todo!(),
             Stmt::Continue => {
+                ctx.emit_loop_jump(false)?;
             },
             Stmt::Break => {
+                ctx.emit_loop_jump(true)?;
+            },
+            Stmt::Throw(exp) => {
+                exp.translate_byte(env, ctx)?;
+                ctx.emit(OpCode::Throw);
+            },
+            // PushHandler(catch); body; PopHandler; Jmp(end); catch: StoreVar(e); handler; end:
+            Stmt::Try { body, ident, handler } => {
+                let catch = ctx.new_label();
+                let end = ctx.new_label();
+                ctx.push_handler(catch);
+                body.translate_byte(env, ctx)?;
+                ctx.pop_handler();
+                ctx.emit_jump(OpCode::Jmp, end);
+                ctx.place(catch);
+                // catch 的变量只在 catch 块内可见
+                env.new_scope();
+                let scope = ctx.locals_mark();
+                let pos = new_local(env, ctx, ident);
+                ctx.emit(OpCode::StoreVar(pos));
+                handler.translate_byte(env, ctx)?;
+                ctx.close_locals(scope);
+                env.leave_scope();
+                ctx.place(end);
             },
         };
-        Ok(res)
+        Ok(())
     }
 }
 
 impl TransByteCode for Decl {
-    fn translate_byte(
-        &mut self,
-        env: &mut Environment,
-        extension: usize,
-    ) -> Result<Vec<OpCode>> {
-        
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            Decl::VarDecl(decl) => decl.translate_byte(env, extension),
-            Decl::ValDecl(decl) => decl.translate_byte(env, extension),
+            Decl::VarDecl(decl) => decl.translate_byte(env, ctx),
+            Decl::ValDecl(decl) => decl.translate_byte(env, ctx),
         }
     }
 }
 
 impl TransByteCode for VarDecl {
-    fn translate_byte(
-        &mut self, 
-        env: &mut Environment,
-        extension: usize,
-    ) -> Result<Vec<OpCode>> {
-        let (mut dep, mut in_global, mut addr) = split(extension);
-        dep = 0;
-        let mut res = Vec::new();
-        if in_global == 1 {
-            res.extend(self.initval.translate_byte(env, get_ext(dep, in_global, addr))?);
-            res.push(OpCode::StoreGlobal(env.get_values_count(0)));
-            env.new_val(self.ident.clone(), 0); 
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
+        self.initval.translate_byte(env, ctx)?;
+        if ctx.in_global() {
+            let pos = env.new_val(self.ident.clone(), 0);
+            ctx.emit(OpCode::StoreGlobal(pos));
         } else {
-            res.extend(self.initval.translate_byte(env, get_ext(dep, in_global, addr))?);
-            // println!("!!! {} {}", self.ident, env.get_values_count(dep));
-            res.push(OpCode::StoreVar(dep, env.get_values_count(0)));
-            env.new_val(self.ident.clone(), dep);
+            let pos = new_local(env, ctx, &self.ident);
+            ctx.emit(OpCode::StoreVar(pos));
         }
-        return Ok(res);
+        Ok(())
     }
 }
 
 impl TransByteCode for ValDecl {
-    fn translate_byte(
-        &mut self,
-        env: &mut Environment,
-        extension: usize,
-    ) -> Result<Vec<OpCode>> {
-        let (mut dep, mut in_global, mut addr) = split(extension);
-        let mut res = Vec::new();
-        if in_global == 1 {
-            res.extend(self.initval.translate_byte(env, extension)?);
-            res.push(OpCode::StoreGlobal(env.get_values_count(0)));
-            env.new_val(self.ident.clone(), 0);
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
+        self.initval.translate_byte(env, ctx)?;
+        if ctx.in_global() {
+            let pos = env.new_val(self.ident.clone(), 0);
+            ctx.emit(OpCode::StoreGlobal(pos));
         } else {
-            res.extend(self.initval.translate_byte(env, extension)?);
-            res.push(OpCode::StoreVar(dep, env.get_values_count(0)));
-            env.new_val(self.ident.clone(), dep);
+            let pos = new_local(env, ctx, &self.ident);
+            ctx.emit(OpCode::StoreVar(pos));
         }
-        return Ok(res);
+        Ok(())
     }
 }
 
 impl TransByteCode for Exp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
-        self.lor_exp.translate_byte(env, extension)
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
+        self.lor_exp.translate_byte(env, ctx)
     }
 }
 
 impl TransByteCode for InitVal {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
-        self.exp.translate_byte(env, extension)
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
+        self.exp.translate_byte(env, ctx)
     }
 }
 
+// 短路求值：左操作数已在栈顶，为 jmp 跳转的条件时结果就是 taken，不再计算右操作数；否则结果由右操作数决定
+fn short_circuit(ctx: &mut CodeGen, jmp: fn(usize) -> OpCode, taken: OpCode, otherwise: OpCode,
+                 rhs: impl FnOnce(&mut CodeGen) -> Result<()>) -> Result<()> {
+    let decided = ctx.new_label();
+    let end = ctx.new_label();
+    ctx.emit_jump(jmp, decided);
+    rhs(ctx)?;
+    ctx.emit_jump(jmp, decided);
+    ctx.emit(otherwise);
+    ctx.emit_jump(OpCode::Jmp, end);
+    ctx.place(decided);
+    ctx.emit(taken);
+    ctx.place(end);
+    Ok(())
+}
+
 impl TransByteCode for LOrExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            LOrExp::And(and) => and.translate_byte(env, extension),
+            LOrExp::And(and) => and.translate_byte(env, ctx),
+            // lhs; JmpTrue(t); rhs; JmpTrue(t); LoadFalse; Jmp(end); t: LoadTrue; end:
             LOrExp::Or(lhs , rhs) => {
-                let mut res = lhs.translate_byte(env, extension)?;
-                let rexp = rhs.translate_byte(env, extension)?;
-                res.extend(rexp);
-                res.push(OpCode::BinOpOr);
-                Ok(res)
+                lhs.translate_byte(env, ctx)?;
+                short_circuit(ctx, OpCode::JmpTrue, OpCode::LoadTrue, OpCode::LoadFalse, |ctx| rhs.translate_byte(env, ctx))
             },
         }
     }
 }
 
 impl TransByteCode for LAndExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            LAndExp::Eq(eq) => eq.translate_byte(env, extension),
+            LAndExp::Eq(eq) => eq.translate_byte(env, ctx),
+            // lhs; JmpFalse(f); rhs; JmpFalse(f); LoadTrue; Jmp(end); f: LoadFalse; end:
             LAndExp::And(lhs, rhs) => {
-                let mut res = lhs.translate_byte(env, extension)?;
-                let rexp = rhs.translate_byte(env, extension)?;
-                res.extend(rexp);
-                res.push(OpCode::BinOpAnd);
-                Ok(res)
+                lhs.translate_byte(env, ctx)?;
+                short_circuit(ctx, OpCode::JmpFalse, OpCode::LoadFalse, OpCode::LoadTrue, |ctx| rhs.translate_byte(env, ctx))
             },
         }
     }
 }
 
 impl TransByteCode for EqExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            EqExp::Rel(rel) => rel.translate_byte(env, extension),
+            EqExp::Rel(rel) => rel.translate_byte(env, ctx),
             EqExp::Eq(lhs, op, rhs) => {
-                let mut res = lhs.translate_byte(env, extension)?;
-                let rexp = rhs.translate_byte(env, extension)?;
-                res.extend(rexp);
+                lhs.translate_byte(env, ctx)?;
+                rhs.translate_byte(env, ctx)?;
                 match op {
-                    BinaryOp::Eq  => res.push(OpCode::BinOpEq),
-                    BinaryOp::Neq => res.push(OpCode::BinOpNe),
+                    BinaryOp::Eq  => ctx.emit(OpCode::BinOpEq),
+                    BinaryOp::Neq => ctx.emit(OpCode::BinOpNe),
                     _ => return  Err(Error::TranslateError(format!("EqExp Error")))
                 }
-                Ok(res)
+                Ok(())
             },
         }
     }
@@ -299,218 +374,234 @@ impl TransByteCode for EqExp {
 
 
 impl TransByteCode for RelExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            RelExp::Add(add) => add.translate_byte(env, extension),
+            RelExp::Add(add) => add.translate_byte(env, ctx),
             RelExp::Rel(lhs, op, rhs) => {
-                let mut res = lhs.translate_byte(env, extension)?;
-                let rexp = rhs.translate_byte(env, extension)?;
-                res.extend(rexp);
+                lhs.translate_byte(env, ctx)?;
+                rhs.translate_byte(env, ctx)?;
                 match op {
-                    BinaryOp::Lt  => res.push(OpCode::BinOpLt),
-                    BinaryOp::Leq => res.push(OpCode::BinOpLe),
-                    BinaryOp::Gt  => res.push(OpCode::BinOpGt),
-                    BinaryOp::Geq => res.push(OpCode::BinOpGe),
+                    BinaryOp::Lt  => ctx.emit(OpCode::BinOpLt),
+                    BinaryOp::Leq => ctx.emit(OpCode::BinOpLe),
+                    BinaryOp::Gt  => ctx.emit(OpCode::BinOpGt),
+                    BinaryOp::Geq => ctx.emit(OpCode::BinOpGe),
                     _ => return  Err(Error::TranslateError(format!("RelExp Error")))
                 }
-                Ok(res)
+                Ok(())
             },
         }
     }
 }
 
 impl TransByteCode for AddExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            AddExp::Mul(mul) => mul.translate_byte(env, extension),
+            AddExp::Mul(mul) => mul.translate_byte(env, ctx),
             AddExp::Add(lhs, op, rhs) => {
-                let mut res = lhs.translate_byte(env, extension)?;
-                let rexp = rhs.translate_byte(env, extension)?;
-                res.extend(rexp);
+                lhs.translate_byte(env, ctx)?;
+                rhs.translate_byte(env, ctx)?;
                 match op {
-                    BinaryOp::Add => res.push(OpCode::BinOpAdd),
-                    BinaryOp::Sub => res.push(OpCode::BinOpSub),
+                    BinaryOp::Add => ctx.emit(OpCode::BinOpAdd),
+                    BinaryOp::Sub => ctx.emit(OpCode::BinOpSub),
                     _ => return  Err(Error::TranslateError(format!("AddExp Error")))
                 }
-                Ok(res)
+                Ok(())
             },
         }
     }
 }
 
 impl TransByteCode for MulExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            MulExp::Unary(una) => una.translate_byte(env, extension),
+            MulExp::Unary(una) => una.translate_byte(env, ctx),
             MulExp::Mul(lhs, op, rhs) => {
-                let mut res = lhs.translate_byte(env, extension)?;
-                let rexp = rhs.translate_byte(env, extension)?;
-                res.extend(rexp);
+                lhs.translate_byte(env, ctx)?;
+                rhs.translate_byte(env, ctx)?;
                 match op {
-                    BinaryOp::Mul => res.push(OpCode::BinOpMul),
-                    BinaryOp::Div => res.push(OpCode::BinOpDiv),
+                    BinaryOp::Mul => ctx.emit(OpCode::BinOpMul),
+                    BinaryOp::Div => ctx.emit(OpCode::BinOpDiv),
+                    BinaryOp::Mod => ctx.emit(OpCode::BinOpMod),
                     _ => return  Err(Error::TranslateError(format!("MulExp Error")))
                 }
-                Ok(res)
+                Ok(())
             },
         }
     }
 }
 
 impl TransByteCode for UnaryExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            UnaryExp::Pri(pri) => pri.translate_byte(env, extension),
+            UnaryExp::Pri(pri) => pri.translate_byte(env, ctx),
             UnaryExp::Unary(op, una) => {
-                let mut res = una.translate_byte(env, extension)?;
+                una.translate_byte(env, ctx)?;
                 match op {
-                    UnaryOp::Neg => res.push(OpCode::UniOpNeg),
-                    UnaryOp::Not => res.push(OpCode::UniOpNot),
+                    UnaryOp::Neg => ctx.emit(OpCode::UniOpNeg),
+                    UnaryOp::Not => ctx.emit(OpCode::UniOpNot),
                 }
-                Ok(res)
+                Ok(())
             },
             UnaryExp::FuncCall { ident, funcrparams } => {
-                let mut res = Vec::new();
+                let exps = funcrparams.as_ref().map_or(Vec::new(), |params| params.exps.clone());
                 if ident == "print" {
-                    if let Some(funcrparams) = funcrparams {
-                        for mut exp in funcrparams.exps.clone() {
-                            res.extend(exp.translate_byte(env, extension)?);
-                            res.push(OpCode::PrintItem);
-                            res.push(OpCode::PrintNewline);
-                        }
+                    for mut exp in exps {
+                        exp.translate_byte(env, ctx)?;
+                        ctx.emit(OpCode::PrintItem);
+                        ctx.emit(OpCode::PrintNewline);
                     }
+                    // 与其他调用一样留下一个值
+                    ctx.emit(OpCode::LoadNull);
                 } else if ident == "getint" {
-                    res.push(OpCode::GetInt);
-                } else {
-                    if let Ok((pc, mut args)) = env.get_func_addr(ident.to_string()) {
-                        if let Some(funcrparams) = funcrparams {
-                            funcrparams.exps.reverse();
-                            for mut exp in funcrparams.exps.clone() {
-                                res.extend(exp.translate_byte(env, extension)?);
-                            }
-                        }
-                        res.push(OpCode::Call(pc, args.len()));
+                    ctx.emit(OpCode::GetInt);
+                } else if let Ok((func, args)) = env.get_func_addr(ident.to_string()) {
+                    if exps.len() != args.len() {
+                        return Err(Error::CallError(format!("in function: {}", ident)));
+                    }
+                    // 用户函数的参数按相反的顺序压栈
+                    for mut exp in exps.into_iter().rev() {
+                        exp.translate_byte(env, ctx)?;
+                    }
+                    ctx.emit(OpCode::Call(func, args.len()));
+                } else if let Some((index, arity)) = env.get_native(ident) {
+                    // 原生函数的参数按原顺序压栈
+                    if exps.len() != arity {
+                        return Err(Error::CallError(format!("in function: {}", ident)));
+                    }
+                    for mut exp in exps {
+                        exp.translate_byte(env, ctx)?;
+                    }
+                    ctx.emit(OpCode::CallNative(index, arity));
+                } else if env.is_object() {
+                    // 其他模块中的函数，参数的顺序与本模块的函数相同
+                    let argc = exps.len();
+                    let func = env.import_func(ident, argc)?;
+                    for mut exp in exps.into_iter().rev() {
+                        exp.translate_byte(env, ctx)?;
                     }
+                    ctx.emit(OpCode::Call(func, argc));
+                } else {
+                    return Err(Error::TranslateError(format!("func {} is not existed !", ident)));
                 }
-                Ok(res)
+                Ok(())
             },
         }
     }
 }
 
 impl TransByteCode for PrimaryExp {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
         match self {
-            PrimaryExp::Exp(exp) => exp.translate_byte(env, extension),
+            PrimaryExp::Exp(exp) => exp.translate_byte(env, ctx),
             PrimaryExp::Number(num) => {
-                Ok(vec![OpCode::LoadConst(num.clone())])
+                ctx.emit(OpCode::LoadConst(*num));
+                Ok(())
             },
-            PrimaryExp::LVal(lval) => lval.translate_byte(env, extension),
+            PrimaryExp::LVal(lval) => lval.translate_byte(env, ctx),
         }
     }
 }
 
 
 impl TransByteCode for LVal {
-    fn translate_byte(&mut self, env: &mut Environment, extension: usize) -> Result<Vec<OpCode>> {
-        let (dep, pos) = env.get_val(self.ident.clone())?;
+    fn translate_byte(&mut self, env: &mut Environment, ctx: &mut CodeGen) -> Result<()> {
+        let (dep, pos) = env.get_val_or_import(self.ident.clone())?;
         if env.is_bottom(dep) {
-            Ok(vec![OpCode::LoadGlobal(pos)])
+            ctx.emit(OpCode::LoadGlobal(pos));
         } else {
-            Ok(vec![OpCode::LoadVar(dep, pos)])
+            ctx.emit(OpCode::LoadVar(pos));
         }
+        Ok(())
     }
 }
 
-pub fn translate_from(bytes: Vec<usize>) -> Vec<OpCode> {
+// 从文本形式的字节码（每个数为一个操作码或操作数）还原指令
+pub fn translate_from(bytes: Vec<usize>) -> Result<Vec<OpCode>> {
     let mut res = Vec::new();
-    let mut cur = 0;
-    while cur < bytes.len() {
-        match bytes[cur] {
-            1 => {
-                cur += 1;
-                let val = bytes[cur] as i32;
-                res.push(OpCode::LoadConst(val));
-            }
-            2 => res.push(OpCode::LoadTrue),
-            3 => res.push(OpCode::LoadFalse),
-            4 => res.push(OpCode::LoadNull),
-            5 => {
-                cur += 1;
-                res.push(OpCode::LoadGlobal(bytes[cur]));
-            }
-            6 => {
-                cur += 1;
-                res.push(OpCode::StoreGlobal(bytes[cur]));
-            }
-            10 => {
-                cur += 1;
-                res.push(OpCode::Jmp(bytes[cur]));
-            }
-            11 => {
-                cur += 1;
-                res.push(OpCode::JmpTrue(bytes[cur]));
-            }
-            12 => {
-                cur += 1;
-                res.push(OpCode::JmpFalse(bytes[cur]));
-            }
-            13 => res.push(OpCode::PrintItem),
-            14 => res.push(OpCode::PrintNewline),
-            15 => res.push(OpCode::GetInt),
-            16 => res.push(OpCode::Pop),
-            17 => res.push(OpCode::UniOpNot),
-            18 => res.push(OpCode::UniOpNeg),
-            19 => res.push(OpCode::StorePC),
-            20 => res.push(OpCode::LoadPC),
-            21 => {
-                cur += 1;
-                let dep = bytes[cur];
-                cur += 1;
-                let pos = bytes[cur];
-                res.push(OpCode::StoreVar(dep, pos));
-            }
-            22 => {
-                cur += 1;
-                let dep = bytes[cur];
-                cur += 1;
-                let pos = bytes[cur];
-                res.push(OpCode::LoadVar(dep, pos));
-            }
-            23 => {
-                cur += 1;
-                res.push(OpCode::EnterScope(bytes[cur]));
-            }
-            24 => res.push(OpCode::LeaveScope),
-            25 => res.push(OpCode::MakeClosure),
+    let mut words = bytes.into_iter();
+    let mut next = || words.next().ok_or(Error::BytecodeError(String::from("指令不完整")));
+    // 有符号的立即数按补码保存，必须在 i32 的范围内
+    let imm = |word: usize| i32::try_from(word as i64).map_err(|_| Error::BytecodeError(format!("立即数超出范围: {}", word)));
+    while let Ok(code) = next() {
+        let op = match code {
+            1 => OpCode::LoadConst(imm(next()?)?),
+            2 => OpCode::LoadTrue,
+            3 => OpCode::LoadFalse,
+            4 => OpCode::LoadNull,
+            5 => OpCode::LoadGlobal(next()?),
+            6 => OpCode::StoreGlobal(next()?),
+            10 => OpCode::Jmp(next()?),
+            11 => OpCode::JmpTrue(next()?),
+            12 => OpCode::JmpFalse(next()?),
+            13 => OpCode::PrintItem,
+            14 => OpCode::PrintNewline,
+            15 => OpCode::GetInt,
+            16 => OpCode::Pop,
+            17 => OpCode::UniOpNot,
+            18 => OpCode::UniOpNeg,
+            19 => OpCode::StorePC,
+            20 => OpCode::LoadPC,
+            21 => OpCode::StoreVar(next()?),
+            22 => OpCode::LoadVar(next()?),
+            23 => OpCode::EnterFrame(next()?),
+            25 => OpCode::MakeClosure,
             26 => {
-                cur += 1;
-                let addr = bytes[cur];
-                cur += 1;
-                let args = bytes[cur];
-                res.push(OpCode::Call(addr, args));
-            }
-            27 => res.push(OpCode::Ret),
-            100 => res.push(OpCode::BinOpAdd),
-            101 => res.push(OpCode::BinOpSub),
-            102 => res.push(OpCode::BinOpMul),
-            103 => res.push(OpCode::BinOpDiv),
-            104 => res.push(OpCode::BinOpGt),
-            105 => res.push(OpCode::BinOpGe),
-            106 => res.push(OpCode::BinOpLt),
-            107 => res.push(OpCode::BinOpLe),
-            108 => res.push(OpCode::BinOpEq),
-            109 => res.push(OpCode::BinOpNe),
-            110 => res.push(OpCode::BinOpOr),
-            111 => res.push(OpCode::BinOpAnd),
-            _ => panic!("Unknown OpCode: {}", bytes[cur]),
-        }
-        cur += 1;
+                let func = next()?;
+                OpCode::Call(func, next()?)
+            },
+            27 => OpCode::Ret,
+            28 => OpCode::NewArray(next()?),
+            29 => OpCode::ArrayGet,
+            30 => OpCode::ArraySet,
+            31 => OpCode::ArrayLen,
+            32 => OpCode::NewStruct(next()?),
+            33 => OpCode::GetField(next()?),
+            34 => OpCode::SetField(next()?),
+            35 => OpCode::AddConst(imm(next()?)?),
+            36 => {
+                let cmp = match next()? {
+                    104 => Cmp::Gt,
+                    105 => Cmp::Ge,
+                    106 => Cmp::Lt,
+                    107 => Cmp::Le,
+                    108 => Cmp::Eq,
+                    109 => Cmp::Ne,
+                    cmp => return Err(Error::BytecodeError(format!("未知的比较方式: {}", cmp))),
+                };
+                OpCode::CmpJmp(cmp, next()?)
+            },
+            37 => {
+                let pos = next()?;
+                OpCode::IncVar(pos, imm(next()?)?)
+            },
+            38 => OpCode::TeeVar(next()?),
+            39 => {
+                let index = next()?;
+                OpCode::CallNative(index, next()?)
+            },
+            40 => OpCode::PushHandler(next()?),
+            41 => OpCode::PopHandler,
+            42 => OpCode::Throw,
+            100 => OpCode::BinOpAdd,
+            101 => OpCode::BinOpSub,
+            102 => OpCode::BinOpMul,
+            103 => OpCode::BinOpDiv,
+            104 => OpCode::BinOpGt,
+            105 => OpCode::BinOpGe,
+            106 => OpCode::BinOpLt,
+            107 => OpCode::BinOpLe,
+            108 => OpCode::BinOpEq,
+            109 => OpCode::BinOpNe,
+            110 => OpCode::BinOpOr,
+            111 => OpCode::BinOpAnd,
+            112 => OpCode::BinOpMod,
+            _ => return Err(Error::BytecodeError(format!("未知的操作码: {} (第 {} 条指令)", code, res.len()))),
+        };
+        res.push(op);
     }
-    res
+    Ok(res)
 }
 
-
 pub fn translate_to(opcodes: Vec<OpCode>) -> Vec<usize> {
     let mut res = Vec::new();
     for code in opcodes {
@@ -533,6 +624,7 @@ pub fn translate_to(opcodes: Vec<OpCode>) -> Vec<usize> {
             OpCode::BinOpNe => res.push(109),
             OpCode::BinOpOr => res.push(110),
             OpCode::BinOpAnd => res.push(111),
+            OpCode::BinOpMod => res.push(112),
             OpCode::Jmp(addr) => res.extend(vec![10, addr]),
             OpCode::JmpTrue(addr) => res.extend(vec![11, addr]),
             OpCode::JmpFalse(addr) => res.extend(vec![12, addr]),
@@ -544,14 +636,88 @@ pub fn translate_to(opcodes: Vec<OpCode>) -> Vec<usize> {
             OpCode::UniOpNeg => res.push(18),
             OpCode::StorePC => res.push(19),
             OpCode::LoadPC => res.push(20),
-            OpCode::StoreVar(dep, pos) => res.extend(vec![21, dep, pos]),
-            OpCode::LoadVar(dep, pos) => res.extend(vec![22, dep, pos]),
-            OpCode::EnterScope(args) => res.extend(vec![23, args]),
-            OpCode::LeaveScope => res.push(24),
+            OpCode::StoreVar(pos) => res.extend(vec![21, pos]),
+            OpCode::LoadVar(pos) => res.extend(vec![22, pos]),
+            OpCode::EnterFrame(size) => res.extend(vec![23, size]),
             OpCode::MakeClosure => res.push(25),
-            OpCode::Call(addr, args) => res.extend(vec![26, addr, args]),
+            OpCode::Call(func, args) => res.extend(vec![26, func, args]),
             OpCode::Ret => res.push(27),
+            OpCode::NewArray(len) => res.extend(vec![28, len]),
+            OpCode::ArrayGet => res.push(29),
+            OpCode::ArraySet => res.push(30),
+            OpCode::ArrayLen => res.push(31),
+            OpCode::NewStruct(len) => res.extend(vec![32, len]),
+            OpCode::GetField(pos) => res.extend(vec![33, pos]),
+            OpCode::SetField(pos) => res.extend(vec![34, pos]),
+            OpCode::AddConst(c) => res.extend(vec![35, c as usize]),
+            OpCode::CmpJmp(cmp, addr) => {
+                let cmp = match cmp {
+                    Cmp::Gt => 104,
+                    Cmp::Ge => 105,
+                    Cmp::Lt => 106,
+                    Cmp::Le => 107,
+                    Cmp::Eq => 108,
+                    Cmp::Ne => 109,
+                };
+                res.extend(vec![36, cmp, addr]);
+            },
+            OpCode::IncVar(pos, c) => res.extend(vec![37, pos, c as usize]),
+            OpCode::TeeVar(pos) => res.extend(vec![38, pos]),
+            OpCode::CallNative(index, args) => res.extend(vec![39, index, args]),
+            OpCode::PushHandler(addr) => res.extend(vec![40, addr]),
+            OpCode::PopHandler => res.push(41),
+            OpCode::Throw => res.push(42),
         }
     }
     res
 }
+
+// 模块的文本形式：
+//   funcs 个数 (名字 入口地址 参数个数 槽位个数 返回类型)... globals 个数 名字... code 指令...
+// 各项以空白分隔，返回类型的编号与二进制形式相同
+pub fn module_to_text(module: &Module) -> String {
+    let mut res = format!("funcs {}\n", module.funcs.len());
+    for func in &module.funcs {
+        res.push_str(&format!("{} {} {} {} {}\n", func.name, func.entry, func.arity, func.nlocals, ret_tag(func.ret)));
+    }
+    res.push_str(&format!("globals {}\n", module.globals.len()));
+    for name in &module.globals {
+        res.push_str(&format!("{}\n", name));
+    }
+    res.push_str("code\n");
+    for word in translate_to(module.code.clone()) {
+        res.push_str(&format!("{} ", word));
+    }
+    res
+}
+
+pub fn module_from_text(text: &str) -> Result<Module> {
+    let mut tokens = text.split_whitespace();
+    let mut next = || tokens.next().ok_or(Error::BytecodeError(String::from("模块不完整")));
+    let num = |s: &str| s.parse::<usize>().map_err(|_| Error::BytecodeError(format!("非法的数: {}", s)));
+    let mut module = Module::default();
+    if next()? != "funcs" {
+        return Err(Error::BytecodeError(String::from("缺少函数表")));
+    }
+    for _ in 0..num(next()?)? {
+        let name = next()?.to_string();
+        let entry = num(next()?)?;
+        let arity = num(next()?)?;
+        let nlocals = num(next()?)?;
+        let tag = next()?;
+        let ret = tag.parse().ok().and_then(ret_from_tag).ok_or(Error::BytecodeError(format!("未知的返回类型: {}", tag)))?;
+        module.funcs.push(Function { name, entry, arity, nlocals, ret });
+    }
+    if next()? != "globals" {
+        return Err(Error::BytecodeError(String::from("缺少全局变量表")));
+    }
+    for _ in 0..num(next()?)? {
+        module.globals.push(next()?.to_string());
+    }
+    if next()? != "code" {
+        return Err(Error::BytecodeError(String::from("缺少代码")));
+    }
+    let words = tokens.map(num).collect::<Result<Vec<usize>>>()?;
+    module.code = translate_from(words)?;
+    Ok(module)
+}
@@ -11,7 +11,15 @@ pub enum Error {
     MissingExpression,      // 缺少表达式
     UnExpectArgs,
     VMError(String),        // 虚拟机错误
+    TypeError(String),      // 运行时类型错误
+    OutOfMemory(usize),     // 堆大小超过上限（字节）
     TranslateError(String), // 翻译错误
+    SnapshotError(String),  // 快照文件格式错误
+    BytecodeError(String),  // 字节码文件格式错误
+    Uncaught(String, Vec<String>),  // 未捕获的异常：异常的值和调用栈回溯（最内层在前）
+    RuntimeError(Box<Error>, Vec<String>),  // 虚拟机执行出错：原来的错误和调用栈回溯（最内层在前）
+    VerifyError(Vec<String>),   // 字节码校验失败，每项为一个问题
+    LinkError(Vec<String>),     // 链接失败，每项为一个问题
     
     IoError(io::Error),
 }
@@ -1,18 +1,25 @@
 /**
- * 表示代码运行的环境，stack 维护上下文（函数栈，当前函数的 loop 栈）
+ * 表示代码运行的环境，stack 维护上下文（函数栈），exception 为表达式求值过程中抛出、尚未处理的异常，
+ * error 为表达式求值过程中出现的运行时错误（例如原生函数返回的错误），由所在的语句返回
  */
 
 use std::collections::HashMap;
 
-use crate::{ast::{FuncDef, FuncRParams, Stmt}, error::{Error, Result}};
+use crate::{ast::{FuncDef, FuncRParams}, error::{Error, Result}, native::Registry, profile::Profiler};
 
-use super::{eval::Evaluate, values::Value, Execute};
+use super::values::Value;
 
 #[derive(Debug)]
 pub struct Environment<'ast> {
     funcs: HashMap<&'ast str, &'ast FuncDef>,
     values: Vec<HashMap<&'ast str, Value>>,
-    stack: Vec<(&'ast FuncDef, Vec<&'ast Stmt>)>,
+    stack: Vec<&'ast FuncDef>,
+    exception: Option<i32>,
+    error: Option<Error>,
+    // 最近一次 throw 时的调用栈（最内层在前）
+    backtrace: Vec<String>,
+    profiler: Option<Profiler>,
+    natives: Registry,
 }
 
 
@@ -22,6 +29,33 @@ impl<'ast> Environment<'ast> {
             funcs: HashMap::new(),
             values: vec![HashMap::new()],
             stack: Vec::new(),
+            exception: None,
+            error: None,
+            backtrace: Vec::new(),
+            profiler: None,
+            natives: Registry::with_defaults(),
+        }
+    }
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+    pub fn set_natives(&mut self, natives: Registry) {
+        self.natives = natives;
+    }
+    pub fn natives_mut(&mut self) -> &mut Registry {
+        &mut self.natives
+    }
+    pub fn profile_step(&mut self, kind: &'static str) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.step(kind);
+        }
+    }
+    pub fn profile_finish(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.finish();
         }
     }
     pub fn new_value(&mut self, ident: &'ast str, v: Value) -> Result<()> {
@@ -71,46 +105,55 @@ impl<'ast> Environment<'ast> {
         self.values.pop();
     }
     pub fn push_func(&mut self, ident: &'ast str) -> Result<()> {
-        self.stack.push((self.func(ident)?, vec![]));
+        self.stack.push(self.func(ident)?);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(ident);
+        }
         Ok(())
     }
     pub fn pop_func(&mut self) -> Result<()> {
-        self.stack.pop();
+        if self.stack.pop().is_some() {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.leave();
+            }
+        }
         Ok(())
     }
     pub fn call_func(&mut self, params: &'ast Option<FuncRParams>) -> Result<Option<Value>> {
-        let (curfunc, _) = self.stack.last().unwrap();
+        let curfunc = self.stack.last().unwrap();
         curfunc.call(params, self)
     }
-    pub fn push_loop(&mut self, stmt: &'ast Stmt) -> Result<()> {
-        self.stack.last_mut().unwrap().1.push(stmt);
-        Ok(())
+    // 记录 throw 时的调用栈
+    pub fn record_backtrace(&mut self) {
+        // 内置函数的参数在调用者中求值，不算一层；最外层与虚拟机一致记为 <global>
+        self.backtrace = self.stack.iter().rev()
+            .map(|func| func.ident.as_str())
+            .filter(|ident| !matches!(*ident, "print" | "getint"))
+            .chain(["<global>"])
+            .map(String::from)
+            .collect();
     }
-    pub fn pop_loop(&mut self) -> Result<()> {
-        self.stack.last_mut().unwrap().1.pop();
-        Ok(())
+    pub fn backtrace(&self) -> &[String] {
+        &self.backtrace
     }
-    pub fn loop_is_empty(&self) -> bool {
-        self.stack.last().unwrap().1.is_empty()
-    }
-    pub fn run_loop(&mut self) -> Result<()> {
-        loop {
-            if let Some(Stmt::While { condition, loopbody }) = &self.stack.last().unwrap().1.last() {
-                if let Some(condition) = condition.eval(self) {
-                    if condition == 0 {
-                        self.pop_loop()?;
-                        continue;
-                    } else {
-                        let loopbody: &Stmt = loopbody;
-                        loopbody.run(self)?;
-                    }
-                } else {
-                    return Err(Error::MissingExpression);
-                }
-            } else {
-                break;
-            }
-        }
-        Ok(())
+    // 异常离开函数，交给调用者所在的语句处理
+    pub fn raise(&mut self, v: i32) {
+        self.exception = Some(v);
+    }
+    pub fn has_exception(&self) -> bool {
+        self.exception.is_some()
+    }
+    pub fn take_exception(&mut self) -> Option<i32> {
+        self.exception.take()
+    }
+    // 只保留第一个错误
+    pub fn fail(&mut self, e: Error) {
+        self.error.get_or_insert(e);
+    }
+    pub fn has_error(&self) -> bool {
+        self.error.is_some()
+    }
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
     }
 }
\ No newline at end of file
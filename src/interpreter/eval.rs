@@ -3,6 +3,8 @@
 */
 
 use crate::ast::{AddExp, BinaryOp, EqExp, Exp, InitVal, LAndExp, LOrExp, LVal, MulExp, PrimaryExp, RelExp, UnaryExp, UnaryOp};
+use crate::error::Error;
+use crate::vm::value::Value as VMValue;
 
 use super::{environment::Environment, values::{Type, Value}};
 
@@ -26,13 +28,12 @@ impl<'ast> Evaluate<'ast> for LOrExp {
     fn eval(&'ast self, env: &mut Environment<'ast>) -> Option<i32> {
         match &self {
             LOrExp::And(and) => and.eval(env),
+            // 左操作数为真时不计算右操作数
             LOrExp::Or(lhs, rhs) => {
-                match (lhs.eval(env), rhs.eval(env)) {
-                    (Some(lhs), Some(rhs)) => {
-                        Some((lhs != 0 || rhs != 0) as i32)
-                    }
-                    _ => None
+                if lhs.eval(env)? != 0 {
+                    return Some(1);
                 }
+                Some((rhs.eval(env)? != 0) as i32)
             }
         }
     }
@@ -42,13 +43,12 @@ impl<'ast> Evaluate<'ast> for LAndExp {
     fn eval(&'ast self, env: &mut Environment<'ast>) -> Option<i32> {
         match &self {
             LAndExp::Eq(eq) => eq.eval(env),
+            // 左操作数为假时不计算右操作数
             LAndExp::And(lhs, rhs) => {
-                match (lhs.eval(env), rhs.eval(env)) {
-                    (Some(lhs), Some(rhs)) => {
-                        Some((lhs != 0 && rhs != 0) as i32)
-                    }
-                    _ => None
+                if lhs.eval(env)? == 0 {
+                    return Some(0);
                 }
+                Some((rhs.eval(env)? != 0) as i32)
             }
         }
     }
@@ -126,11 +126,16 @@ impl<'ast> Evaluate<'ast> for MulExp {
             MulExp::Unary(unary) => unary.eval(env),
             MulExp::Mul(lhs, op, rhs) => {
                 match (lhs.eval(env), rhs.eval(env)) {
+                    (Some(_), Some(0)) if matches!(op, BinaryOp::Div | BinaryOp::Mod) => {
+                        env.fail(Error::VMError(String::from("除数为 0")));
+                        None
+                    }
                     (Some(lhs), Some(rhs)) => {
                         match op {
                             BinaryOp::Mul => Some(lhs * rhs),
-                            BinaryOp::Div => Some(lhs / rhs),
-                            BinaryOp::Mod => Some(lhs % rhs),
+                            // 与虚拟机一致，i32::MIN 除以 -1 时回绕
+                            BinaryOp::Div => Some(lhs.wrapping_div(rhs)),
+                            BinaryOp::Mod => Some(lhs.wrapping_rem(rhs)),
                             _ => None,
                         }
                     }
@@ -151,9 +156,33 @@ impl<'ast> Evaluate<'ast> for UnaryExp {
                     UnaryOp::Not => (exp == 0) as i32,
                 }),
             UnaryExp::FuncCall { ident, funcrparams } => {
-                env.push_func(&ident).ok();
+                // 表达式中前面的调用已经抛出异常或者出错，不再调用
+                if env.has_exception() || env.has_error() {
+                    return None;
+                }
+                // 没有同名的用户函数时调用原生函数
+                if env.func(ident).is_err() {
+                    if let Some((index, _)) = env.natives_mut().lookup(ident) {
+                        let mut args = Vec::new();
+                        if let Some(params) = funcrparams {
+                            for exp in &params.exps {
+                                args.push(VMValue::Int(exp.eval(env)?));
+                            }
+                        }
+                        return match env.natives_mut().call(index, &args) {
+                            Ok(Some(VMValue::Int(v))) => Some(v),
+                            Ok(Some(VMValue::Bool(b))) => Some(b as i32),
+                            Ok(_) => None,
+                            Err(e) => {
+                                env.fail(e);
+                                None
+                            },
+                        };
+                    }
+                }
+                env.push_func(ident).ok();
                 // println!("{:?}", funcrparams);
-                let x = env.call_func(funcrparams).ok();
+                let x = env.call_func(funcrparams).map_err(|e| env.fail(e)).ok();
                 env.pop_func().ok();
                 if let Some(Some(Value::Const(Type::I32(x)))) = x {
                     // println!("{}", x);
@@ -46,22 +46,30 @@ impl<'ast> FuncDef {
             }
         }
         // println!("{:#?}", env.values);
+        // 参数求值时抛出了异常或者出错，不再执行函数体
+        if env.has_exception() || env.has_error() {
+            env.exit();
+            return Ok(None);
+        }
         for item in &self.block.items {
-            match item {
-                BlockItem::Decl(decl) => {
-                    decl.run(env)?;
-                }
-                BlockItem::Stmt(stmt) => {
-                    if let Some(Label::Type(ret)) = stmt.run(env)? {
-                        res = match ret {
-                            None => Ok(None),
-                            Some(typ) => Ok(Some(Value::new(true, typ))),
-                        };
-                        break;
-                    }
-                }
+            let label = match item {
+                BlockItem::Decl(decl, _) => decl.run(env)?,
+                BlockItem::Stmt(stmt, _) => stmt.run(env)?,
+            };
+            match label {
+                Some(Label::Type(ret)) => {
+                    res = match ret {
+                        None => Ok(None),
+                        Some(typ) => Ok(Some(Value::new(true, typ))),
+                    };
+                    break;
+                },
+                Some(Label::Throw(v)) => {
+                    env.raise(v);
+                    break;
+                },
+                _ => (),
             }
-            env.run_loop()?;
         }
         env.exit();
         // println!("{:?}", res);
@@ -1,41 +1,63 @@
-use crate::{ast::{BlockItem, CompUnit, Decl, FuncDef, GlobalDef, Stmt, ValDecl, VarDecl}, error::{Error, Result}};
+use crate::{ast::{Block, BlockItem, CompUnit, Decl, FuncDef, GlobalDef, Stmt, ValDecl, VarDecl}, error::{Error, Result}};
 
 use super::{environment::Environment, eval::Evaluate, values::{Type, Value}, Execute};
 
+// 求值表达式；求值过程中（被调用的函数里）抛出的异常转为 Label::Throw 返回，出现的错误直接返回
+macro_rules! eval_or_throw {
+    ($exp: expr, $env: expr) => {{
+        let v = $exp.eval($env);
+        if let Some(e) = $env.take_error() {
+            return Err(e);
+        }
+        if let Some(e) = $env.take_exception() {
+            return Ok(Some(Label::Throw(e)));
+        }
+        v
+    }};
+}
+
 impl<'ast> Execute<'ast> for CompUnit {
     fn run(&'ast self, env: &mut Environment<'ast>) -> Result<Option<Label>> {
         for global_def in &self.globaldefs {
-            match global_def {
+            let label = match global_def {
                 GlobalDef::FuncDef(funcdef) => funcdef.run(env)?,
-                GlobalDef::Decl(decl) => decl.run(env)?,
+                GlobalDef::Decl(decl, _) => decl.run(env)?,
             };
+            if let Some(Label::Throw(e)) = label {
+                env.record_backtrace();
+                env.raise(e);
+                break;
+            }
         }
         // printfunc.run(env)?;
 
-        if let Ok(_) = env.push_func("main") {
+        if !env.has_exception() && env.push_func("main").is_ok() {
             env.call_func(&None)?;
             // println!("{:?}", env.values);
             env.pop_func()?;
         }
+        env.profile_finish();
+        if let Some(e) = env.take_exception() {
+            return Err(Error::Uncaught(e.to_string(), env.backtrace().to_vec()));
+        }
         Ok(None)
     }
 }
 
 impl<'ast> Execute<'ast> for Decl {
     fn run(&'ast self, env: &mut Environment<'ast>) -> Result<Option<Label>> {
+        env.profile_step("Decl");
         match &self {
-            Decl::ValDecl(decl) => decl.run(env)?,
-            Decl::VarDecl(decl) => decl.run(env)?,
-        };
-
+            Decl::ValDecl(decl) => decl.run(env),
+            Decl::VarDecl(decl) => decl.run(env),
+        }
         // println!("{:?}", env.values);
-        Ok(None)
     }
 }
 
 impl<'ast> Execute<'ast> for ValDecl {
     fn run(&'ast self, env: &mut Environment<'ast>) -> Result<Option<Label>> {
-        let val = Type::from(self.initval.eval(env));
+        let val = Type::from(eval_or_throw!(self.initval, env));
         env.new_value(&self.ident, Value::new(true, val))?;
         Ok(None)
     }
@@ -43,7 +65,7 @@ impl<'ast> Execute<'ast> for ValDecl {
 
 impl<'ast> Execute<'ast> for VarDecl {
     fn run(&'ast self, env: &mut Environment<'ast>) -> Result<Option<Label>> {
-        let val = Type::from(self.initval.eval(env));
+        let val = Type::from(eval_or_throw!(self.initval, env));
         env.new_value(&self.ident, Value::new(false, val))?;
         Ok(None)
     }
@@ -58,17 +80,24 @@ impl<'ast> Execute<'ast> for FuncDef {
 
 impl<'ast> Execute<'ast> for BlockItem {
     fn run(&'ast self, env: &mut Environment<'ast>) -> Result<Option<Label>> {
-        let mut res = Ok(None);
         match &self {
-            BlockItem::Decl(decl) => {
-                decl.run(env)?;
-            },
-            BlockItem::Stmt(stmt) => {
-                if let Some(content) = stmt.run(env)? {
-                    res = Ok(Some(content));
-                }
+            BlockItem::Decl(decl, _) => decl.run(env),
+            BlockItem::Stmt(stmt, _) => stmt.run(env),
+        }
+    }
+}
+
+impl<'ast> Execute<'ast> for Block {
+    fn run(&'ast self, env: &mut Environment<'ast>) -> Result<Option<Label>> {
+        env.enter();
+        let mut res = Ok(None);
+        for item in &self.items {
+            if let Some(label) = item.run(env)? {
+                res = Ok(Some(label));
+                break;
             }
-        };
+        }
+        env.exit();
         res
     }
 }
@@ -78,6 +107,7 @@ pub enum Label {
     Type(Option<Type>),
     Continue,
     Break,
+    Throw(i32),     // 抛出的异常，向外传播直到被 try 捕获
 }
 
 impl From<Option<Type>> for Label {
@@ -86,34 +116,45 @@ impl From<Option<Type>> for Label {
     }
 }
 
+impl Stmt {
+    // 语句的种类，用于性能分析
+    fn kind(&self) -> &'static str {
+        match self {
+            Stmt::Assign(..) => "Assign",
+            Stmt::Block(_) => "Block",
+            Stmt::Exp(_) => "Exp",
+            Stmt::Ret(_) => "Ret",
+            Stmt::If { .. } => "If",
+            Stmt::While { .. } => "While",
+            Stmt::FuncDef(_) => "FuncDef",
+            Stmt::Continue => "Continue",
+            Stmt::Break => "Break",
+            Stmt::Throw(_) => "Throw",
+            Stmt::Try { .. } => "Try",
+        }
+    }
+}
+
 impl<'ast> Execute<'ast> for Stmt {
     fn run(&'ast self, env: &mut Environment<'ast>) -> Result<Option<Label>> {
+        env.profile_step(self.kind());
         match &self {
             Stmt::Assign(lval, exp) => {
-                let val = Type::from(exp.eval(env));
+                let val = Type::from(eval_or_throw!(exp, env));
                 env.update_value(&lval.ident, Value::new(false, val))?;
                 // println!("{:?}", env.value(&lval.ident)?)
             }
             Stmt::Block(block) => {
-                env.enter();
-                let mut res = Ok(None);
-                for item in &block.items {
-                    if let Some(label) = item.run(env)? {
-                        res = Ok(Some(label));
-                        break;
-                    }                    
-                }
-                env.exit();
-                return res;
+                return block.run(env);
             }
             Stmt::Exp(exp) => {
                 if let Some(exp) = exp {
-                    exp.eval(env);
+                    eval_or_throw!(exp, env);
                 }
             }
             Stmt::Ret(exp) => {
                 if let Some(exp) = exp {
-                    let x = Label::Type(Some(Type::from(exp.eval(env))));
+                    let x = Label::Type(Some(Type::from(eval_or_throw!(exp, env))));
                     // println!("{:?}", x);
 
                     return Ok(Some(x))
@@ -123,11 +164,11 @@ impl<'ast> Execute<'ast> for Stmt {
             },
             Stmt::If { condition, then_branch, else_branch } => {
                 // println!("{:?}", env.values);
-                if let Some(condition) = condition.eval(env) {
+                if let Some(condition) = eval_or_throw!(condition, env) {
                     if condition != 0 {
                         if let Some(label) = then_branch.run(env)? {
                             return Ok(Some(label));
-                        }                        
+                        }
                     } else {
                         if let Some(else_branch) = else_branch {
                             if let Some(label) = else_branch.run(env)? {
@@ -139,8 +180,19 @@ impl<'ast> Execute<'ast> for Stmt {
                     return Err(Error::MissingExpression);
                 }
             },
-            Stmt::While {..} => {
-                env.push_loop(self)?;
+            Stmt::While { condition, loopbody } => {
+                loop {
+                    match eval_or_throw!(condition, env) {
+                        Some(0) => break,
+                        Some(_) => (),
+                        None => return Err(Error::MissingExpression),
+                    }
+                    match loopbody.run(env)? {
+                        Some(Label::Break) => break,
+                        Some(Label::Continue) | None => (),
+                        Some(label) => return Ok(Some(label)),
+                    }
+                }
             },
             Stmt::FuncDef(_) => {
                 todo!()
@@ -149,10 +201,27 @@ impl<'ast> Execute<'ast> for Stmt {
                 return Ok(Some(Label::Continue));
             },
             Stmt::Break => {
-                env.pop_loop()?;
                 return Ok(Some(Label::Break));
             },
+            Stmt::Throw(exp) => {
+                let v = eval_or_throw!(exp, env).ok_or(Error::MissingExpression)?;
+                env.record_backtrace();
+                return Ok(Some(Label::Throw(v)));
+            },
+            Stmt::Try { body, ident, handler } => {
+                match body.run(env)? {
+                    Some(Label::Throw(e)) => {
+                        // 异常的值绑定到 catch 的变量上，作用域只在 catch 块内
+                        env.enter();
+                        env.new_value(ident, Value::new(false, Type::I32(e)))?;
+                        let res = handler.run(env);
+                        env.exit();
+                        return res;
+                    },
+                    label => return Ok(label),
+                }
+            },
         };
         Ok(None)
     }
-}
\ No newline at end of file
+}
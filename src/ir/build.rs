@@ -0,0 +1,646 @@
+/*!
+ * 从 AST 构造 SSA 形式的 IR。
+ *
+ * 采用 Braun 等人的方法（Simple and Efficient Construction of SSA Form）：翻译时记录每个变量在每个块中的当前值，
+ * 读取变量时沿前驱查找，多个前驱时插入 phi；块的前驱全部确定之后才“封闭”，封闭之前读取的变量先放一个不完整的 phi。
+ * 结构化的控制流在生成完对应的跳转后即可封闭块。翻译完函数后删除不可达的块和多余的 phi，并按块的顺序重新编号。
+ *
+ * 求值顺序与字节码翻译器相同：用户函数的参数从最后一个开始计算，原生函数的参数按原顺序计算。
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::error::{Error, Result};
+use crate::native::Registry;
+
+use super::{BinOp, Block, BlockId, Func, Inst, Program, Term, UnOp, Value};
+
+// 名字对应的变量
+#[derive(Debug, Clone, Copy)]
+enum Var {
+    Ssa(usize),     // 转换为 SSA 值的局部变量
+    Slot(usize),    // 保存在内存槽位中的局部变量
+}
+
+// 整个程序共用的符号
+#[derive(Debug, Default)]
+struct Symbols {
+    globals: HashMap<String, usize>,
+    global_names: Vec<String>,
+    // 函数名到 (编号, 参数个数)
+    funcs: HashMap<String, (usize, usize)>,
+    natives: HashMap<String, (usize, usize)>,
+}
+
+// 循环的 continue 和 break 目标，以及进入循环时的 try 块层数
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    head: BlockId,
+    exit: BlockId,
+    handlers: usize,
+}
+
+#[derive(Debug)]
+struct FuncBuilder {
+    name: String,
+    arity: usize,
+    ret: bool,
+    // 函数头的位置，以及当前语句的位置：之后新建的指令和终结指令属于这条语句
+    header: Span,
+    span: Span,
+    insts: Vec<Inst>,
+    spans: Vec<Span>,
+    blocks: Vec<Vec<Value>>,
+    terms: Vec<Option<(Term, Span)>>,
+    preds: Vec<Vec<BlockId>>,
+    sealed: Vec<bool>,
+    // 每个块中变量的当前值
+    defs: Vec<HashMap<usize, Value>>,
+    // 封闭之前插入的 phi：(变量, phi)
+    incomplete: Vec<Vec<(usize, Value)>>,
+    cur: BlockId,
+    nvars: usize,
+    slots: usize,
+    scopes: Vec<HashMap<String, Var>>,
+    // 在 try 块中被赋值的变量名，这些变量保存在内存槽位中
+    demoted: HashSet<String>,
+    loops: Vec<Loop>,
+    handlers: usize,
+}
+
+pub fn build(unit: &CompUnit) -> Result<Program> {
+    let mut syms = Symbols {
+        natives: Registry::with_defaults().iter().map(|(i, native)| (native.name.clone(), (i, native.arity()))).collect(),
+        ..Default::default()
+    };
+    // 先登记所有函数，调用时与定义的先后顺序无关
+    let mut nfuncs = 0;
+    for global_def in &unit.globaldefs {
+        if let GlobalDef::FuncDef(funcdef) = global_def {
+            if syms.funcs.contains_key(&funcdef.ident) {
+                return Err(Error::TranslateError(format!("func {} is defined twice !", funcdef.ident)));
+            }
+            let arity = funcdef.funcfparams.as_ref().map_or(0, |params| params.params.len());
+            syms.funcs.insert(funcdef.ident.clone(), (nfuncs, arity));
+            nfuncs += 1;
+        }
+    }
+    let main = match syms.funcs.get("main") {
+        Some((main, _)) => Some(*main),
+        None if nfuncs == 0 => None,
+        None => return Err(Error::TranslateError(String::from("缺少 main 函数"))),
+    };
+
+    // 全局变量的初始化代码和函数按源代码的顺序翻译，函数中只能使用在它之前声明的全局变量
+    let mut init = FuncBuilder::new("<init>", Span::default(), 0, false, HashSet::new());
+    let mut funcs = Vec::new();
+    for global_def in &unit.globaldefs {
+        match global_def {
+            GlobalDef::FuncDef(funcdef) => funcs.push(FuncBuilder::func(&syms, funcdef)?),
+            GlobalDef::Decl(decl, span) => {
+                init.span = *span;
+                let (ident, initval) = match decl {
+                    Decl::VarDecl(decl) => (&decl.ident, &decl.initval),
+                    Decl::ValDecl(decl) => (&decl.ident, &decl.initval),
+                };
+                let v = init.exp(&syms, &initval.exp)?;
+                let g = match syms.globals.get(ident) {
+                    Some(g) => *g,
+                    None => {
+                        syms.global_names.push(ident.clone());
+                        syms.globals.insert(ident.clone(), syms.global_names.len() - 1);
+                        syms.global_names.len() - 1
+                    },
+                };
+                init.push(Inst::StoreGlobal(g, v));
+            },
+        }
+    }
+    funcs.push(init.finish());
+    Ok(Program { globals: syms.global_names, init: funcs.len() - 1, funcs, main })
+}
+
+// 块中被赋值的变量名，in_try 表示是否在 try 块中
+fn assigned_in_try(block: &crate::ast::Block, in_try: bool, names: &mut HashSet<String>) {
+    for item in &block.items {
+        if let BlockItem::Stmt(stmt, _) = item {
+            stmt_assigned_in_try(stmt, in_try, names);
+        }
+    }
+}
+
+fn stmt_assigned_in_try(stmt: &Stmt, in_try: bool, names: &mut HashSet<String>) {
+    match stmt {
+        Stmt::Assign(lval, _) if in_try => {
+            names.insert(lval.ident.clone());
+        },
+        Stmt::Block(block) => assigned_in_try(block, in_try, names),
+        Stmt::If { then_branch, else_branch, .. } => {
+            stmt_assigned_in_try(then_branch, in_try, names);
+            if let Some(else_branch) = else_branch {
+                stmt_assigned_in_try(else_branch, in_try, names);
+            }
+        },
+        Stmt::While { loopbody, .. } => stmt_assigned_in_try(loopbody, in_try, names),
+        Stmt::Try { body, handler, .. } => {
+            assigned_in_try(body, true, names);
+            assigned_in_try(handler, in_try, names);
+        },
+        _ => (),
+    }
+}
+
+impl FuncBuilder {
+    fn new(name: &str, header: Span, arity: usize, ret: bool, demoted: HashSet<String>) -> Self {
+        let mut builder = Self {
+            name: name.to_string(),
+            arity,
+            ret,
+            header,
+            span: header,
+            insts: Vec::new(),
+            spans: Vec::new(),
+            blocks: Vec::new(),
+            terms: Vec::new(),
+            preds: Vec::new(),
+            sealed: Vec::new(),
+            defs: Vec::new(),
+            incomplete: Vec::new(),
+            cur: BlockId(0),
+            nvars: 0,
+            slots: 0,
+            scopes: vec![HashMap::new()],
+            demoted,
+            loops: Vec::new(),
+            handlers: 0,
+        };
+        let entry = builder.new_block();
+        builder.seal(entry);
+        builder
+    }
+
+    fn func(syms: &Symbols, funcdef: &FuncDef) -> Result<Func> {
+        let mut demoted = HashSet::new();
+        assigned_in_try(&funcdef.block, false, &mut demoted);
+        let params = funcdef.funcfparams.as_ref().map_or(&[][..], |params| &params.params[..]);
+        let mut builder = FuncBuilder::new(&funcdef.ident, funcdef.span, params.len(), funcdef.btype.is_some(), demoted);
+        for (i, param) in params.iter().enumerate() {
+            let v = builder.push(Inst::Param(i));
+            builder.declare(&param.ident, v);
+        }
+        builder.block(syms, &funcdef.block)?;
+        Ok(builder.finish())
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Vec::new());
+        self.terms.push(None);
+        self.preds.push(Vec::new());
+        self.sealed.push(false);
+        self.defs.push(HashMap::new());
+        self.incomplete.push(Vec::new());
+        BlockId(self.blocks.len() - 1)
+    }
+    fn switch_to(&mut self, block: BlockId) {
+        self.cur = block;
+    }
+    fn push(&mut self, inst: Inst) -> Value {
+        let v = self.new_inst(inst);
+        self.blocks[self.cur.0].push(v);
+        v
+    }
+    fn new_inst(&mut self, inst: Inst) -> Value {
+        self.insts.push(inst);
+        self.spans.push(self.span);
+        Value(self.insts.len() - 1)
+    }
+    // 结束当前块；之后的代码（如 return 之后的语句）不可达，写入一个新的块，最后删除
+    fn terminate(&mut self, term: Term) {
+        for succ in term.succs() {
+            self.preds[succ.0].push(self.cur);
+        }
+        self.terms[self.cur.0] = Some((term, self.span));
+        let next = self.new_block();
+        self.seal(next);
+        self.switch_to(next);
+    }
+    fn jump(&mut self, target: BlockId) {
+        self.terminate(Term::Jmp(target));
+    }
+
+    fn write_var(&mut self, var: usize, block: BlockId, v: Value) {
+        self.defs[block.0].insert(var, v);
+    }
+    fn read_var(&mut self, var: usize, block: BlockId) -> Value {
+        if let Some(v) = self.defs[block.0].get(&var) {
+            return *v;
+        }
+        let v = if !self.sealed[block.0] {
+            let phi = self.new_phi(block);
+            self.incomplete[block.0].push((var, phi));
+            phi
+        } else if self.preds[block.0].len() == 1 {
+            let pred = self.preds[block.0][0];
+            self.read_var(var, pred)
+        } else if self.preds[block.0].is_empty() {
+            // 不可达的块
+            let v = self.new_inst(Inst::Null);
+            self.blocks[block.0].insert(0, v);
+            v
+        } else {
+            // 先记录 phi 再查找前驱，循环时才会终止
+            let phi = self.new_phi(block);
+            self.write_var(var, block, phi);
+            self.add_phi_operands(var, phi, block);
+            phi
+        };
+        self.write_var(var, block, v);
+        v
+    }
+    fn new_phi(&mut self, block: BlockId) -> Value {
+        let phi = self.new_inst(Inst::Phi(Vec::new()));
+        self.blocks[block.0].insert(0, phi);
+        phi
+    }
+    fn add_phi_operands(&mut self, var: usize, phi: Value, block: BlockId) {
+        for pred in self.preds[block.0].clone() {
+            let v = self.read_var(var, pred);
+            if let Inst::Phi(incoming) = &mut self.insts[phi.0] {
+                incoming.push((pred, v));
+            }
+        }
+    }
+    // 块的前驱都已确定
+    fn seal(&mut self, block: BlockId) {
+        for (var, phi) in std::mem::take(&mut self.incomplete[block.0]) {
+            self.add_phi_operands(var, phi, block);
+        }
+        self.sealed[block.0] = true;
+    }
+
+    fn declare(&mut self, ident: &str, v: Value) {
+        let var = if self.demoted.contains(ident) {
+            let slot = self.slots;
+            self.slots += 1;
+            self.push(Inst::Store(slot, v));
+            Var::Slot(slot)
+        } else {
+            let var = self.nvars;
+            self.nvars += 1;
+            self.write_var(var, self.cur, v);
+            Var::Ssa(var)
+        };
+        self.scopes.last_mut().unwrap().insert(ident.to_string(), var);
+    }
+    fn lookup(&self, ident: &str) -> Option<Var> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(ident).copied())
+    }
+
+    fn block(&mut self, syms: &Symbols, block: &crate::ast::Block) -> Result<()> {
+        self.scopes.push(HashMap::new());
+        for item in &block.items {
+            match item {
+                BlockItem::Decl(decl, span) => {
+                    self.span = *span;
+                    let (ident, initval) = match decl {
+                        Decl::VarDecl(decl) => (&decl.ident, &decl.initval),
+                        Decl::ValDecl(decl) => (&decl.ident, &decl.initval),
+                    };
+                    let v = self.exp(syms, &initval.exp)?;
+                    self.declare(ident, v);
+                },
+                BlockItem::Stmt(stmt, span) => {
+                    self.span = *span;
+                    self.stmt(syms, stmt)?;
+                },
+            }
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn stmt(&mut self, syms: &Symbols, stmt: &Stmt) -> Result<()> {
+        match stmt {
+            Stmt::Assign(lval, exp) => {
+                let v = self.exp(syms, exp)?;
+                match self.lookup(&lval.ident) {
+                    Some(Var::Ssa(var)) => self.write_var(var, self.cur, v),
+                    Some(Var::Slot(slot)) => {
+                        self.push(Inst::Store(slot, v));
+                    },
+                    None => match syms.globals.get(&lval.ident) {
+                        Some(g) => {
+                            self.push(Inst::StoreGlobal(*g, v));
+                        },
+                        None => return Err(Error::TranslateError(format!("val: {} is not existed !", lval.ident))),
+                    },
+                }
+            },
+            Stmt::Block(block) => self.block(syms, block)?,
+            Stmt::Exp(exp) => {
+                if let Some(exp) = exp {
+                    self.exp(syms, exp)?;
+                }
+            },
+            Stmt::Ret(exp) => {
+                let v = match exp {
+                    Some(exp) => self.exp(syms, exp)?,
+                    None => self.push(Inst::Null),
+                };
+                self.terminate(Term::Ret(v));
+            },
+            Stmt::If { condition, then_branch, else_branch } => {
+                let cond = self.exp(syms, condition)?;
+                let then_block = self.new_block();
+                let end = self.new_block();
+                let else_block = if else_branch.is_some() { self.new_block() } else { end };
+                self.terminate(Term::Br(cond, then_block, else_block));
+                self.seal(then_block);
+                self.switch_to(then_block);
+                self.stmt(syms, then_branch)?;
+                self.jump(end);
+                if let Some(else_branch) = else_branch {
+                    self.seal(else_block);
+                    self.switch_to(else_block);
+                    self.stmt(syms, else_branch)?;
+                    self.jump(end);
+                }
+                self.seal(end);
+                self.switch_to(end);
+            },
+            Stmt::While { condition, loopbody } => {
+                // 循环头的前驱包括循环体的末尾和 continue，循环结束后才封闭
+                let head = self.new_block();
+                self.jump(head);
+                self.switch_to(head);
+                let cond = self.exp(syms, condition)?;
+                let body = self.new_block();
+                let exit = self.new_block();
+                self.terminate(Term::Br(cond, body, exit));
+                self.seal(body);
+                self.switch_to(body);
+                self.loops.push(Loop { head, exit, handlers: self.handlers });
+                self.stmt(syms, loopbody)?;
+                self.loops.pop();
+                self.jump(head);
+                self.seal(head);
+                self.seal(exit);
+                self.switch_to(exit);
+            },
+            Stmt::FuncDef(funcdef) => {
+                return Err(Error::TranslateError(format!("不支持嵌套的函数定义 {}", funcdef.ident)));
+            },
+            Stmt::Continue | Stmt::Break => {
+                let brk = matches!(stmt, Stmt::Break);
+                let name = if brk { "break" } else { "continue" };
+                let lp = *self.loops.last().ok_or(Error::TranslateError(format!("{} 不在循环中", name)))?;
+                // 跳出的 try 块的异常处理器先弹出
+                for _ in lp.handlers..self.handlers {
+                    self.push(Inst::PopHandler);
+                }
+                self.jump(if brk { lp.exit } else { lp.head });
+            },
+            Stmt::Throw(exp) => {
+                let v = self.exp(syms, exp)?;
+                self.terminate(Term::Throw(v));
+            },
+            Stmt::Try { body, ident, handler } => {
+                let body_block = self.new_block();
+                let catch = self.new_block();
+                let end = self.new_block();
+                self.terminate(Term::Try(body_block, catch));
+                self.seal(body_block);
+                self.seal(catch);
+                self.switch_to(body_block);
+                self.handlers += 1;
+                self.block(syms, body)?;
+                self.handlers -= 1;
+                self.push(Inst::PopHandler);
+                self.jump(end);
+                // catch 的变量只在 catch 块内可见
+                self.switch_to(catch);
+                self.scopes.push(HashMap::new());
+                let e = self.push(Inst::Catch);
+                self.declare(ident, e);
+                self.block(syms, handler)?;
+                self.scopes.pop();
+                self.jump(end);
+                self.seal(end);
+                self.switch_to(end);
+            },
+        }
+        Ok(())
+    }
+
+    fn exp(&mut self, syms: &Symbols, exp: &Exp) -> Result<Value> {
+        self.lor(syms, &exp.lor_exp)
+    }
+
+    // 短路求值：lhs 为 jump_on 时结果就是 jump_on，否则由 rhs 决定，结果为布尔值
+    fn short_circuit(&mut self, lhs: Value, jump_on: bool, rhs: impl FnOnce(&mut Self) -> Result<Value>) -> Result<Value> {
+        let rhs_block = self.new_block();
+        let decided = self.new_block();
+        let otherwise = self.new_block();
+        let end = self.new_block();
+        let br = |cond, decided, rhs| if jump_on { Term::Br(cond, decided, rhs) } else { Term::Br(cond, rhs, decided) };
+        self.terminate(br(lhs, decided, rhs_block));
+        self.seal(rhs_block);
+        self.switch_to(rhs_block);
+        let b = rhs(self)?;
+        self.terminate(br(b, decided, otherwise));
+        self.seal(decided);
+        self.seal(otherwise);
+        self.switch_to(decided);
+        let t = self.push(Inst::Bool(jump_on));
+        self.jump(end);
+        self.switch_to(otherwise);
+        let f = self.push(Inst::Bool(!jump_on));
+        self.jump(end);
+        self.seal(end);
+        self.switch_to(end);
+        let phi = self.new_phi(end);
+        self.insts[phi.0] = Inst::Phi(vec![(decided, t), (otherwise, f)]);
+        Ok(phi)
+    }
+
+    fn lor(&mut self, syms: &Symbols, exp: &LOrExp) -> Result<Value> {
+        match exp {
+            LOrExp::And(and) => self.land(syms, and),
+            LOrExp::Or(lhs, rhs) => {
+                let a = self.lor(syms, lhs)?;
+                self.short_circuit(a, true, |b| b.land(syms, rhs))
+            },
+        }
+    }
+
+    fn land(&mut self, syms: &Symbols, exp: &LAndExp) -> Result<Value> {
+        match exp {
+            LAndExp::Eq(eq) => self.eq(syms, eq),
+            LAndExp::And(lhs, rhs) => {
+                let a = self.land(syms, lhs)?;
+                self.short_circuit(a, false, |b| b.eq(syms, rhs))
+            },
+        }
+    }
+
+    fn binary(&mut self, op: &BinaryOp, a: Value, b: Value) -> Value {
+        let op = match op {
+            BinaryOp::Add => BinOp::Add,
+            BinaryOp::Sub => BinOp::Sub,
+            BinaryOp::Mul => BinOp::Mul,
+            BinaryOp::Div => BinOp::Div,
+            BinaryOp::Mod => BinOp::Mod,
+            BinaryOp::Lt => BinOp::Lt,
+            BinaryOp::Gt => BinOp::Gt,
+            BinaryOp::Leq => BinOp::Le,
+            BinaryOp::Geq => BinOp::Ge,
+            BinaryOp::Eq => BinOp::Eq,
+            BinaryOp::Neq => BinOp::Ne,
+        };
+        self.push(Inst::Binary(op, a, b))
+    }
+
+    fn eq(&mut self, syms: &Symbols, exp: &EqExp) -> Result<Value> {
+        match exp {
+            EqExp::Rel(rel) => self.rel(syms, rel),
+            EqExp::Eq(lhs, op, rhs) => {
+                let a = self.eq(syms, lhs)?;
+                let b = self.rel(syms, rhs)?;
+                Ok(self.binary(op, a, b))
+            },
+        }
+    }
+
+    fn rel(&mut self, syms: &Symbols, exp: &RelExp) -> Result<Value> {
+        match exp {
+            RelExp::Add(add) => self.add(syms, add),
+            RelExp::Rel(lhs, op, rhs) => {
+                let a = self.rel(syms, lhs)?;
+                let b = self.add(syms, rhs)?;
+                Ok(self.binary(op, a, b))
+            },
+        }
+    }
+
+    fn add(&mut self, syms: &Symbols, exp: &AddExp) -> Result<Value> {
+        match exp {
+            AddExp::Mul(mul) => self.mul(syms, mul),
+            AddExp::Add(lhs, op, rhs) => {
+                let a = self.add(syms, lhs)?;
+                let b = self.mul(syms, rhs)?;
+                Ok(self.binary(op, a, b))
+            },
+        }
+    }
+
+    fn mul(&mut self, syms: &Symbols, exp: &MulExp) -> Result<Value> {
+        match exp {
+            MulExp::Unary(unary) => self.unary(syms, unary),
+            MulExp::Mul(lhs, op, rhs) => {
+                let a = self.mul(syms, lhs)?;
+                let b = self.unary(syms, rhs)?;
+                Ok(self.binary(op, a, b))
+            },
+        }
+    }
+
+    fn unary(&mut self, syms: &Symbols, exp: &UnaryExp) -> Result<Value> {
+        match exp {
+            UnaryExp::Pri(pri) => self.primary(syms, pri),
+            UnaryExp::Unary(op, unary) => {
+                let a = self.unary(syms, unary)?;
+                let op = match op {
+                    UnaryOp::Neg => UnOp::Neg,
+                    UnaryOp::Not => UnOp::Not,
+                };
+                Ok(self.push(Inst::Unary(op, a)))
+            },
+            UnaryExp::FuncCall { ident, funcrparams } => {
+                let exps: &[Exp] = match funcrparams {
+                    Some(params) => &params.exps,
+                    None => &[],
+                };
+                if ident == "print" {
+                    for exp in exps {
+                        let v = self.exp(syms, exp)?;
+                        self.push(Inst::Print(v));
+                    }
+                    return Ok(self.push(Inst::Null));
+                }
+                if ident == "getint" {
+                    return Ok(self.push(Inst::GetInt));
+                }
+                if let Some((func, arity)) = syms.funcs.get(ident).copied() {
+                    if exps.len() != arity {
+                        return Err(Error::CallError(format!("in function: {}", ident)));
+                    }
+                    // 与字节码翻译器相同，从最后一个参数开始计算
+                    let mut args = vec![Value(0); arity];
+                    for (i, exp) in exps.iter().enumerate().rev() {
+                        args[i] = self.exp(syms, exp)?;
+                    }
+                    return Ok(self.push(Inst::Call(func, args)));
+                }
+                if let Some((index, arity)) = syms.natives.get(ident).copied() {
+                    if exps.len() != arity {
+                        return Err(Error::CallError(format!("in function: {}", ident)));
+                    }
+                    let mut args = Vec::new();
+                    for exp in exps {
+                        args.push(self.exp(syms, exp)?);
+                    }
+                    return Ok(self.push(Inst::CallNative(index, args)));
+                }
+                Err(Error::TranslateError(format!("func {} is not existed !", ident)))
+            },
+        }
+    }
+
+    fn primary(&mut self, syms: &Symbols, exp: &PrimaryExp) -> Result<Value> {
+        match exp {
+            PrimaryExp::Exp(exp) => self.exp(syms, exp),
+            PrimaryExp::Number(num) => Ok(self.push(Inst::Int(*num))),
+            PrimaryExp::LVal(lval) => match self.lookup(&lval.ident) {
+                Some(Var::Ssa(var)) => Ok(self.read_var(var, self.cur)),
+                Some(Var::Slot(slot)) => Ok(self.push(Inst::Load(slot))),
+                None => match syms.globals.get(&lval.ident) {
+                    Some(g) => Ok(self.push(Inst::LoadGlobal(*g))),
+                    None => Err(Error::TranslateError(format!("val: {} is not existed !", lval.ident))),
+                },
+            },
+        }
+    }
+
+    // 没有结束的块返回 null；然后删除不可达的块和多余的 phi，按块的顺序重新编号
+    fn finish(mut self) -> Func {
+        for b in 0..self.blocks.len() {
+            if self.terms[b].is_none() {
+                self.switch_to(BlockId(b));
+                let v = self.push(Inst::Null);
+                self.terms[b] = Some((Term::Ret(v), self.span));
+            }
+            if !self.sealed[b] {
+                self.seal(BlockId(b));
+            }
+        }
+        let blocks = self.blocks.into_iter().zip(self.terms).map(|(insts, term)| {
+            let (term, span) = term.unwrap();
+            Block { insts, term, span }
+        }).collect();
+        let mut func = Func {
+            name: self.name,
+            arity: self.arity,
+            ret: self.ret,
+            slots: self.slots,
+            span: self.header,
+            insts: self.insts,
+            spans: self.spans,
+            blocks,
+        };
+        func.remove_unreachable();
+        func.remove_trivial_phis();
+        func.compact();
+        func
+    }
+}
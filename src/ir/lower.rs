@@ -0,0 +1,312 @@
+/*!
+ * 把 IR 翻译为栈式虚拟机的模块。
+ *
+ * 程序的开头依次调用 <init> 和 main，与字节码翻译器生成的代码一样，之后是各个函数。
+ * 栈帧的槽位：参数在前（param 直接使用参数的槽位），然后是内存槽位，然后 phi 和其他需要保存的值各占一个槽位。
+ * 常数不占槽位，在每个使用处重新加载。只使用一次、并且正好是下一条指令最先压栈的操作数的值留在栈上，不经过槽位。
+ * 块按逆后序排列，跳转到紧接着的块时省略跳转指令。phi 在前驱块跳转之前赋值：先加载所有来源的值，再倒序存入各个 phi 的槽位，
+ * 所以 phi 之间互相引用也没有问题；条件跳转的两个目标都有 phi 时，为假的一边先跳到一段单独的赋值代码。
+ * 环境记录了源代码时，按指令的位置生成行号表，与字节码翻译器一样调用 main 的指令属于 main 的函数头所在的行。
+ */
+
+use crate::ast::Span;
+use crate::bytecode_translation::codegen::{CodeGen, Label, Section};
+use crate::bytecode_translation::environment::Environment;
+use crate::native::NativeType;
+use crate::vm::module::{DebugInfo, Function, Module};
+use crate::vm::OpCode;
+
+use super::{BlockId, Func, Inst, Program, Term, UnOp, Value};
+
+pub fn lower(program: &Program, env: &Environment) -> Module {
+    let mut ctx = CodeGen::new(env.source_file().is_some());
+    ctx.set_section(Section::Init);
+    ctx.emit(OpCode::Call(program.init, 0));
+    ctx.emit(OpCode::Pop);
+    if let Some(main) = program.main {
+        if let Some(line) = env.source_line(program.funcs[main].span) {
+            ctx.line(line);
+        }
+        ctx.emit(OpCode::Call(main, 0));
+        ctx.emit(OpCode::Pop);
+    }
+    let end = ctx.new_label();
+    ctx.emit_jump(OpCode::Jmp, end);
+
+    ctx.set_section(Section::Funcs);
+    let mut funcs = Vec::new();
+    for (index, func) in program.funcs.iter().enumerate() {
+        let nlocals = FuncLower::new(func, env, &mut ctx).lower(index);
+        funcs.push(Function {
+            name: func.name.clone(),
+            entry: 0,
+            arity: func.arity,
+            nlocals,
+            ret: if func.ret { Some(NativeType::Int) } else { None },
+        });
+    }
+    ctx.place(end);
+    let out = ctx.finish();
+    for (index, entry) in out.entries {
+        funcs[index].entry = entry;
+    }
+    let debug = env.source_file().map(|file| DebugInfo { files: vec![(0, file.to_string())], lines: out.lines, locals: Vec::new() });
+    Module { funcs, globals: program.globals.clone(), code: out.code, debug, link: None }
+}
+
+struct FuncLower<'a> {
+    func: &'a Func,
+    env: &'a Environment,
+    ctx: &'a mut CodeGen,
+    // 当前生成的代码所在的行
+    line: Option<usize>,
+    labels: Vec<Label>,
+    // 每个值使用的次数
+    uses: Vec<usize>,
+    // 每个值的槽位
+    slots: Vec<Option<usize>>,
+    nlocals: usize,
+    // 留在栈上的值
+    pending: Option<Value>,
+}
+
+impl<'a> FuncLower<'a> {
+    fn new(func: &'a Func, env: &'a Environment, ctx: &'a mut CodeGen) -> Self {
+        let mut uses = vec![0; func.insts.len()];
+        for block in &func.blocks {
+            for v in &block.insts {
+                for op in func.insts[v.0].operands() {
+                    uses[op.0] += 1;
+                }
+            }
+            for op in block.term.operands() {
+                uses[op.0] += 1;
+            }
+        }
+        let labels = func.blocks.iter().map(|_| ctx.new_label()).collect();
+        Self {
+            func,
+            env,
+            ctx,
+            line: None,
+            labels,
+            uses,
+            slots: vec![None; func.insts.len()],
+            nlocals: func.arity + func.slots,
+            pending: None,
+        }
+    }
+
+    // 返回栈帧的槽位个数
+    fn lower(mut self, index: usize) -> usize {
+        let func = self.func;
+        let order = func.rpo();
+        // phi 在前驱块中赋值，先分配槽位；其他的值在存入时分配
+        for b in &order {
+            for v in &func.blocks[b.0].insts {
+                match func.insts[v.0] {
+                    Inst::Param(i) => self.slots[v.0] = Some(i),
+                    Inst::Phi(_) => {
+                        self.new_slot(*v);
+                    },
+                    _ => (),
+                }
+            }
+        }
+        self.ctx.func_entry(index, self.labels[0]);
+        self.ctx.place(self.labels[0]);
+        // 栈帧大小在生成完函数后回填
+        let frame = self.ctx.offset();
+        self.ctx.emit(OpCode::EnterFrame(0));
+        for (i, b) in order.iter().enumerate() {
+            if i > 0 {
+                self.ctx.place(self.labels[b.0]);
+            }
+            self.block(*b, order.get(i + 1).copied());
+        }
+        self.ctx.replace(frame, OpCode::EnterFrame(self.nlocals));
+        self.nlocals
+    }
+
+    fn new_slot(&mut self, v: Value) -> usize {
+        self.nlocals += 1;
+        self.slots[v.0] = Some(self.nlocals - 1);
+        self.nlocals - 1
+    }
+
+    // 之后的指令属于 span 所在的行
+    fn set_line(&mut self, span: Span) {
+        let line = self.env.source_line(span);
+        if let Some(l) = line.filter(|_| line != self.line) {
+            self.ctx.line(l);
+            self.line = line;
+        }
+    }
+
+    // 把值压栈
+    fn load(&mut self, v: Value) {
+        if self.pending == Some(v) {
+            self.pending = None;
+            return;
+        }
+        match self.func.insts[v.0] {
+            Inst::Int(k) => self.ctx.emit(OpCode::LoadConst(k)),
+            Inst::Bool(true) => self.ctx.emit(OpCode::LoadTrue),
+            Inst::Bool(false) => self.ctx.emit(OpCode::LoadFalse),
+            Inst::Null => self.ctx.emit(OpCode::LoadNull),
+            _ => self.ctx.emit(OpCode::LoadVar(self.slots[v.0].expect("值没有槽位"))),
+        }
+    }
+
+    // 下一条指令最先压栈的操作数
+    fn first_operand(inst: &Inst) -> Option<Value> {
+        match inst {
+            Inst::Phi(_) => None,
+            Inst::Call(_, args) => args.last().copied(),
+            inst => inst.operands().first().copied(),
+        }
+    }
+
+    fn block(&mut self, b: BlockId, next: Option<BlockId>) {
+        let func = self.func;
+        let block = &func.blocks[b.0];
+        // 块可能从别处跳转过来，开头重新记录行号
+        self.line = None;
+        for (i, v) in block.insts.iter().enumerate() {
+            let inst = &func.insts[v.0];
+            if !inst.is_const() && !matches!(inst, Inst::Param(_) | Inst::Phi(_)) {
+                self.set_line(func.spans[v.0]);
+            }
+            match inst {
+                Inst::Param(_) | Inst::Int(_) | Inst::Bool(_) | Inst::Null | Inst::Phi(_) => continue,
+                Inst::Unary(op, a) => {
+                    self.load(*a);
+                    self.ctx.emit(match op {
+                        UnOp::Neg => OpCode::UniOpNeg,
+                        UnOp::Not => OpCode::UniOpNot,
+                    });
+                },
+                Inst::Binary(op, a, b) => {
+                    self.load(*a);
+                    self.load(*b);
+                    self.ctx.emit(op.opcode());
+                },
+                Inst::LoadGlobal(g) => self.ctx.emit(OpCode::LoadGlobal(*g)),
+                Inst::StoreGlobal(g, a) => {
+                    self.load(*a);
+                    self.ctx.emit(OpCode::StoreGlobal(*g));
+                },
+                Inst::Load(s) => self.ctx.emit(OpCode::LoadVar(func.arity + s)),
+                Inst::Store(s, a) => {
+                    self.load(*a);
+                    self.ctx.emit(OpCode::StoreVar(func.arity + s));
+                },
+                Inst::Call(f, args) => {
+                    // 参数按相反的顺序压栈
+                    for a in args.iter().rev() {
+                        self.load(*a);
+                    }
+                    self.ctx.emit(OpCode::Call(*f, args.len()));
+                },
+                Inst::CallNative(f, args) => {
+                    for a in args {
+                        self.load(*a);
+                    }
+                    self.ctx.emit(OpCode::CallNative(*f, args.len()));
+                },
+                Inst::Print(a) => {
+                    self.load(*a);
+                    self.ctx.emit(OpCode::PrintItem);
+                    self.ctx.emit(OpCode::PrintNewline);
+                },
+                Inst::GetInt => self.ctx.emit(OpCode::GetInt),
+                Inst::PopHandler => self.ctx.emit(OpCode::PopHandler),
+                // 异常已经在栈顶
+                Inst::Catch => (),
+            }
+            if !inst.has_value() {
+                continue;
+            }
+            // 结果留给下一条生成代码的指令、存入槽位或者丢弃
+            let next_inst = block.insts[i + 1..].iter().map(|v| &func.insts[v.0]).find(|inst| !inst.is_const() && !matches!(inst, Inst::Param(_)));
+            let consumer = match next_inst {
+                Some(inst) => Self::first_operand(inst),
+                None => block.term.operands().first().copied(),
+            };
+            if self.uses[v.0] == 1 && consumer == Some(*v) {
+                self.pending = Some(*v);
+            } else if self.uses[v.0] > 0 {
+                let slot = self.new_slot(*v);
+                self.ctx.emit(OpCode::StoreVar(slot));
+            } else {
+                self.ctx.emit(OpCode::Pop);
+            }
+        }
+        self.set_line(block.span);
+        match &block.term {
+            Term::Jmp(t) => {
+                self.phi_copies(b, *t);
+                self.jump(*t, next);
+            },
+            Term::Br(c, t, f) => {
+                self.load(*c);
+                if self.has_phis(*f) {
+                    let stub = self.ctx.new_label();
+                    self.ctx.emit_jump(OpCode::JmpFalse, stub);
+                    self.phi_copies(b, *t);
+                    self.ctx.emit_jump(OpCode::Jmp, self.labels[t.0]);
+                    self.ctx.place(stub);
+                    self.phi_copies(b, *f);
+                    self.jump(*f, next);
+                } else {
+                    self.ctx.emit_jump(OpCode::JmpFalse, self.labels[f.0]);
+                    self.phi_copies(b, *t);
+                    self.jump(*t, next);
+                }
+            },
+            Term::Ret(v) => {
+                self.load(*v);
+                self.ctx.emit(OpCode::Ret);
+            },
+            Term::Throw(v) => {
+                self.load(*v);
+                self.ctx.emit(OpCode::Throw);
+            },
+            Term::Try(body, catch) => {
+                self.ctx.emit_jump(OpCode::PushHandler, self.labels[catch.0]);
+                self.jump(*body, next);
+            },
+        }
+    }
+
+    fn jump(&mut self, target: BlockId, next: Option<BlockId>) {
+        if next != Some(target) {
+            self.ctx.emit_jump(OpCode::Jmp, self.labels[target.0]);
+        }
+    }
+
+    fn phis(&self, b: BlockId) -> impl Iterator<Item = (Value, &Vec<(BlockId, Value)>)> + '_ {
+        self.func.blocks[b.0].insts.iter().map_while(|v| match &self.func.insts[v.0] {
+            Inst::Phi(incoming) => Some((*v, incoming)),
+            _ => None,
+        })
+    }
+
+    fn has_phis(&self, b: BlockId) -> bool {
+        self.phis(b).next().is_some()
+    }
+
+    // 从 from 跳到 to 时给 to 的 phi 赋值
+    fn phi_copies(&mut self, from: BlockId, to: BlockId) {
+        let copies: Vec<(Value, Value)> = self.phis(to)
+            .map(|(phi, incoming)| (phi, incoming.iter().find(|(p, _)| *p == from).expect("phi 缺少来源").1))
+            .filter(|(phi, src)| phi != src)
+            .collect();
+        for (_, src) in &copies {
+            self.load(*src);
+        }
+        for (phi, _) in copies.iter().rev() {
+            self.ctx.emit(OpCode::StoreVar(self.slots[phi.0].unwrap()));
+        }
+    }
+}
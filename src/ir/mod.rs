@@ -0,0 +1,538 @@
+/*!
+ * SSA 形式的中间表示：每个函数是由基本块组成的控制流图，位于 AST 和栈式虚拟机的指令之间，供优化使用。
+ *
+ * 函数的指令保存在 insts 中，编号即指令定义的值（%n）；基本块依次列出其中的指令，最后是一条终结指令。
+ * 每个值只在一处定义，块开头的 phi 按从哪个前驱块到达选择值。局部变量在构造时转换为 SSA 值；
+ * 在 try 块中被赋值的变量保存在栈帧的内存槽位中（load / store），抛出异常后 catch 块读到的才是最后一次赋的值。
+ * 全局变量的初始化代码是函数 <init>，程序先调用它，再调用 main。
+ * 每条指令和每个块的终结指令都记录所在语句的源代码位置，优化时随指令移动，lower 据此生成行号表。
+ *
+ * build 从 AST 构造 IR，verify 检查 IR 的结构和 SSA 性质，lower 把 IR 翻译为栈式虚拟机的模块。
+ * 文本形式（Display）只用于查看和调试：
+ *
+ * ```text
+ * func fact(1) -> int {
+ * bb0:
+ *     %0 = param 0
+ *     %1 = int 0
+ *     %2 = eq %0, %1
+ *     br %2, bb1, bb2
+ * ...
+ * ```
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::Span;
+use crate::vm::OpCode;
+
+pub mod build;
+pub mod verify;
+pub mod lower;
+pub mod opt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Param(usize),                   // 第几个参数，只在入口块
+    Int(i32),
+    Bool(bool),
+    Null,
+    Unary(UnOp, Value),
+    Binary(BinOp, Value, Value),
+    LoadGlobal(usize),              // 全局变量编号
+    StoreGlobal(usize, Value),
+    Load(usize),                    // 内存槽位
+    Store(usize, Value),
+    Call(usize, Vec<Value>),        // 函数编号, 参数
+    CallNative(usize, Vec<Value>),  // 原生函数编号, 参数
+    Print(Value),
+    GetInt,
+    PopHandler,                     // 离开 try 块
+    Catch,                          // catch 块的第一条指令，值为异常
+    Phi(Vec<(BlockId, Value)>),     // (前驱块, 值)
+}
+
+impl Inst {
+    // 是否定义一个值
+    pub fn has_value(&self) -> bool {
+        !matches!(self, Inst::StoreGlobal(..) | Inst::Store(..) | Inst::Print(_) | Inst::PopHandler)
+    }
+    // 是否只是一个常数，翻译时可以在使用处直接加载
+    pub fn is_const(&self) -> bool {
+        matches!(self, Inst::Int(_) | Inst::Bool(_) | Inst::Null)
+    }
+    // 删除后程序的行为是否可能改变：写入变量、调用、输入输出、异常处理，以及可能出错的运算
+    pub fn has_effect(&self) -> bool {
+        match self {
+            Inst::Param(_) | Inst::Int(_) | Inst::Bool(_) | Inst::Null | Inst::LoadGlobal(_) | Inst::Load(_) | Inst::Phi(_) => false,
+            // 比较是否相等不会出错；其他运算在操作数类型不对（例如 null）或除数为 0 时出错，
+            // 优化时由类型分析排除不会出错的情况（见 opt::has_effect）
+            Inst::Binary(BinOp::Eq | BinOp::Ne, ..) => false,
+            _ => true,
+        }
+    }
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Inst::Unary(_, a) | Inst::StoreGlobal(_, a) | Inst::Store(_, a) | Inst::Print(a) => vec![*a],
+            Inst::Binary(_, a, b) => vec![*a, *b],
+            Inst::Call(_, args) | Inst::CallNative(_, args) => args.clone(),
+            Inst::Phi(incoming) => incoming.iter().map(|(_, v)| *v).collect(),
+            _ => Vec::new(),
+        }
+    }
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Inst::Unary(_, a) | Inst::StoreGlobal(_, a) | Inst::Store(_, a) | Inst::Print(a) => vec![a],
+            Inst::Binary(_, a, b) => vec![a, b],
+            Inst::Call(_, args) | Inst::CallNative(_, args) => args.iter_mut().collect(),
+            Inst::Phi(incoming) => incoming.iter_mut().map(|(_, v)| v).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Jmp(BlockId),
+    Br(Value, BlockId, BlockId),    // 条件为真时到第一个块
+    Ret(Value),
+    Throw(Value),
+    Try(BlockId, BlockId),          // 进入 try 块，抛出异常时到 catch 块
+}
+
+impl Term {
+    pub fn succs(&self) -> Vec<BlockId> {
+        match self {
+            Term::Jmp(b) => vec![*b],
+            Term::Br(_, t, f) => vec![*t, *f],
+            Term::Try(body, catch) => vec![*body, *catch],
+            Term::Ret(_) | Term::Throw(_) => Vec::new(),
+        }
+    }
+    pub fn succs_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Term::Jmp(b) => vec![b],
+            Term::Br(_, t, f) => vec![t, f],
+            Term::Try(body, catch) => vec![body, catch],
+            Term::Ret(_) | Term::Throw(_) => Vec::new(),
+        }
+    }
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Term::Br(v, ..) | Term::Ret(v) | Term::Throw(v) => vec![*v],
+            Term::Jmp(_) | Term::Try(..) => Vec::new(),
+        }
+    }
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Term::Br(v, ..) | Term::Ret(v) | Term::Throw(v) => vec![v],
+            Term::Jmp(_) | Term::Try(..) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub insts: Vec<Value>,
+    pub term: Term,
+    pub span: Span,                 // 终结指令的位置
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Func {
+    pub name: String,
+    pub arity: usize,
+    pub ret: bool,                  // 是否有返回值；没有时返回 null
+    pub slots: usize,               // 内存槽位的个数
+    pub span: Span,                 // 函数头的位置
+    pub insts: Vec<Inst>,
+    pub spans: Vec<Span>,           // 每条指令的位置，与 insts 一一对应
+    pub blocks: Vec<Block>,         // 第一个是入口块
+}
+
+impl Func {
+    // 每个块的前驱，按块的顺序
+    pub fn preds(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for succ in block.term.succs() {
+                if let Some(p) = preds.get_mut(succ.0) {
+                    p.push(BlockId(i));
+                }
+            }
+        }
+        preds
+    }
+    // 从入口可以到达的块，按逆后序；后继按相反的顺序访问，条件跳转为真的目标排在紧接着的位置
+    pub fn rpo(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut post = Vec::new();
+        // (块, 下一个要访问的后继)
+        let mut stack = vec![(BlockId(0), 0)];
+        visited[0] = true;
+        while let Some((b, i)) = stack.pop() {
+            let succs = self.blocks[b.0].term.succs();
+            if let Some(succ) = succs.iter().rev().nth(i) {
+                stack.push((b, i + 1));
+                if succ.0 < self.blocks.len() && !visited[succ.0] {
+                    visited[succ.0] = true;
+                    stack.push((*succ, 0));
+                }
+            } else {
+                post.push(b);
+            }
+        }
+        post.reverse();
+        post
+    }
+    // 每个块的直接支配者，入口块为它自己，不可达的块为 None
+    pub fn idoms(&self) -> Vec<Option<BlockId>> {
+        let rpo = self.rpo();
+        let mut order = vec![usize::MAX; self.blocks.len()];
+        for (i, b) in rpo.iter().enumerate() {
+            order[b.0] = i;
+        }
+        let preds = self.preds();
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(BlockId(0));
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while order[a.0] > order[b.0] {
+                    a = idom[a.0].unwrap();
+                }
+                while order[b.0] > order[a.0] {
+                    b = idom[b.0].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in rpo.iter().skip(1) {
+                let mut new = None;
+                for p in &preds[b.0] {
+                    if idom[p.0].is_some() {
+                        new = Some(match new {
+                            None => *p,
+                            Some(cur) => intersect(&idom, *p, cur),
+                        });
+                    }
+                }
+                if new.is_some() && idom[b.0] != new {
+                    idom[b.0] = new;
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+
+    // 删除从入口不可达的块，以及 phi 中来自这些块的值；返回是否删除了块
+    pub fn remove_unreachable(&mut self) -> bool {
+        let mut reachable = vec![false; self.blocks.len()];
+        for b in self.rpo() {
+            reachable[b.0] = true;
+        }
+        if reachable.iter().all(|r| *r) {
+            return false;
+        }
+        for b in 0..self.blocks.len() {
+            if !reachable[b] {
+                continue;
+            }
+            for v in &self.blocks[b].insts {
+                if let Inst::Phi(incoming) = &mut self.insts[v.0] {
+                    incoming.retain(|(pred, _)| reachable[pred.0]);
+                }
+            }
+        }
+        let mut map = vec![None; self.blocks.len()];
+        let mut blocks = Vec::new();
+        for (b, block) in std::mem::take(&mut self.blocks).into_iter().enumerate() {
+            if reachable[b] {
+                map[b] = Some(BlockId(blocks.len()));
+                blocks.push(block);
+            }
+        }
+        let remap = |b: &mut BlockId| *b = map[b.0].unwrap();
+        for block in &mut blocks {
+            block.term.succs_mut().into_iter().for_each(remap);
+            for v in &block.insts {
+                if let Inst::Phi(incoming) = &mut self.insts[v.0] {
+                    incoming.iter_mut().for_each(|(pred, _)| remap(pred));
+                }
+            }
+        }
+        self.blocks = blocks;
+        true
+    }
+    // 删除所有值都相同（或是它自己）的 phi，用那个值代替它；返回是否删除了 phi
+    pub fn remove_trivial_phis(&mut self) -> bool {
+        let mut replace = HashMap::new();
+        loop {
+            let mut changed = false;
+            for b in 0..self.blocks.len() {
+                for v in self.blocks[b].insts.clone() {
+                    let Inst::Phi(incoming) = &self.insts[v.0] else {
+                        continue;
+                    };
+                    let mut same = None;
+                    let mut trivial = true;
+                    for (_, op) in incoming {
+                        let op = resolve(&replace, *op);
+                        if op == v || same == Some(op) {
+                            continue;
+                        }
+                        if same.is_some() {
+                            trivial = false;
+                            break;
+                        }
+                        same = Some(op);
+                    }
+                    if let (true, Some(same)) = (trivial, same) {
+                        replace.insert(v, same);
+                        self.blocks[b].insts.retain(|x| *x != v);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        self.replace_uses(&replace);
+        !replace.is_empty()
+    }
+    // 把使用的值 v 换成 replace[v]，替换后的值如果也在 replace 中则继续替换
+    pub fn replace_uses(&mut self, replace: &HashMap<Value, Value>) {
+        if !replace.is_empty() {
+            self.map_values(|v| resolve(replace, v));
+        }
+    }
+    // 把所有使用的值换成 f(值)
+    pub fn map_values(&mut self, f: impl Fn(Value) -> Value) {
+        for block in &mut self.blocks {
+            for v in &block.insts {
+                for op in self.insts[v.0].operands_mut() {
+                    *op = f(*op);
+                }
+            }
+            for op in block.term.operands_mut() {
+                *op = f(*op);
+            }
+        }
+    }
+    // 新建一条不在任何块中的指令
+    pub fn new_inst(&mut self, inst: Inst, span: Span) -> Value {
+        self.insts.push(inst);
+        self.spans.push(span);
+        Value(self.insts.len() - 1)
+    }
+    // 按块的顺序给块中的指令重新编号，删除不在任何块中的指令
+    pub fn compact(&mut self) {
+        let mut map = vec![None; self.insts.len()];
+        let mut insts = Vec::new();
+        let mut spans = Vec::new();
+        for block in &mut self.blocks {
+            for v in &mut block.insts {
+                map[v.0] = Some(Value(insts.len()));
+                insts.push(self.insts[v.0].clone());
+                spans.push(self.spans[v.0]);
+                *v = Value(insts.len() - 1);
+            }
+        }
+        self.insts = insts;
+        self.spans = spans;
+        self.map_values(|v| map[v.0].unwrap_or(v));
+    }
+}
+
+fn resolve(replace: &HashMap<Value, Value>, mut v: Value) -> Value {
+    while let Some(r) = replace.get(&v) {
+        v = *r;
+    }
+    v
+}
+
+// a 是否支配 b（包括 a == b），idom 由 Func::idoms 得到
+pub fn dominates(idom: &[Option<BlockId>], a: BlockId, mut b: BlockId) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        match idom[b.0] {
+            Some(up) if up != b => b = up,
+            _ => return false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub globals: Vec<String>,
+    pub funcs: Vec<Func>,
+    pub init: usize,                // 初始化全局变量的函数
+    pub main: Option<usize>,        // 没有定义函数时为 None
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl BinOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Mod => "mod",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+            BinOp::Gt => "gt",
+            BinOp::Ge => "ge",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+        }
+    }
+    // 对应的虚拟机指令
+    pub fn opcode(&self) -> OpCode {
+        match self {
+            BinOp::Add => OpCode::BinOpAdd,
+            BinOp::Sub => OpCode::BinOpSub,
+            BinOp::Mul => OpCode::BinOpMul,
+            BinOp::Div => OpCode::BinOpDiv,
+            BinOp::Mod => OpCode::BinOpMod,
+            BinOp::Lt => OpCode::BinOpLt,
+            BinOp::Le => OpCode::BinOpLe,
+            BinOp::Gt => OpCode::BinOpGt,
+            BinOp::Ge => OpCode::BinOpGe,
+            BinOp::Eq => OpCode::BinOpEq,
+            BinOp::Ne => OpCode::BinOpNe,
+        }
+    }
+}
+
+fn join(values: &[Value]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl Program {
+    fn fmt_inst(&self, f: &mut fmt::Formatter<'_>, inst: &Inst) -> fmt::Result {
+        let global = |g: usize| self.globals.get(g).map_or(format!("@{}", g), |name| format!("@{}", name));
+        let func = |i: usize| self.funcs.get(i).map_or(format!("#{}", i), |func| func.name.clone());
+        match inst {
+            Inst::Param(i) => write!(f, "param {}", i),
+            Inst::Int(k) => write!(f, "int {}", k),
+            Inst::Bool(b) => write!(f, "bool {}", b),
+            Inst::Null => write!(f, "null"),
+            Inst::Unary(UnOp::Neg, a) => write!(f, "neg {}", a),
+            Inst::Unary(UnOp::Not, a) => write!(f, "not {}", a),
+            Inst::Binary(op, a, b) => write!(f, "{} {}, {}", op.name(), a, b),
+            Inst::LoadGlobal(g) => write!(f, "load_global {}", global(*g)),
+            Inst::StoreGlobal(g, v) => write!(f, "store_global {}, {}", global(*g), v),
+            Inst::Load(s) => write!(f, "load ${}", s),
+            Inst::Store(s, v) => write!(f, "store ${}, {}", s, v),
+            Inst::Call(i, args) => write!(f, "call {}({})", func(*i), join(args)),
+            Inst::CallNative(i, args) => write!(f, "call_native #{}({})", i, join(args)),
+            Inst::Print(v) => write!(f, "print {}", v),
+            Inst::GetInt => write!(f, "getint"),
+            Inst::PopHandler => write!(f, "pop_handler"),
+            Inst::Catch => write!(f, "catch"),
+            Inst::Phi(incoming) => {
+                let items: Vec<String> = incoming.iter().map(|(b, v)| format!("{}: {}", b, v)).collect();
+                write!(f, "phi [{}]", items.join(", "))
+            },
+        }
+    }
+    pub fn fmt_func(&self, f: &mut fmt::Formatter<'_>, func: &Func) -> fmt::Result {
+        write!(f, "func {}({})", func.name, func.arity)?;
+        if func.ret {
+            write!(f, " -> int")?;
+        }
+        if func.slots > 0 {
+            write!(f, ", slots {}", func.slots)?;
+        }
+        writeln!(f, " {{")?;
+        let preds = func.preds();
+        for (i, block) in func.blocks.iter().enumerate() {
+            write!(f, "{}:", BlockId(i))?;
+            if !preds[i].is_empty() {
+                let preds: Vec<String> = preds[i].iter().map(|p| p.to_string()).collect();
+                write!(f, "    ; preds {}", preds.join(", "))?;
+            }
+            writeln!(f)?;
+            for v in &block.insts {
+                write!(f, "    ")?;
+                match func.insts.get(v.0) {
+                    Some(inst) => {
+                        if inst.has_value() {
+                            write!(f, "{} = ", v)?;
+                        }
+                        self.fmt_inst(f, inst)?;
+                    },
+                    None => write!(f, "<{}?>", v)?,
+                }
+                writeln!(f)?;
+            }
+            match &block.term {
+                Term::Jmp(b) => writeln!(f, "    jmp {}", b)?,
+                Term::Br(c, t, e) => writeln!(f, "    br {}, {}, {}", c, t, e)?,
+                Term::Ret(v) => writeln!(f, "    ret {}", v)?,
+                Term::Throw(v) => writeln!(f, "    throw {}", v)?,
+                Term::Try(body, catch) => writeln!(f, "    try {}, catch {}", body, catch)?,
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for g in &self.globals {
+            writeln!(f, "global @{}", g)?;
+        }
+        for func in &self.funcs {
+            writeln!(f)?;
+            self.fmt_func(f, func)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+/*!
+ * 公共子表达式删除：沿支配树从上到下记录已经计算过的运算，被支配的块中相同的计算直接使用之前的结果。
+ * 常数操作数按值比较；加法、乘法、相等和不相等的两个操作数交换后视为相同。
+ * 常数本身不合并：生成代码时常数在每个使用处重新加载，合并只会让常数离开它原来所在的块。
+ *
+ * 除法和取余也可以删除：支配它的相同运算已经执行过而没有出错，再算一次结果相同。
+ * 读内存的指令不在这里处理（见 propagate）。
+ */
+
+use std::collections::HashMap;
+
+use crate::ir::{BinOp, BlockId, Func, Inst, UnOp, Value};
+
+// 操作数：常数按值比较，同一个常数的不同定义视为相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Operand {
+    Int(i32),
+    Bool(bool),
+    Null,
+    Value(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Expr {
+    Unary(UnOp, Operand),
+    Binary(BinOp, Operand, Operand),
+}
+
+fn operand(func: &Func, v: Value) -> Operand {
+    match func.insts[v.0] {
+        Inst::Int(k) => Operand::Int(k),
+        Inst::Bool(b) => Operand::Bool(b),
+        Inst::Null => Operand::Null,
+        _ => Operand::Value(v),
+    }
+}
+
+fn expr(func: &Func, inst: &Inst) -> Option<Expr> {
+    match *inst {
+        Inst::Unary(op, a) => Some(Expr::Unary(op, operand(func, a))),
+        Inst::Binary(op @ (BinOp::Add | BinOp::Mul | BinOp::Eq | BinOp::Ne), a, b) => {
+            let (a, b) = (operand(func, a), operand(func, b));
+            Some(Expr::Binary(op, a.min(b), a.max(b)))
+        },
+        Inst::Binary(op, a, b) => Some(Expr::Binary(op, operand(func, a), operand(func, b))),
+        _ => None,
+    }
+}
+
+pub fn run(func: &mut Func) -> bool {
+    let idom = func.idoms();
+    let mut children = vec![Vec::new(); func.blocks.len()];
+    for (b, d) in idom.iter().enumerate() {
+        if let Some(d) = d {
+            if d.0 != b {
+                children[d.0].push(BlockId(b));
+            }
+        }
+    }
+    let mut table: HashMap<Expr, Value> = HashMap::new();
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    // (块, 是否是离开)；离开块时删除它记录的表达式
+    let mut added: Vec<Vec<Expr>> = vec![Vec::new(); func.blocks.len()];
+    let mut stack = vec![(BlockId(0), false)];
+    while let Some((b, leave)) = stack.pop() {
+        if leave {
+            for e in &added[b.0] {
+                table.remove(e);
+            }
+            continue;
+        }
+        stack.push((b, true));
+        let mut removed = false;
+        for v in &func.blocks[b.0].insts {
+            // 操作数先换成之前的结果
+            for op in func.insts[v.0].operands_mut() {
+                if let Some(r) = replace.get(op) {
+                    *op = *r;
+                }
+            }
+            let Some(e) = expr(func, &func.insts[v.0]) else {
+                continue;
+            };
+            match table.get(&e) {
+                Some(prev) => {
+                    replace.insert(*v, *prev);
+                    removed = true;
+                },
+                None => {
+                    table.insert(e, *v);
+                    added[b.0].push(e);
+                },
+            }
+        }
+        if removed {
+            func.blocks[b.0].insts.retain(|v| !replace.contains_key(v));
+        }
+        stack.extend(children[b.0].iter().rev().map(|c| (*c, false)));
+    }
+    if replace.is_empty() {
+        return false;
+    }
+    func.replace_uses(&replace);
+    true
+}
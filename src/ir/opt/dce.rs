@@ -0,0 +1,136 @@
+/*!
+ * 死代码删除：从有副作用的指令（见 opt::has_effect，可能出错的运算也算）和终结指令出发标记用到的值，删除没有被标记的指令；
+ * 然后删除不可达的块，把只有一个前驱、前驱无条件跳转过来的块合并到前驱中，
+ * 并让跳转到空块（只有一条无条件跳转）的前驱直接跳到它的目标。
+ */
+
+use std::collections::HashMap;
+
+use super::{has_effect, types};
+use crate::ir::{BlockId, Func, Inst, Term, Value};
+
+pub fn run(func: &mut Func) -> bool {
+    let mut changed = remove_dead(func);
+    changed |= func.remove_unreachable();
+    changed |= merge_blocks(func);
+    changed |= skip_empty_blocks(func);
+    if changed {
+        func.remove_unreachable();
+        func.remove_trivial_phis();
+    }
+    changed
+}
+
+fn remove_dead(func: &mut Func) -> bool {
+    let mut live = vec![false; func.insts.len()];
+    let mut work: Vec<Value> = Vec::new();
+    let ty = types(func);
+    for block in &func.blocks {
+        for v in &block.insts {
+            if has_effect(func, &ty, *v) {
+                work.push(*v);
+            }
+        }
+        work.extend(block.term.operands());
+    }
+    while let Some(v) = work.pop() {
+        if live[v.0] {
+            continue;
+        }
+        live[v.0] = true;
+        work.extend(func.insts[v.0].operands());
+    }
+    let mut changed = false;
+    for block in &mut func.blocks {
+        let len = block.insts.len();
+        block.insts.retain(|v| live[v.0]);
+        changed |= block.insts.len() != len;
+    }
+    changed
+}
+
+// 把 phi 中来自 from 的值改为来自 to
+fn retarget_phis(func: &mut Func, block: BlockId, from: BlockId, to: BlockId) {
+    for v in &func.blocks[block.0].insts {
+        if let Inst::Phi(incoming) = &mut func.insts[v.0] {
+            for (pred, _) in incoming.iter_mut() {
+                if *pred == from {
+                    *pred = to;
+                }
+            }
+        }
+    }
+}
+
+// b 以 jmp t 结束并且是 t 唯一的前驱时，把 t 接到 b 的末尾；t 变为不可达，之后删除
+fn merge_blocks(func: &mut Func) -> bool {
+    let mut preds = func.preds();
+    let mut changed = false;
+    for b in func.rpo() {
+        while let Term::Jmp(t) = func.blocks[b.0].term {
+            if t == b || t.0 == 0 || preds[t.0].len() != 1 {
+                break;
+            }
+            // t 中的 phi 只有一个来源
+            let mut replace = HashMap::new();
+            let mut insts = Vec::new();
+            for v in std::mem::take(&mut func.blocks[t.0].insts) {
+                match &func.insts[v.0] {
+                    Inst::Phi(incoming) => {
+                        replace.insert(v, incoming[0].1);
+                    },
+                    _ => insts.push(v),
+                }
+            }
+            let term = std::mem::replace(&mut func.blocks[t.0].term, Term::Jmp(t));
+            let span = func.blocks[t.0].span;
+            preds[t.0].clear();
+            for succ in term.succs() {
+                retarget_phis(func, succ, t, b);
+                for pred in preds[succ.0].iter_mut() {
+                    if *pred == t {
+                        *pred = b;
+                    }
+                }
+            }
+            func.blocks[b.0].insts.extend(insts);
+            func.blocks[b.0].term = term;
+            func.blocks[b.0].span = span;
+            func.replace_uses(&replace);
+            changed = true;
+        }
+    }
+    changed
+}
+
+// 空块 e 以 jmp x 结束、x 开头没有 phi 时，e 的前驱直接跳到 x。x 有 phi 时不处理：
+// 生成代码时 phi 的赋值放在每条边上，原来共用 e 中的一份，直接跳过去反而每个前驱都要一份。
+// 前驱已经是 x 的前驱时也不处理，否则同一个块会成为两个跳转目标
+fn skip_empty_blocks(func: &mut Func) -> bool {
+    let mut changed = false;
+    for e in 1..func.blocks.len() {
+        let e = BlockId(e);
+        let Term::Jmp(x) = func.blocks[e.0].term else {
+            continue;
+        };
+        if x == e || !func.blocks[e.0].insts.is_empty() {
+            continue;
+        }
+        if func.blocks[x.0].insts.first().is_some_and(|v| matches!(func.insts[v.0], Inst::Phi(_))) {
+            continue;
+        }
+        let preds = func.preds();
+        for p in &preds[e.0] {
+            if preds[x.0].contains(p) {
+                continue;
+            }
+            for succ in func.blocks[p.0].term.succs_mut() {
+                if *succ == e {
+                    *succ = x;
+                    changed = true;
+                }
+            }
+        }
+    }
+    changed
+}
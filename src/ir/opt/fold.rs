@@ -0,0 +1,73 @@
+/*!
+ * 常量传播和折叠：操作数都是常数的运算直接算出结果，来源都是同一个常数的 phi 换成这个常数，
+ * 条件是常数的分支换成无条件跳转，然后删除不再可达的块。
+ *
+ * 运算用虚拟机的 Value 计算，结果与运行时完全相同；会出错的运算（例如除数为 0）不折叠，留到运行时报告。
+ */
+
+use crate::vm::value::Value as V;
+
+use super::{const_inst, constant, phis_first};
+use crate::ir::{Func, Inst, Term, UnOp};
+
+pub fn run(func: &mut Func) -> bool {
+    let mut changed = false;
+    let mut phi_folded = false;
+    // 按逆后序处理，折叠出的常数在同一遍中继续传播
+    for b in func.rpo() {
+        for i in 0..func.blocks[b.0].insts.len() {
+            let v = func.blocks[b.0].insts[i];
+            let folded = match &func.insts[v.0] {
+                Inst::Unary(op, a) => constant(func, *a).and_then(|a| match op {
+                    UnOp::Neg => a.checked_neg().ok(),
+                    UnOp::Not => a.logical_not().ok(),
+                }),
+                Inst::Binary(op, a, b) => match (constant(func, *a), constant(func, *b)) {
+                    (Some(a), Some(b)) => V::binop(op.opcode(), a, b).ok(),
+                    _ => None,
+                },
+                Inst::Phi(incoming) => {
+                    let values: Option<Vec<V>> = incoming.iter().map(|(_, v)| constant(func, *v)).collect();
+                    match values {
+                        Some(values) if !values.is_empty() && values.iter().all(|x| *x == values[0]) => {
+                            phi_folded = true;
+                            Some(values[0])
+                        },
+                        _ => None,
+                    }
+                },
+                _ => None,
+            };
+            if let Some(inst) = folded.and_then(const_inst) {
+                func.insts[v.0] = inst;
+                changed = true;
+            }
+        }
+    }
+    if phi_folded {
+        phis_first(func);
+    }
+
+    // 条件确定的分支
+    for b in 0..func.blocks.len() {
+        let Term::Br(c, t, f) = func.blocks[b].term else {
+            continue;
+        };
+        let Some(cond) = constant(func, c).and_then(|c| c.truthy().ok()) else {
+            continue;
+        };
+        let (taken, dropped) = if cond { (t, f) } else { (f, t) };
+        func.blocks[b].term = Term::Jmp(taken);
+        for v in &func.blocks[dropped.0].insts {
+            if let Inst::Phi(incoming) = &mut func.insts[v.0] {
+                incoming.retain(|(pred, _)| pred.0 != b);
+            }
+        }
+        changed = true;
+    }
+    if changed {
+        func.remove_unreachable();
+        func.remove_trivial_phis();
+    }
+    changed
+}
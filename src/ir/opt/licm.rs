@@ -0,0 +1,104 @@
+/*!
+ * 循环不变量外提：操作数都在循环外定义（或者已经外提）的无副作用运算移到循环的前置块末尾，只计算一次。
+ * 读内存时还要求循环中没有写同一个位置：槽位没有 store，全局变量没有 store_global 并且循环中没有调用用户函数。
+ * 调用不外提。可能出错的运算（除法、取余，以及操作数不能确定是数的运算）只从循环头外提，
+ * 并且循环头中在它之前的指令都已经外提或者没有副作用：进入循环时一定会执行到它，提前到前置块中计算
+ * 不改变是否出错和出错的时机；循环体中的这些运算在循环一次都不执行时不能执行。
+ * 常数本身不外提（生成代码时在使用处加载），外提的指令用到循环中的常数时在前置块中复制一份。
+ *
+ * 从内层循环开始处理，外提到内层循环前置块的指令在下一轮可以继续外提到外层循环之外。
+ */
+
+use std::collections::HashSet;
+
+use super::loops::find_loops;
+use super::{has_effect, types};
+use crate::ir::{BlockId, Func, Inst, Value};
+
+pub fn run(func: &mut Func) -> bool {
+    let mut changed = false;
+    for lp in find_loops(func) {
+        let Some(pre) = lp.preheader else {
+            continue;
+        };
+        // 每个值所在的块
+        let mut place = vec![None; func.insts.len()];
+        for (b, block) in func.blocks.iter().enumerate() {
+            for v in &block.insts {
+                place[v.0] = Some(BlockId(b));
+            }
+        }
+        let (mut slots, mut globals, mut calls) = (HashSet::new(), HashSet::new(), false);
+        for (b, block) in func.blocks.iter().enumerate() {
+            if !lp.contains(BlockId(b)) {
+                continue;
+            }
+            for v in &block.insts {
+                match func.insts[v.0] {
+                    Inst::Store(s, _) => {
+                        slots.insert(s);
+                    },
+                    Inst::StoreGlobal(g, _) => {
+                        globals.insert(g);
+                    },
+                    Inst::Call(..) => calls = true,
+                    _ => (),
+                }
+            }
+        }
+        // 按逆后序检查，操作数先于使用它的指令
+        let ty = types(func);
+        let mut invariant = HashSet::new();
+        let mut hoisted = Vec::new();
+        for b in func.rpo() {
+            if !lp.contains(b) {
+                continue;
+            }
+            // 循环头中到目前为止的指令都已经外提或者没有副作用
+            let mut first = b == lp.header;
+            for v in &func.blocks[b.0].insts {
+                let inst = &func.insts[v.0];
+                let effect = has_effect(func, &ty, *v);
+                let movable = match inst {
+                    Inst::Phi(_) | Inst::Param(_) | Inst::Catch => false,
+                    inst if inst.is_const() => false,
+                    Inst::Load(s) => !slots.contains(s),
+                    Inst::LoadGlobal(g) => !calls && !globals.contains(g),
+                    Inst::Unary(..) | Inst::Binary(..) => !effect || first,
+                    _ => false,
+                };
+                let outside = |op: &Value| {
+                    invariant.contains(op) || func.insts[op.0].is_const() || !place[op.0].is_some_and(|b| lp.contains(b))
+                };
+                if movable && inst.operands().iter().all(outside) {
+                    invariant.insert(*v);
+                    hoisted.push(*v);
+                } else if effect {
+                    first = false;
+                }
+            }
+        }
+        if hoisted.is_empty() {
+            continue;
+        }
+        for (b, block) in func.blocks.iter_mut().enumerate() {
+            if lp.contains(BlockId(b)) {
+                block.insts.retain(|v| !invariant.contains(v));
+            }
+        }
+        // 外提的指令使用的循环中的常数，在前置块中重新定义
+        for v in hoisted {
+            for i in 0..func.insts[v.0].operands().len() {
+                let op = func.insts[v.0].operands()[i];
+                if func.insts[op.0].is_const() && place[op.0].is_some_and(|b| lp.contains(b)) {
+                    let copy = func.new_inst(func.insts[op.0].clone(), func.spans[op.0]);
+                    func.blocks[pre.0].insts.push(copy);
+                    *func.insts[v.0].operands_mut()[i] = copy;
+                }
+            }
+            func.blocks[pre.0].insts.push(v);
+        }
+        changed = true;
+    }
+    changed
+}
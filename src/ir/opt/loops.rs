@@ -0,0 +1,71 @@
+/*!
+ * 找出函数中的自然循环：跳到支配自己的块（循环头）的边是回边，循环包括循环头和能不经过循环头到达回边起点的块。
+ * 同一个循环头的多条回边合并为一个循环。源代码中只有结构化的控制流，所有循环都有唯一的入口。
+ */
+
+use crate::ir::{dominates, BlockId, Func, Term};
+
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: BlockId,
+    pub blocks: Vec<bool>,          // 每个块是否在循环中
+    pub latches: Vec<BlockId>,      // 回边的起点
+    // 循环外唯一的前驱，并且它只跳到循环头；没有这样的块时为 None
+    pub preheader: Option<BlockId>,
+}
+
+impl Loop {
+    pub fn contains(&self, b: BlockId) -> bool {
+        self.blocks[b.0]
+    }
+    pub fn size(&self) -> usize {
+        self.blocks.iter().filter(|b| **b).count()
+    }
+}
+
+// 按从内到外的顺序（块数从少到多）返回所有循环
+pub fn find_loops(func: &Func) -> Vec<Loop> {
+    let idom = func.idoms();
+    let preds = func.preds();
+    let mut loops: Vec<Loop> = Vec::new();
+    for (b, block) in func.blocks.iter().enumerate() {
+        let latch = BlockId(b);
+        if idom[b].is_none() {
+            continue;
+        }
+        for header in block.term.succs() {
+            if !dominates(&idom, header, latch) {
+                continue;
+            }
+            let i = match loops.iter().position(|lp| lp.header == header) {
+                Some(i) => i,
+                None => {
+                    let mut blocks = vec![false; func.blocks.len()];
+                    blocks[header.0] = true;
+                    loops.push(Loop { header, blocks, latches: Vec::new(), preheader: None });
+                    loops.len() - 1
+                },
+            };
+            let lp = &mut loops[i];
+            lp.latches.push(latch);
+            let mut work = vec![latch];
+            while let Some(x) = work.pop() {
+                if lp.blocks[x.0] {
+                    continue;
+                }
+                lp.blocks[x.0] = true;
+                work.extend(preds[x.0].iter().copied());
+            }
+        }
+    }
+    for lp in &mut loops {
+        let outside: Vec<BlockId> = preds[lp.header.0].iter().copied().filter(|p| !lp.blocks[p.0]).collect();
+        if let [p] = outside[..] {
+            if func.blocks[p.0].term == Term::Jmp(lp.header) {
+                lp.preheader = Some(p);
+            }
+        }
+    }
+    loops.sort_by_key(|lp| lp.size());
+    loops
+}
@@ -0,0 +1,204 @@
+/*!
+ * IR 上的优化。PassManager 按优化级别选择一组 pass，对每个函数依次运行，直到没有 pass 再改变函数（最多 MAX_ROUNDS 轮）。
+ *
+ * - `-O0`：不优化；
+ * - `-O1`：常量传播和折叠（包括条件确定的分支）、复制传播、死代码删除（包括合并只有一个前驱的块）；
+ * - `-O2`：另外进行公共子表达式删除、循环不变量外提和强度削减。
+ *
+ * 每个 pass 之后用 verify 检查 IR，出错时报告是哪个 pass 破坏了 IR。
+ * 运行时错误与输出和对变量的写入一样是程序的行为，都保持原来的顺序：除数为 0 的除法和操作数类型不对的运算
+ * （例如对没有返回值的调用的结果做加法）在每个级别都同样报错，不会被删除，也不会提前到原来不执行的地方。
+ * 类型分析（types）证明操作数都是整数或布尔值的运算才当作没有副作用（见 has_effect）。
+ */
+
+use crate::error::{Error, Result};
+
+use super::verify::verify;
+use super::{BinOp, Func, Inst, Program, UnOp, Value};
+
+pub mod cse;
+pub mod dce;
+pub mod fold;
+pub mod licm;
+pub mod loops;
+pub mod propagate;
+pub mod strength;
+
+const MAX_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    // 命令行参数 -O0、-O1、-O2
+    pub fn parse(arg: &str) -> Option<OptLevel> {
+        match arg {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+// 一个 pass 处理一个函数，返回是否改变了函数
+#[derive(Clone, Copy)]
+pub struct Pass {
+    pub name: &'static str,
+    pub run: fn(&mut Func) -> bool,
+}
+
+pub struct PassManager {
+    passes: Vec<Pass>,
+}
+
+impl PassManager {
+    pub fn new(level: OptLevel) -> Self {
+        let mut passes = Vec::new();
+        if level >= OptLevel::O1 {
+            passes.push(Pass { name: "fold", run: fold::run });
+            passes.push(Pass { name: "propagate", run: propagate::run });
+        }
+        if level >= OptLevel::O2 {
+            passes.push(Pass { name: "strength", run: strength::run });
+            passes.push(Pass { name: "cse", run: cse::run });
+            passes.push(Pass { name: "licm", run: licm::run });
+        }
+        if level >= OptLevel::O1 {
+            passes.push(Pass { name: "dce", run: dce::run });
+        }
+        Self { passes }
+    }
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    pub fn run(&self, program: &mut Program) -> Result<()> {
+        for f in 0..program.funcs.len() {
+            for _ in 0..MAX_ROUNDS {
+                let mut changed = false;
+                for pass in &self.passes {
+                    if (pass.run)(&mut program.funcs[f]) {
+                        changed = true;
+                        verify(program).map_err(|e| match e {
+                            Error::VerifyError(errors) => {
+                                Error::VerifyError(errors.into_iter().map(|e| format!("{} 之后: {}", pass.name, e)).collect())
+                            },
+                            e => e,
+                        })?;
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+            program.funcs[f].compact();
+        }
+        Ok(())
+    }
+}
+
+// 值的类型。数（整数、布尔值和浮点数）参与运算不会出现类型错误；参数、全局变量和调用的结果可能是 null 等其他类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    Int,
+    Bool,
+    Num,            // 不确定是哪一种数，例如操作数不确定的运算的结果（运算没有出错，结果就是数）
+    Unknown,
+}
+
+// 每个值的类型；先假定 phi 的类型与来源一致，不一致时再改为 Num 或 Unknown，直到不再变化
+pub fn types(func: &Func) -> Vec<Ty> {
+    let mut ty: Vec<Option<Ty>> = vec![None; func.insts.len()];
+    let order = func.rpo();
+    loop {
+        let mut changed = false;
+        for b in &order {
+            for v in &func.blocks[b.0].insts {
+                let exact = |x: &Value| ty[x.0].is_none_or(|t| matches!(t, Ty::Int | Ty::Bool));
+                let new = match &func.insts[v.0] {
+                    Inst::Int(_) | Inst::GetInt => Ty::Int,
+                    Inst::Bool(_) => Ty::Bool,
+                    Inst::Unary(UnOp::Not, _) => Ty::Bool,
+                    Inst::Unary(UnOp::Neg, a) => if exact(a) { Ty::Int } else { Ty::Num },
+                    Inst::Binary(BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod, a, b) => {
+                        if exact(a) && exact(b) { Ty::Int } else { Ty::Num }
+                    },
+                    Inst::Binary(BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne, ..) => Ty::Bool,
+                    Inst::Phi(incoming) => {
+                        let mut join = None;
+                        for (_, x) in incoming {
+                            join = match (join, ty[x.0]) {
+                                (j, None) => j,
+                                (None, t) => t,
+                                (Some(j), Some(t)) if j == t => Some(j),
+                                (Some(Ty::Unknown), _) | (_, Some(Ty::Unknown)) => Some(Ty::Unknown),
+                                _ => Some(Ty::Num),
+                            };
+                        }
+                        match join {
+                            Some(t) => t,
+                            None => continue,
+                        }
+                    },
+                    _ => Ty::Unknown,
+                };
+                if ty[v.0] != Some(new) {
+                    ty[v.0] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    ty.into_iter().map(|t| t.unwrap_or(Ty::Unknown)).collect()
+}
+
+// 删除 v 或者提前计算 v 是否可能改变程序的行为，ty 由 types 得到。
+// 运算的操作数都是数时不会出错，除法和取余除外（除数可能为 0）
+pub fn has_effect(func: &Func, ty: &[Ty], v: Value) -> bool {
+    let known = |x: &Value| ty.get(x.0).is_some_and(|t| *t != Ty::Unknown);
+    match &func.insts[v.0] {
+        inst if !inst.has_effect() => false,
+        Inst::Binary(BinOp::Div | BinOp::Mod, ..) => true,
+        Inst::Unary(_, a) => !known(a),
+        Inst::Binary(_, a, b) => !known(a) || !known(b),
+        _ => true,
+    }
+}
+
+// 值的常数，不是常数时为 None
+pub fn constant(func: &Func, v: Value) -> Option<crate::vm::value::Value> {
+    use crate::vm::value::Value as V;
+    match func.insts[v.0] {
+        Inst::Int(k) => Some(V::Int(k)),
+        Inst::Bool(b) => Some(V::Bool(b)),
+        Inst::Null => Some(V::Null),
+        _ => None,
+    }
+}
+
+// 常数对应的指令
+pub fn const_inst(value: crate::vm::value::Value) -> Option<Inst> {
+    use crate::vm::value::Value as V;
+    match value {
+        V::Int(k) => Some(Inst::Int(k)),
+        V::Bool(b) => Some(Inst::Bool(b)),
+        V::Null => Some(Inst::Null),
+        _ => None,
+    }
+}
+
+// 把块中的 phi 移到最前面，其他指令的顺序不变；phi 被改写为常数之后使用
+pub fn phis_first(func: &mut Func) {
+    for block in &mut func.blocks {
+        let (phis, rest): (Vec<Value>, Vec<Value>) = block.insts.iter().partition(|v| matches!(func.insts[v.0], Inst::Phi(_)));
+        block.insts = phis.into_iter().chain(rest).collect();
+    }
+}
@@ -0,0 +1,59 @@
+/*!
+ * 复制传播。SSA 中的复制就是来源都相同的 phi，用来源代替它；另外在块内把读内存转发为已知的值：
+ * - 写入槽位之后读同一个槽位，得到的是写入的值；再次读同一个槽位，得到的是上一次读到的值；
+ * - 全局变量相同，但调用用户函数之后全局变量可能已经改变，之前记录的值作废（原生函数不访问全局变量）。
+ *
+ * 只在块内进行：同一块中的指令依次执行，抛出异常时直接离开块，之后的指令不会执行。
+ */
+
+use std::collections::HashMap;
+
+use crate::ir::{Func, Inst, Value};
+
+pub fn run(func: &mut Func) -> bool {
+    let mut changed = func.remove_trivial_phis();
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    for block in &mut func.blocks {
+        let mut slots: HashMap<usize, Value> = HashMap::new();
+        let mut globals: HashMap<usize, Value> = HashMap::new();
+        block.insts.retain(|v| {
+            let known = match func.insts[v.0] {
+                Inst::Store(s, x) => {
+                    slots.insert(s, x);
+                    None
+                },
+                Inst::StoreGlobal(g, x) => {
+                    globals.insert(g, x);
+                    None
+                },
+                Inst::Load(s) => {
+                    let known = slots.get(&s).copied();
+                    slots.entry(s).or_insert(*v);
+                    known
+                },
+                Inst::LoadGlobal(g) => {
+                    let known = globals.get(&g).copied();
+                    globals.entry(g).or_insert(*v);
+                    known
+                },
+                Inst::Call(..) => {
+                    globals.clear();
+                    None
+                },
+                _ => None,
+            };
+            match known {
+                Some(x) => {
+                    replace.insert(*v, x);
+                    false
+                },
+                None => true,
+            }
+        });
+    }
+    if !replace.is_empty() {
+        func.replace_uses(&replace);
+        changed = true;
+    }
+    changed
+}
@@ -0,0 +1,162 @@
+/*!
+ * 强度削减：
+ * - 代数化简：x + 0、x - 0、x * 1、x / 1 就是 x，x * 0、x % 1、x - x 是 0，x * -1、x / -1、0 - x 换成取负；
+ * - 归纳变量：循环头的 i = phi [前置块: init, 回边: i + c]（c 为常数）时，循环中的 i * k（k 为常数）
+ *   换成新的归纳变量 j = phi [前置块: init * k, 回边: j + c * k]，每次迭代的乘法变成加法。
+ *   整数运算都是回绕的，所以 j 总是等于 i * k。
+ *
+ * 布尔值参与运算时按 0/1 处理，结果是整数，所以化简为 x 要求 x 是整数，其余的化简要求 x 是整数或布尔值（类型见 opt::types）。
+ */
+
+use std::collections::HashMap;
+
+use super::{constant, types, Ty};
+use super::loops::{find_loops, Loop};
+use crate::ast::Span;
+use crate::ir::{BinOp, BlockId, Func, Inst, UnOp, Value};
+use crate::vm::value::Value as V;
+
+pub fn run(func: &mut Func) -> bool {
+    let mut changed = simplify(func);
+    changed |= induction(func);
+    changed
+}
+
+// 化简的结果
+enum Rewrite {
+    Copy(Value),
+    Zero,
+    Neg(Value),
+}
+
+fn simplify(func: &mut Func) -> bool {
+    let ty = types(func);
+    let int = |x: Value| ty[x.0] == Ty::Int;
+    let num = |x: Value| matches!(ty[x.0], Ty::Int | Ty::Bool);
+    let k = |x: Value| match constant(func, x) {
+        Some(V::Int(k)) => Some(k),
+        _ => None,
+    };
+    let mut rewrites = Vec::new();
+    for block in &func.blocks {
+        for v in &block.insts {
+            let Inst::Binary(op, a, b) = func.insts[v.0] else {
+                continue;
+            };
+            let rewrite = match (op, k(a), k(b)) {
+                (BinOp::Add, _, Some(0)) | (BinOp::Sub, _, Some(0)) | (BinOp::Mul, _, Some(1)) | (BinOp::Div, _, Some(1)) if int(a) => Rewrite::Copy(a),
+                (BinOp::Add, Some(0), _) | (BinOp::Mul, Some(1), _) if int(b) => Rewrite::Copy(b),
+                (BinOp::Mul, _, Some(0)) | (BinOp::Mod, _, Some(1 | -1)) if num(a) => Rewrite::Zero,
+                (BinOp::Mul, Some(0), _) if num(b) => Rewrite::Zero,
+                (BinOp::Sub, ..) if a == b && num(a) => Rewrite::Zero,
+                (BinOp::Mul, _, Some(-1)) | (BinOp::Div, _, Some(-1)) if num(a) => Rewrite::Neg(a),
+                (BinOp::Mul, Some(-1), _) | (BinOp::Sub, Some(0), _) if num(b) => Rewrite::Neg(b),
+                _ => continue,
+            };
+            rewrites.push((*v, rewrite));
+        }
+    }
+    let mut replace = HashMap::new();
+    for (v, rewrite) in &rewrites {
+        match rewrite {
+            Rewrite::Copy(x) => {
+                replace.insert(*v, *x);
+            },
+            Rewrite::Zero => func.insts[v.0] = Inst::Int(0),
+            Rewrite::Neg(x) => func.insts[v.0] = Inst::Unary(UnOp::Neg, *x),
+        }
+    }
+    if !replace.is_empty() {
+        for block in &mut func.blocks {
+            block.insts.retain(|v| !replace.contains_key(v));
+        }
+        func.replace_uses(&replace);
+    }
+    !rewrites.is_empty()
+}
+
+fn induction(func: &mut Func) -> bool {
+    let mut changed = false;
+    for lp in find_loops(func) {
+        let (Some(pre), &[latch]) = (lp.preheader, &lp.latches[..]) else {
+            continue;
+        };
+        let ty = types(func);
+        // 基本归纳变量：phi -> (init, c)
+        let mut ivs = HashMap::new();
+        for v in &func.blocks[lp.header.0].insts {
+            let Inst::Phi(incoming) = &func.insts[v.0] else {
+                break;
+            };
+            let (Some(&(_, init)), Some(&(_, next))) = (incoming.iter().find(|(p, _)| *p == pre), incoming.iter().find(|(p, _)| *p == latch)) else {
+                continue;
+            };
+            let step = match func.insts[next.0] {
+                Inst::Binary(BinOp::Add, a, c) | Inst::Binary(BinOp::Add, c, a) if a == *v => constant(func, c),
+                _ => None,
+            };
+            if let (Some(V::Int(c)), Ty::Int) = (step, ty[init.0]) {
+                ivs.insert(*v, (init, c));
+            }
+        }
+        if ivs.is_empty() {
+            continue;
+        }
+        // 循环中的 i * k；同一个 (i, k) 共用一个新的归纳变量
+        let mut derived: HashMap<(Value, i32), Value> = HashMap::new();
+        let mut replace = HashMap::new();
+        for b in func.rpo() {
+            if !lp.contains(b) {
+                continue;
+            }
+            for v in func.blocks[b.0].insts.clone() {
+                let Inst::Binary(BinOp::Mul, x, y) = func.insts[v.0] else {
+                    continue;
+                };
+                let (iv, k) = match (constant(func, x), constant(func, y)) {
+                    (_, Some(V::Int(k))) if ivs.contains_key(&x) => (x, k),
+                    (Some(V::Int(k)), _) if ivs.contains_key(&y) => (y, k),
+                    _ => continue,
+                };
+                let j = match derived.get(&(iv, k)) {
+                    Some(j) => *j,
+                    None => {
+                        let (init, c) = ivs[&iv];
+                        let j = new_iv(func, &lp, init, k, c.wrapping_mul(k), func.spans[v.0]);
+                        derived.insert((iv, k), j);
+                        j
+                    },
+                };
+                replace.insert(v, j);
+            }
+        }
+        if !replace.is_empty() {
+            for block in &mut func.blocks {
+                block.insts.retain(|v| !replace.contains_key(v));
+            }
+            func.replace_uses(&replace);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn push(func: &mut Func, block: BlockId, inst: Inst, span: Span) -> Value {
+    let v = func.new_inst(inst, span);
+    func.blocks[block.0].insts.push(v);
+    v
+}
+
+// 新的归纳变量 j = phi [pre: init * k, latch: j + step]，新的指令都属于被替换的乘法所在的语句
+fn new_iv(func: &mut Func, lp: &Loop, init: Value, k: i32, step: i32, span: Span) -> Value {
+    let (pre, latch) = (lp.preheader.unwrap(), lp.latches[0]);
+    let kv = push(func, pre, Inst::Int(k), span);
+    let start = push(func, pre, Inst::Binary(BinOp::Mul, init, kv), span);
+    // phi 和回边上的加法互相引用，先放一个空的 phi
+    let j = func.new_inst(Inst::Phi(Vec::new()), span);
+    func.blocks[lp.header.0].insts.insert(0, j);
+    let sv = push(func, latch, Inst::Int(step), span);
+    let next = push(func, latch, Inst::Binary(BinOp::Add, j, sv), span);
+    func.insts[j.0] = Inst::Phi(vec![(pre, start), (latch, next)]);
+    j
+}
@@ -0,0 +1,184 @@
+/*!
+ * 检查 IR 的结构和 SSA 性质，优化之后也用它确认没有破坏 IR。
+ *
+ * 检查的内容：
+ * - 每个值只在一个块中出现一次，操作数是定义值的指令，跳转目标、槽位、全局变量和函数的编号有效，调用的参数个数正确；
+ * - 入口块没有前驱，所有块都可以从入口到达，条件跳转和 try 的两个目标不同；
+ * - phi 在块的开头，来源块恰好是块的全部前驱；param 只在入口块；catch 恰好是每个 catch 块的第一条指令；
+ * - 值的定义支配它的使用：同一块中先定义后使用，phi 使用的值的定义支配对应的前驱块；
+ * - 每条指令都有源代码位置。
+ *
+ * 所有问题收集起来一起报告。
+ */
+
+use std::collections::HashSet;
+
+use crate::error::{Error, Result};
+use crate::native::Registry;
+
+use super::{dominates, BlockId, Func, Inst, Program, Term, Value};
+
+pub fn verify(program: &Program) -> Result<()> {
+    let natives = Registry::with_defaults();
+    let mut errors = Vec::new();
+    if program.init >= program.funcs.len() {
+        errors.push(format!("初始化函数 #{} 不存在", program.init));
+    }
+    if let Some(main) = program.main {
+        if main >= program.funcs.len() {
+            errors.push(format!("main 函数 #{} 不存在", main));
+        }
+    }
+    for func in &program.funcs {
+        let mut report = |msg: String| errors.push(format!("{}: {}", func.name, msg));
+        verify_func(program, &natives, func, &mut report);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::VerifyError(errors))
+    }
+}
+
+fn verify_func(program: &Program, natives: &Registry, func: &Func, report: &mut impl FnMut(String)) {
+    if func.blocks.is_empty() {
+        report(String::from("没有基本块"));
+        return;
+    }
+    if func.spans.len() != func.insts.len() {
+        report(format!("有 {} 条指令，但有 {} 个指令位置", func.insts.len(), func.spans.len()));
+    }
+    let nblocks = func.blocks.len();
+    // 每个值所在的块和在块中的位置
+    let mut place: Vec<Option<(BlockId, usize)>> = vec![None; func.insts.len()];
+    for (b, block) in func.blocks.iter().enumerate() {
+        for (i, v) in block.insts.iter().enumerate() {
+            match place.get(v.0) {
+                None => report(format!("bb{}: {} 不存在", b, v)),
+                Some(Some(_)) => report(format!("bb{}: {} 出现了多次", b, v)),
+                Some(None) => place[v.0] = Some((BlockId(b), i)),
+            }
+        }
+        for succ in block.term.succs() {
+            if succ.0 >= nblocks {
+                report(format!("bb{}: 跳转目标 {} 不存在", b, succ));
+            }
+        }
+        if let Term::Br(_, t, f) | Term::Try(t, f) = block.term {
+            if t == f {
+                report(format!("bb{}: 两个跳转目标相同", b));
+            }
+        }
+    }
+    if func.blocks.iter().any(|block| block.term.succs().iter().any(|succ| succ.0 >= nblocks)) {
+        return;
+    }
+
+    let preds = func.preds();
+    if !preds[0].is_empty() {
+        report(String::from("入口块有前驱"));
+    }
+    let idom = func.idoms();
+    for (b, d) in idom.iter().enumerate() {
+        if d.is_none() {
+            report(format!("bb{} 不可达", b));
+        }
+    }
+    let catches: HashSet<BlockId> = func.blocks.iter().filter_map(|block| match block.term {
+        Term::Try(_, catch) => Some(catch),
+        _ => None,
+    }).collect();
+
+    // 使用处 (块, 位置) 的值 v 是否已经定义，位置为块中指令的个数时表示终结指令
+    let defined_at = |v: Value, b: BlockId, i: usize| match place.get(v.0).copied().flatten() {
+        Some((db, di)) if db == b => di < i,
+        Some((db, _)) => idom[b.0].is_some() && dominates(&idom, db, b),
+        None => false,
+    };
+    let check_operand = |report: &mut dyn FnMut(String), v: Value, b: usize| {
+        match func.insts.get(v.0) {
+            None => report(format!("bb{}: 使用的 {} 不存在", b, v)),
+            Some(inst) if !inst.has_value() => report(format!("bb{}: 使用的 {} 没有值", b, v)),
+            Some(_) if place[v.0].is_none() => report(format!("bb{}: 使用的 {} 不在任何块中", b, v)),
+            Some(_) => return true,
+        }
+        false
+    };
+
+    for (b, block) in func.blocks.iter().enumerate() {
+        let id = BlockId(b);
+        let mut phis = true;
+        if catches.contains(&id) && !matches!(block.insts.first().and_then(|v| func.insts.get(v.0)), Some(Inst::Catch)) {
+            report(format!("bb{}: catch 块没有以 catch 开始", b));
+        }
+        for (i, v) in block.insts.iter().enumerate() {
+            let Some(inst) = func.insts.get(v.0) else {
+                continue;
+            };
+            if !matches!(inst, Inst::Phi(_)) {
+                phis = false;
+            }
+            match inst {
+                Inst::Phi(incoming) => {
+                    if !phis {
+                        report(format!("bb{}: {} 不在块的开头", b, v));
+                    }
+                    let mut from: Vec<BlockId> = incoming.iter().map(|(p, _)| *p).collect();
+                    let mut expect = preds[b].clone();
+                    from.sort();
+                    expect.sort();
+                    if from != expect {
+                        report(format!("bb{}: {} 的来源块与前驱不一致", b, v));
+                    }
+                    for (p, op) in incoming {
+                        if check_operand(report, *op, b) && p.0 < nblocks && idom[p.0].is_some() {
+                            let len = func.blocks[p.0].insts.len();
+                            if !defined_at(*op, *p, len + 1) {
+                                report(format!("bb{}: {} 使用的 {} 的定义不支配 {}", b, v, op, p));
+                            }
+                        }
+                    }
+                    continue;
+                },
+                Inst::Param(k) => {
+                    if b != 0 {
+                        report(format!("bb{}: {} 不在入口块", b, v));
+                    }
+                    if *k >= func.arity {
+                        report(format!("bb{}: {} 的参数编号超出范围", b, v));
+                    }
+                },
+                Inst::Catch if i != 0 || !catches.contains(&id) => {
+                    report(format!("bb{}: {} 不是 catch 块的第一条指令", b, v));
+                },
+                Inst::Load(s) | Inst::Store(s, _) if *s >= func.slots => {
+                    report(format!("bb{}: {} 的槽位超出范围", b, v));
+                },
+                Inst::LoadGlobal(g) | Inst::StoreGlobal(g, _) if *g >= program.globals.len() => {
+                    report(format!("bb{}: {} 的全局变量不存在", b, v));
+                },
+                Inst::Call(f, args) => match program.funcs.get(*f) {
+                    Some(callee) if callee.arity == args.len() => (),
+                    Some(_) => report(format!("bb{}: {} 的参数个数不正确", b, v)),
+                    None => report(format!("bb{}: {} 调用的函数不存在", b, v)),
+                },
+                Inst::CallNative(f, args) => match natives.get(*f) {
+                    Some(native) if native.arity() == args.len() => (),
+                    Some(_) => report(format!("bb{}: {} 的参数个数不正确", b, v)),
+                    None => report(format!("bb{}: {} 调用的原生函数不存在", b, v)),
+                },
+                _ => (),
+            }
+            for op in inst.operands() {
+                if check_operand(report, op, b) && !defined_at(op, id, i) {
+                    report(format!("bb{}: {} 使用的 {} 的定义不支配使用处", b, v, op));
+                }
+            }
+        }
+        for op in block.term.operands() {
+            if check_operand(report, op, b) && !defined_at(op, id, block.insts.len()) {
+                report(format!("bb{}: 终结指令使用的 {} 的定义不支配使用处", b, op));
+            }
+        }
+    }
+}
@@ -1,6 +1,15 @@
+use lalrpop_util::lalrpop_mod;
+
 pub mod error;
 pub mod ast;
 pub mod interpreter;
 pub mod vm;
+pub mod profile;
+pub mod native;
 
 pub mod bytecode_translation;
+pub mod ir;
+
+
+// 引用 lalrpop 生成的解析器
+lalrpop_mod!(pub cy);
@@ -1,19 +1,59 @@
-use cilly::ast::{Block, FuncDef, FuncFParam, FuncFParams};
-use cilly::bytecode_translation::translate::{translate_from, translate_to};
-use cilly::bytecode_translation::TransByteCode;
+use cilly::ast::{Block, FuncDef, FuncFParam, FuncFParams, Span};
+use cilly::bytecode_translation::{binary, peephole};
+use cilly::bytecode_translation::asm::{assemble, disassemble};
+use cilly::bytecode_translation::link::link;
+use cilly::bytecode_translation::register::translate_register;
+use cilly::bytecode_translation::translate::{module_from_text, module_to_text, translate_module};
 use cilly::error::{Error, Result};
 use cilly::interpreter::environment::Environment;
 use cilly::interpreter::Execute;
-use cilly::vm::VM;
-use lalrpop_util::lalrpop_mod;
-use std::env::args;
-use std::fs::{read_to_string, File};
+use cilly::ir;
+use cilly::ir::opt::{OptLevel, PassManager};
+use cilly::profile::Profiler;
+use cilly::vm::register::RegVM;
+use cilly::vm::trace::{TraceFormat, Tracer};
+use cilly::vm::module::Module;
+use cilly::vm::{Status, VM};
+use cilly::cy;
+use std::env::{args, Args};
+use std::fs::{read, read_to_string, File};
 use std::io::{self, Write};
 
-// 引用 lalrpop 生成的解析器
-lalrpop_mod!(pub cy);
-
 fn main() -> Result<()> {
+    match run() {
+        Err(Error::Uncaught(v, backtrace)) => {
+            eprintln!("uncaught exception: {}", v);
+            for frame in backtrace {
+                eprintln!("    at {}", frame);
+            }
+            std::process::exit(1);
+        },
+        Err(Error::VerifyError(errors)) => {
+            eprintln!("invalid bytecode:");
+            for e in errors {
+                eprintln!("    {}", e);
+            }
+            std::process::exit(1);
+        },
+        Err(Error::LinkError(errors)) => {
+            eprintln!("link failed:");
+            for e in errors {
+                eprintln!("    {}", e);
+            }
+            std::process::exit(1);
+        },
+        Err(Error::RuntimeError(e, backtrace)) => {
+            eprintln!("runtime error: {:?}", e);
+            for frame in backtrace {
+                eprintln!("    at {}", frame);
+            }
+            std::process::exit(1);
+        },
+        res => res,
+    }
+}
+
+fn run() -> Result<()> {
     // 解析命令行参数
     let mut args = args();
     args.next();
@@ -26,12 +66,14 @@ fn main() -> Result<()> {
         btype: None,
         funcfparams: Some(FuncFParams{params:vec![FuncFParam {ident: String::from("content"), btype: cilly::ast::BType::I32}]}),
         block: Block { items: vec![] },
+        span: Span::default(),
     };
     let getintfunc = FuncDef {
         ident: "getint".to_string(),
         btype: None,
         funcfparams: None,
         block: Block{ items: vec![] },
+        span: Span::default(),
     };
     let mut env = Environment::new();
     env.new_func("print", &printfunc)?;
@@ -44,7 +86,12 @@ fn main() -> Result<()> {
             let input = read_to_string(input)?;
             // 调用 lalrpop 生成的 parser 解析输入文件
             let ast = cy::CompUnitParser::new().parse(&input).unwrap();
+            let options = Options::parse(args)?;
+            if let Some(profiler) = options.profiler() {
+                env.set_profiler(profiler);
+            }
             ast.run(&mut env)?;
+            options.report(env.profiler())?;
         },
         "--translate" => {
             let filename = args.next().unwrap();
@@ -52,22 +99,120 @@ fn main() -> Result<()> {
             let input = read_to_string(&filename)?;
             // 调用 lalrpop 生成的 parser 解析输入文件
             let mut ast = cy::CompUnitParser::new().parse(&input).unwrap();
-            let res = ast.translate_byte(&mut cilly::bytecode_translation::environment::Environment::new(), 0)?;
-            let res = translate_to(res);
+            // 默认进行窥孔优化并输出二进制格式；--no-peephole 关闭优化，--text 输出文本形式（用于调试），
+            // --object 生成可重定位的目标模块，由 link 合并；--ir 经过 SSA 中间表示生成代码，
+            // -O0/-O1/-O2 经过中间表示并按级别优化，同时报告优化前后的指令数
+            let (mut peephole, mut text, mut object) = (true, false, false);
+            let (mut via_ir, mut level) = (false, None);
+            for arg in args {
+                match arg.as_str() {
+                    "--no-peephole" => peephole = false,
+                    "--text" => text = true,
+                    "--object" => object = true,
+                    "--ir" => via_ir = true,
+                    arg => match OptLevel::parse(arg) {
+                        Some(l) => level = Some(l),
+                        None => return Err(Error::UnExpectArgs),
+                    },
+                }
+            }
+            let via_ir = via_ir || level.is_some();
+            if text && object {
+                return Err(Error::TranslateError(String::from("文本形式不能保存目标模块的链接信息")));
+            }
+            if via_ir && object {
+                return Err(Error::TranslateError(String::from("中间表示不支持生成目标模块")));
+            }
+            let mut env = cilly::bytecode_translation::environment::Environment::new().with_source(&filename, &input);
+            if object {
+                env = env.as_object();
+            }
+            let finish = |module| if peephole { peephole::optimize(module) } else { module };
+            let res = if via_ir {
+                let mut program = ir::build::build(&ast)?;
+                ir::verify::verify(&program)?;
+                match level {
+                    Some(level) => {
+                        let before = finish(ir::lower::lower(&program, &env)).code.len();
+                        PassManager::new(level).run(&mut program)?;
+                        let res = finish(ir::lower::lower(&program, &env));
+                        println!("instructions: {} -> {}", before, res.code.len());
+                        res
+                    },
+                    None => finish(ir::lower::lower(&program, &env)),
+                }
+            } else {
+                finish(translate_module(&mut ast, &mut env)?)
+            };
             let filename = filename.replace(".cil", ".cby");
             let mut file = File::create(&filename)?;
-            for i in &res {
-                file.write(format!("{} ",i).as_bytes()).unwrap();
+            if text {
+                file.write_all(module_to_text(&res).as_bytes())?;
+            } else {
+                file.write_all(&binary::encode(&res))?;
             }
             println!("{} is created !", filename);
         },
+        "--dump-ir" => {
+            // 可以加 -O1/-O2 查看优化之后的中间表示
+            let input = read_to_string(args.next().ok_or(Error::UnExpectArgs)?)?;
+            let level = match args.next() {
+                Some(arg) => OptLevel::parse(&arg).ok_or(Error::UnExpectArgs)?,
+                None => OptLevel::O0,
+            };
+            let ast = cy::CompUnitParser::new().parse(&input).unwrap();
+            let mut program = ir::build::build(&ast)?;
+            ir::verify::verify(&program)?;
+            PassManager::new(level).run(&mut program)?;
+            print!("{}", program);
+        },
         "--vmrun" => {
+            let input = args.next().ok_or(Error::UnExpectArgs)?;
+            let module = load_module(&input)?;
+            let options = Options::parse(args)?;
+            run_vm(VM::new(module), &options)?;
+        }
+        "--disasm" | "disasm" => {
+            let input = args.next().ok_or(Error::UnExpectArgs)?;
+            let module = load_module(&input)?;
+            print!("{}", disassemble(&module));
+        }
+        "--link" | "link" => {
+            // link a.cby b.cby ... [-o out.cby]
+            let mut objects = Vec::new();
+            let mut output = String::from("out.cby");
+            while let Some(arg) = args.next() {
+                if arg == "-o" {
+                    output = args.next().ok_or(Error::UnExpectArgs)?;
+                } else {
+                    let module = load_module(&arg)?;
+                    objects.push((arg, module));
+                }
+            }
+            let module = link(&objects)?;
+            File::create(&output)?.write_all(&binary::encode(&module))?;
+            println!("{} is created !", output);
+        }
+        "--asm" | "asm" => {
+            let filename = args.next().ok_or(Error::UnExpectArgs)?;
+            let module = assemble(&read_to_string(&filename)?)?;
+            let filename = filename.replace(".cas", ".cby");
+            File::create(&filename)?.write_all(&binary::encode(&module))?;
+            println!("{} is created !", filename);
+        }
+        "--resume" => {
+            let input = args.next().ok_or(Error::UnExpectArgs)?;
+            let vm = VM::load(&read(input)?)?;
+            let options = Options::parse(args)?;
+            run_vm(vm, &options)?;
+        }
+        "--regrun" => {
             let input = args.next().unwrap();
             // 读取输入文件
             let input = read_to_string(input)?;
-            let code: Vec<usize> = input.split_whitespace().map(|s| s.parse().unwrap()).collect();
-            let code = translate_from(code);
-            let mut vm = VM::new(code);
+            let ast = cy::CompUnitParser::new().parse(&input).unwrap();
+            let program = translate_register(&ast)?;
+            let mut vm = RegVM::new(program);
             vm.run()?;
         }
         _ => return Err(Error::UnExpectArgs),
@@ -85,6 +230,156 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+// 读取字节码文件，二进制和文本形式都可以
+fn load_module(filename: &str) -> Result<Module> {
+    let input = read(filename)?;
+    if binary::is_binary(&input) {
+        binary::decode(&input)
+    } else {
+        let input = String::from_utf8(input).map_err(|_| Error::BytecodeError(String::from("不是字节码文件")))?;
+        module_from_text(&input)
+    }
+}
+
+// 输入文件之后的可选参数
+#[derive(Debug, Default)]
+struct Options {
+    trace: bool,
+    trace_out: Option<String>,
+    trace_json: bool,
+    trace_fn: Option<String>,     // 函数名或函数表中的编号
+    trace_pc: Option<(usize, usize)>,
+    profile: bool,
+    profile_collapsed: Option<String>,
+    heap_limit: Option<usize>,
+    gc_stats: bool,
+    threaded: bool,
+    fuel: Option<u64>,
+    snapshot: Option<String>,
+    snapshot_at_getint: bool,
+}
+
+impl Options {
+    fn parse(mut args: Args) -> Result<Self> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--trace" => options.trace = true,
+                "--trace-json" => {
+                    options.trace = true;
+                    options.trace_json = true;
+                },
+                "--trace-out" => {
+                    options.trace = true;
+                    options.trace_out = Some(args.next().ok_or(Error::UnExpectArgs)?);
+                },
+                "--trace-fn" => {
+                    options.trace = true;
+                    options.trace_fn = Some(args.next().ok_or(Error::UnExpectArgs)?);
+                },
+                "--trace-pc" => {
+                    // lo..hi
+                    options.trace = true;
+                    let range = args.next().ok_or(Error::UnExpectArgs)?;
+                    let (lo, hi) = range.split_once("..").ok_or(Error::UnExpectArgs)?;
+                    options.trace_pc = Some((parse_num(Some(lo.to_string()))?, parse_num(Some(hi.to_string()))?));
+                },
+                "--profile" => options.profile = true,
+                "--profile-collapsed" => {
+                    options.profile = true;
+                    options.profile_collapsed = Some(args.next().ok_or(Error::UnExpectArgs)?);
+                },
+                "--heap-limit" => options.heap_limit = Some(parse_num(args.next())?),
+                "--gc-stats" => options.gc_stats = true,
+                "--threaded" => options.threaded = true,
+                "--fuel" => options.fuel = Some(parse_num(args.next())? as u64),
+                "--snapshot" => options.snapshot = Some(args.next().ok_or(Error::UnExpectArgs)?),
+                "--snapshot-at-getint" => options.snapshot_at_getint = true,
+                _ => return Err(Error::UnExpectArgs),
+            }
+        }
+        Ok(options)
+    }
+    fn tracer(&self, module: &Module) -> Result<Option<Tracer>> {
+        if !self.trace {
+            return Ok(None);
+        }
+        let out: Box<dyn Write> = match &self.trace_out {
+            Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stderr()),
+        };
+        let format = if self.trace_json { TraceFormat::Json } else { TraceFormat::Text };
+        let mut tracer = Tracer::new(out, format);
+        if let Some(func) = &self.trace_fn {
+            let index = func.parse().ok().or_else(|| module.func_index(func))
+                .ok_or(Error::CallError(format!("func {} is not existed !", func)))?;
+            tracer = tracer.with_func(index);
+        }
+        if let Some((lo, hi)) = self.trace_pc {
+            tracer = tracer.with_pc_range(lo, hi);
+        }
+        Ok(Some(tracer))
+    }
+    fn profiler(&self) -> Option<Profiler> {
+        if self.profile {
+            Some(Profiler::new("<global>"))
+        } else {
+            None
+        }
+    }
+    // 输出性能分析结果：表格打印到 stderr，collapsed-stack 写入文件
+    fn report(&self, profiler: Option<&Profiler>) -> Result<()> {
+        if let Some(profiler) = profiler {
+            profiler.write_table(&mut io::stderr())?;
+            if let Some(path) = &self.profile_collapsed {
+                let mut file = io::BufWriter::new(File::create(path)?);
+                profiler.write_collapsed(&mut file)?;
+                file.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run_vm(mut vm: VM, options: &Options) -> Result<()> {
+    vm.verify()?;
+    if let Some(tracer) = options.tracer(vm.module())? {
+        vm.set_tracer(tracer);
+    }
+    if let Some(profiler) = options.profiler() {
+        vm.set_profiler(profiler);
+    }
+    if let Some(limit) = options.heap_limit {
+        vm.set_heap_limit(limit);
+    }
+    if let Some(fuel) = options.fuel {
+        vm.set_fuel(fuel);
+    }
+    vm.set_pause_at_getint(options.snapshot_at_getint);
+    vm.set_threaded(options.threaded);
+    let status = vm.run()?;
+    // 暂停前的输出要先于提示信息写出
+    io::stdout().flush()?;
+    options.report(vm.profiler())?;
+    if options.gc_stats {
+        let stats = vm.gc_stats();
+        eprintln!("gc: allocations: {}  collections: {}  freed: {}  live objects: {}  live bytes: {}  peak bytes: {}",
+            stats.allocations, stats.collections, stats.freed, stats.live_objects, stats.live_bytes, stats.peak_bytes);
+    }
+    if status != Status::Finished {
+        let path = options.snapshot.as_deref().unwrap_or("vm.snap");
+        let mut file = io::BufWriter::new(File::create(path)?);
+        vm.save(&mut file)?;
+        file.flush()?;
+        eprintln!("paused ({:?}) after {} steps, snapshot saved to {}", status, vm.steps(), path);
+    }
+    Ok(())
+}
+
+fn parse_num(arg: Option<String>) -> Result<usize> {
+    arg.ok_or(Error::UnExpectArgs)?.parse().map_err(|_| Error::UnExpectArgs)
+}
+
 fn testcode1() -> &'static str {
     r#"
     var a: i32 = 10;
@@ -0,0 +1,149 @@
+/*!
+ * 宿主函数（原生函数）注册表：嵌入者用名字、参数类型和返回类型注册 Rust 函数，
+ * 字节码通过 CallNative(编号, 参数个数) 调用，解释器按名字查找同一个注册表。
+ * 用户定义的同名函数优先于原生函数；print 和 getint 仍然由编译器直接翻译为专门的指令。
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::{Error, Result};
+use crate::vm::value::Value;
+
+// 参数和返回值的类型，Any 不做检查
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeType {
+    Int,
+    Float,
+    Bool,
+    Any,
+}
+
+impl NativeType {
+    fn accepts(&self, v: &Value) -> bool {
+        matches!((self, v), (NativeType::Any, _) | (NativeType::Int, Value::Int(_))
+            | (NativeType::Float, Value::Float(_)) | (NativeType::Bool, Value::Bool(_)))
+    }
+}
+
+pub type NativeFn = Box<dyn FnMut(&[Value]) -> Result<Option<Value>>>;
+// 带状态的原生函数，第一个参数为函数的状态
+pub type StatefulFn = Box<dyn FnMut(&mut [Value], &[Value]) -> Result<Option<Value>>>;
+
+pub struct Native {
+    pub name: String,
+    pub params: Vec<NativeType>,
+    pub ret: Option<NativeType>,    // None 表示没有返回值
+    // 状态保存在注册表中而不是闭包中，虚拟机的快照会一并保存
+    state: Vec<Value>,
+    func: StatefulFn,
+}
+
+impl Native {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Native").field("name", &self.name).field("params", &self.params).field("ret", &self.ret)
+            .field("state", &self.state).finish()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    natives: Vec<Native>,
+    index: HashMap<String, usize>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // 带有默认原生函数的注册表，编号依注册顺序为 abs, min, max, sqrt, rand
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        let int = |v: &Value| match v {
+            Value::Int(v) => *v,
+            _ => 0,
+        };
+        registry.register("abs", vec![NativeType::Int], Some(NativeType::Int),
+            Box::new(move |args| Ok(Some(Value::Int(int(&args[0]).wrapping_abs()))))).unwrap();
+        registry.register("min", vec![NativeType::Int, NativeType::Int], Some(NativeType::Int),
+            Box::new(move |args| Ok(Some(Value::Int(int(&args[0]).min(int(&args[1]))))))).unwrap();
+        registry.register("max", vec![NativeType::Int, NativeType::Int], Some(NativeType::Int),
+            Box::new(move |args| Ok(Some(Value::Int(int(&args[0]).max(int(&args[1]))))))).unwrap();
+        // 整数平方根，向下取整
+        registry.register("sqrt", vec![NativeType::Int], Some(NativeType::Int), Box::new(move |args| {
+            let v = int(&args[0]);
+            if v < 0 {
+                return Err(Error::CallError(format!("sqrt 的参数不能为负数: {}", v)));
+            }
+            Ok(Some(Value::Int((v as f64).sqrt() as i32)))
+        })).unwrap();
+        // xorshift 伪随机数，种子固定，同一程序每次运行的结果相同
+        registry.register_stateful("rand", vec![], Some(NativeType::Int), vec![Value::Int(2463534242u32 as i32)], Box::new(move |state, _| {
+            let mut x = int(&state[0]) as u32;
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            state[0] = Value::Int(x as i32);
+            Ok(Some(Value::Int((x >> 1) as i32)))
+        })).unwrap();
+        registry
+    }
+    // 返回函数的编号；闭包中的状态不会保存到快照中，需要保存的状态用 register_stateful 注册
+    pub fn register(&mut self, name: &str, params: Vec<NativeType>, ret: Option<NativeType>, mut func: NativeFn) -> Result<usize> {
+        self.register_stateful(name, params, ret, Vec::new(), Box::new(move |_, args| func(args)))
+    }
+    // state 为状态的初始值，每次调用时传给 func
+    pub fn register_stateful(&mut self, name: &str, params: Vec<NativeType>, ret: Option<NativeType>, state: Vec<Value>, func: StatefulFn) -> Result<usize> {
+        if self.index.contains_key(name) {
+            return Err(Error::DuplicatedDef);
+        }
+        self.natives.push(Native { name: name.to_string(), params, ret, state, func });
+        self.index.insert(name.to_string(), self.natives.len() - 1);
+        Ok(self.natives.len() - 1)
+    }
+    pub fn lookup(&self, name: &str) -> Option<(usize, &Native)> {
+        self.index.get(name).map(|i| (*i, &self.natives[*i]))
+    }
+    pub fn get(&self, index: usize) -> Option<&Native> {
+        self.natives.get(index)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Native)> {
+        self.natives.iter().enumerate()
+    }
+    pub fn len(&self) -> usize {
+        self.natives.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.natives.is_empty()
+    }
+    pub fn state(&self, index: usize) -> &[Value] {
+        &self.natives[index].state
+    }
+    pub fn set_state(&mut self, index: usize, state: Vec<Value>) {
+        self.natives[index].state = state;
+    }
+    // 检查参数个数和类型后调用
+    pub fn call(&mut self, index: usize, args: &[Value]) -> Result<Option<Value>> {
+        let native = self.natives.get_mut(index).ok_or(Error::CallError(format!("原生函数 #{} 不存在", index)))?;
+        if args.len() != native.params.len() {
+            return Err(Error::CallError(format!("{} 需要 {} 个参数，实际为 {} 个", native.name, native.params.len(), args.len())));
+        }
+        for (i, (ty, arg)) in native.params.iter().zip(args).enumerate() {
+            if !ty.accepts(arg) {
+                return Err(Error::TypeError(format!("{} 的第 {} 个参数类型应为 {:?}，实际为 {}", native.name, i + 1, ty, arg.type_name())));
+            }
+        }
+        let ret = (native.func)(&mut native.state, args)?;
+        match (native.ret, &ret) {
+            (None, None) => Ok(ret),
+            (Some(ty), Some(v)) if ty.accepts(v) => Ok(ret),
+            _ => Err(Error::TypeError(format!("{} 的返回值与声明的类型不符", native.name))),
+        }
+    }
+}
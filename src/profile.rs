@@ -0,0 +1,174 @@
+/*!
+ * 函数级性能分析：统计每个函数的调用次数、执行的指令数、耗时，以及指令直方图。
+ * 虚拟机和解释器共用，结果可以输出为排序后的表格或 flamegraph 使用的 collapsed-stack 格式。
+ */
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct FuncStat {
+    calls: u64,
+    active: usize,          // 当前处于调用栈中的次数（递归时只统计最外层的耗时）
+    entered: Option<Instant>,
+    time: Duration,
+}
+
+// 调用树上的一个结点，对应一条调用路径
+#[derive(Debug)]
+struct Node {
+    func: usize,
+    parent: usize,
+    children: HashMap<usize, usize>,
+    count: u64,             // 在这条调用路径上（作为栈顶）执行的指令数
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    start: Instant,
+    wall: Option<Duration>,
+    names: Vec<String>,
+    name_ids: HashMap<String, usize>,
+    funcs: Vec<FuncStat>,
+    histogram: HashMap<&'static str, u64>,
+    nodes: Vec<Node>,
+    cur: usize,
+}
+
+impl Profiler {
+    // root: 最外层（不在任何函数中）代码的名字
+    pub fn new(root: &str) -> Self {
+        let mut profiler = Self {
+            start: Instant::now(),
+            wall: None,
+            names: Vec::new(),
+            name_ids: HashMap::new(),
+            funcs: Vec::new(),
+            histogram: HashMap::new(),
+            nodes: Vec::new(),
+            cur: 0,
+        };
+        let root = profiler.func_id(root);
+        profiler.nodes.push(Node { func: root, parent: 0, children: HashMap::new(), count: 0 });
+        profiler.funcs[root].calls = 1;
+        profiler
+    }
+    fn func_id(&mut self, name: &str) -> usize {
+        if let Some(id) = self.name_ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len();
+        self.names.push(name.to_string());
+        self.name_ids.insert(name.to_string(), id);
+        self.funcs.push(FuncStat::default());
+        id
+    }
+    // 进入名为 name 的函数
+    pub fn enter(&mut self, name: &str) {
+        let id = self.func_id(name);
+        self.enter_id(id);
+    }
+    fn enter_id(&mut self, id: usize) {
+        let next = match self.nodes[self.cur].children.get(&id) {
+            Some(next) => *next,
+            None => {
+                let next = self.nodes.len();
+                self.nodes.push(Node { func: id, parent: self.cur, children: HashMap::new(), count: 0 });
+                self.nodes[self.cur].children.insert(id, next);
+                next
+            }
+        };
+        self.cur = next;
+        let stat = &mut self.funcs[id];
+        stat.calls += 1;
+        if stat.active == 0 {
+            stat.entered = Some(Instant::now());
+        }
+        stat.active += 1;
+    }
+    // 从当前函数返回
+    pub fn leave(&mut self) {
+        if self.cur == 0 {
+            return;
+        }
+        let stat = &mut self.funcs[self.nodes[self.cur].func];
+        stat.active -= 1;
+        if stat.active == 0 {
+            if let Some(entered) = stat.entered.take() {
+                stat.time += entered.elapsed();
+            }
+        }
+        self.cur = self.nodes[self.cur].parent;
+    }
+    // 执行了一条指令（或一条语句），kind 为其种类
+    pub fn step(&mut self, kind: &'static str) {
+        self.nodes[self.cur].count += 1;
+        *self.histogram.entry(kind).or_insert(0) += 1;
+    }
+    // 程序结束，记录总耗时
+    pub fn finish(&mut self) {
+        while self.cur != 0 {
+            self.leave();
+        }
+        let wall = self.start.elapsed();
+        self.funcs[0].time = wall;
+        self.wall = Some(wall);
+    }
+    fn path(&self, mut node: usize) -> Vec<usize> {
+        let mut path = vec![self.nodes[node].func];
+        while node != 0 {
+            node = self.nodes[node].parent;
+            path.push(self.nodes[node].func);
+        }
+        path.reverse();
+        path
+    }
+    pub fn write_table(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut self_count = vec![0; self.names.len()];
+        let mut total_count = vec![0; self.names.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            self_count[node.func] += node.count;
+            let mut path = self.path(i);
+            path.sort();
+            path.dedup();
+            for func in path {
+                total_count[func] += node.count;
+            }
+        }
+        let totle: u64 = self_count.iter().sum();
+        let wall = self.wall.unwrap_or_else(|| self.start.elapsed());
+
+        let mut order: Vec<usize> = (0..self.names.len()).collect();
+        order.sort_by(|a, b| self_count[*b].cmp(&self_count[*a]).then(a.cmp(b)));
+        writeln!(out, "{:<24} {:>10} {:>12} {:>8} {:>12} {:>12}", "function", "calls", "self", "self%", "total", "time(ms)")?;
+        for id in order {
+            let percent = if totle == 0 { 0.0 } else { self_count[id] as f64 * 100.0 / totle as f64 };
+            writeln!(out, "{:<24} {:>10} {:>12} {:>7.2}% {:>12} {:>12.3}",
+                self.names[id], self.funcs[id].calls, self_count[id], percent, total_count[id],
+                self.funcs[id].time.as_secs_f64() * 1000.0)?;
+        }
+
+        let mut kinds: Vec<(&&'static str, &u64)> = self.histogram.iter().collect();
+        kinds.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(out)?;
+        writeln!(out, "{:<24} {:>12}", "kind", "count")?;
+        for (kind, count) in kinds {
+            writeln!(out, "{:<24} {:>12}", kind, count)?;
+        }
+        writeln!(out)?;
+        writeln!(out, "executed: {}  wall time: {:.3}ms", totle, wall.as_secs_f64() * 1000.0)?;
+        Ok(())
+    }
+    // flamegraph.pl / inferno 使用的 collapsed-stack 格式: `main;fact;fact 42`
+    pub fn write_collapsed(&self, out: &mut dyn Write) -> io::Result<()> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.count == 0 {
+                continue;
+            }
+            let path: Vec<&str> = self.path(i).into_iter().map(|f| self.names[f].as_str()).collect();
+            writeln!(out, "{} {}", path.join(";"), node.count)?;
+        }
+        Ok(())
+    }
+}
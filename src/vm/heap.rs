@@ -0,0 +1,275 @@
+/*!
+ * 虚拟机的堆：保存数组、结构体等非标量数据，栈上通过 Value::Ref 引用。
+ * 使用标记-清除回收，根为操作数栈、所有栈帧的局部变量和全局变量。
+ */
+
+use std::mem::size_of;
+
+use crate::error::{Error, Result};
+
+use super::snapshot::{Reader, Writer};
+use super::value::Value;
+
+// 第一次回收前允许分配的字节数，之后为存活字节数的两倍
+const INITIAL_GC_THRESHOLD: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub enum Object {
+    Array(Vec<Value>),
+    Struct(Vec<Value>),
+}
+
+impl Object {
+    fn size(&self) -> usize {
+        let len = match self {
+            Object::Array(items) => items.len(),
+            Object::Struct(fields) => fields.len(),
+        };
+        size_of::<Slot>() + len * size_of::<Value>()
+    }
+    fn children(&self) -> &[Value] {
+        match self {
+            Object::Array(items) => items,
+            Object::Struct(fields) => fields,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    obj: Option<Object>,
+    marked: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    pub allocations: u64,       // 累计分配的对象个数
+    pub collections: u64,       // 回收次数
+    pub freed: u64,             // 累计回收的对象个数
+    pub live_objects: usize,    // 当前存活的对象个数
+    pub live_bytes: usize,      // 当前占用的字节数
+    pub peak_bytes: usize,      // 占用字节数的峰值
+}
+
+#[derive(Debug, Clone)]
+pub struct Heap {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    limit: Option<usize>,
+    next_gc: usize,
+    stats: GcStats,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            limit: None,
+            next_gc: INITIAL_GC_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+    // 堆大小上限（字节），超过时分配失败
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = Some(limit);
+        self.next_gc = self.next_gc.min(limit);
+    }
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+    // 分配 bytes 字节之前是否应当先回收
+    pub fn should_collect(&self, bytes: usize) -> bool {
+        self.stats.live_bytes + bytes > self.next_gc
+    }
+    pub fn object_size(len: usize) -> usize {
+        size_of::<Slot>() + len * size_of::<Value>()
+    }
+    pub fn alloc(&mut self, obj: Object) -> Result<Value> {
+        let size = obj.size();
+        if let Some(limit) = self.limit {
+            if self.stats.live_bytes + size > limit {
+                return Err(Error::OutOfMemory(limit));
+            }
+        }
+        self.stats.allocations += 1;
+        self.stats.live_objects += 1;
+        self.stats.live_bytes += size;
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.live_bytes);
+        let slot = Slot { obj: Some(obj), marked: false };
+        let r = match self.free.pop() {
+            Some(r) => {
+                self.slots[r] = slot;
+                r
+            },
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            },
+        };
+        Ok(Value::Ref(r))
+    }
+    pub fn get(&self, v: Value) -> Result<&Object> {
+        match v {
+            Value::Ref(r) => self.slots.get(r).and_then(|slot| slot.obj.as_ref())
+                .ok_or(Error::VMError(format!("非法引用 #{}", r))),
+            _ => Err(Error::TypeError(format!("需要引用类型，实际为 {}", v.type_name()))),
+        }
+    }
+    pub fn get_mut(&mut self, v: Value) -> Result<&mut Object> {
+        match v {
+            Value::Ref(r) => self.slots.get_mut(r).and_then(|slot| slot.obj.as_mut())
+                .ok_or(Error::VMError(format!("非法引用 #{}", r))),
+            _ => Err(Error::TypeError(format!("需要引用类型，实际为 {}", v.type_name()))),
+        }
+    }
+    // 标记-清除，roots 为所有可能持有引用的位置
+    pub fn collect<'a>(&mut self, roots: impl Iterator<Item = &'a Value>) {
+        let mut work: Vec<usize> = roots.filter_map(|v| match v {
+            Value::Ref(r) => Some(*r),
+            _ => None,
+        }).collect();
+        while let Some(r) = work.pop() {
+            let slot = &mut self.slots[r];
+            if slot.marked {
+                continue;
+            }
+            slot.marked = true;
+            if let Some(obj) = &slot.obj {
+                for child in obj.children() {
+                    if let Value::Ref(c) = child {
+                        work.push(*c);
+                    }
+                }
+            }
+        }
+        for (r, slot) in self.slots.iter_mut().enumerate() {
+            if slot.marked {
+                slot.marked = false;
+            } else if let Some(obj) = slot.obj.take() {
+                self.stats.freed += 1;
+                self.stats.live_objects -= 1;
+                self.stats.live_bytes -= obj.size();
+                self.free.push(r);
+            }
+        }
+        self.stats.collections += 1;
+        self.next_gc = (self.stats.live_bytes * 2).max(INITIAL_GC_THRESHOLD);
+        if let Some(limit) = self.limit {
+            self.next_gc = self.next_gc.min(limit);
+        }
+    }
+    pub(super) fn save(&self, w: &mut Writer) {
+        w.usize(self.slots.len());
+        for slot in &self.slots {
+            match &slot.obj {
+                None => w.u8(0),
+                Some(Object::Array(items)) => {
+                    w.u8(1);
+                    w.values(items);
+                },
+                Some(Object::Struct(fields)) => {
+                    w.u8(2);
+                    w.values(fields);
+                },
+            }
+        }
+        w.usize(self.free.len());
+        for r in &self.free {
+            w.usize(*r);
+        }
+        match self.limit {
+            Some(limit) => {
+                w.u8(1);
+                w.usize(limit);
+            },
+            None => w.u8(0),
+        }
+        w.usize(self.next_gc);
+        w.u64(self.stats.allocations);
+        w.u64(self.stats.collections);
+        w.u64(self.stats.freed);
+        w.usize(self.stats.live_objects);
+        w.usize(self.stats.live_bytes);
+        w.usize(self.stats.peak_bytes);
+    }
+    pub(super) fn load(r: &mut Reader) -> Result<Heap> {
+        let mut heap = Heap::new();
+        let len = r.usize()?;
+        for _ in 0..len {
+            let obj = match r.u8()? {
+                0 => None,
+                1 => Some(Object::Array(r.values()?)),
+                2 => Some(Object::Struct(r.values()?)),
+                tag => return Err(Error::SnapshotError(format!("未知的对象类型 {}", tag))),
+            };
+            heap.slots.push(Slot { obj, marked: false });
+        }
+        let len = r.usize()?;
+        for _ in 0..len {
+            heap.free.push(r.usize()?);
+        }
+        heap.limit = match r.u8()? {
+            0 => None,
+            _ => Some(r.usize()?),
+        };
+        heap.next_gc = r.usize()?;
+        heap.stats = GcStats {
+            allocations: r.u64()?,
+            collections: r.u64()?,
+            freed: r.u64()?,
+            live_objects: r.usize()?,
+            live_bytes: r.usize()?,
+            peak_bytes: r.usize()?,
+        };
+        heap.check()?;
+        Ok(heap)
+    }
+    // 文件中的数据不可信：空闲列表、统计和对象中的引用须与槽位一致，否则之后分配和回收时会越界
+    fn check(&self) -> Result<()> {
+        let mut free = vec![false; self.slots.len()];
+        for &r in &self.free {
+            if r >= self.slots.len() || self.slots[r].obj.is_some() || free[r] {
+                return Err(Error::SnapshotError(format!("空闲列表中的槽位 {} 无效", r)));
+            }
+            free[r] = true;
+        }
+        let live: Vec<&Object> = self.slots.iter().filter_map(|slot| slot.obj.as_ref()).collect();
+        if live.len() + self.free.len() != self.slots.len() {
+            return Err(Error::SnapshotError(String::from("空闲列表与空的槽位不一致")));
+        }
+        if live.len() != self.stats.live_objects || live.iter().map(|obj| obj.size()).sum::<usize>() != self.stats.live_bytes {
+            return Err(Error::SnapshotError(String::from("存活对象的统计与堆中的对象不一致")));
+        }
+        for obj in live {
+            self.check_refs(obj.children())?;
+        }
+        Ok(())
+    }
+    // values 中的引用都指向存活的对象
+    pub(super) fn check_refs(&self, values: &[Value]) -> Result<()> {
+        for v in values {
+            if let Value::Ref(r) = *v {
+                if self.slots.get(r).is_none_or(|slot| slot.obj.is_none()) {
+                    return Err(Error::SnapshotError(format!("无效的引用 #{}", r)));
+                }
+            }
+        }
+        Ok(())
+    }
+    // 输出对象的内容，嵌套的引用只输出编号
+    pub fn display(&self, v: Value) -> String {
+        let join = |items: &[Value]| items.iter().map(|item| item.to_output()).collect::<Vec<_>>().join(", ");
+        match self.get(v) {
+            Ok(Object::Array(items)) => format!("[{}]", join(items)),
+            Ok(Object::Struct(fields)) => format!("{{{}}}", join(fields)),
+            Err(_) => v.to_string(),
+        }
+    }
+}
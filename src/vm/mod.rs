@@ -0,0 +1,705 @@
+use std::io;
+
+use crate::error::{Error, Result};
+use crate::native::Registry;
+use crate::profile::Profiler;
+
+use self::heap::{GcStats, Heap, Object};
+use self::module::Module;
+use self::trace::Tracer;
+use self::value::Value;
+
+pub mod heap;
+pub mod module;
+pub mod threaded;
+pub mod trace;
+pub mod value;
+pub mod register;
+pub mod snapshot;
+pub mod verify;
+
+#[derive(Debug, Clone, Copy)]
+pub enum OpCode {
+    LoadConst(i32),             // 1   加载一个常数到栈顶。
+    LoadTrue,                   // 2   加载True到栈顶。
+    LoadFalse,                  // 3   加载False到栈顶。
+    LoadNull,                   // 4   加载NULL到栈顶。
+    LoadGlobal(usize),          // 5   从全局变量中加载一个变量到栈顶。
+    StoreGlobal(usize),         // 6   将栈顶的值储存到全局变量表中。
+
+    BinOpAdd,                   // 100 栈顶两个值相加。 
+    BinOpSub,                   // 101 栈顶两个值相减。
+    BinOpMul,                   // 102 栈顶两个值相乘。
+    BinOpDiv,                   // 103 栈顶两个值相除。
+    BinOpGt,                    // 104 比较栈顶两个值，大于则结果为true。
+    BinOpGe,                    // 105 比较栈顶两个值，大于等于则结果为true。
+    BinOpLt,                    // 106 比较栈顶两个值，小于则结果为true。
+    BinOpLe,                    // 107 比较栈顶两个值，小于等于则结果为true。
+    BinOpEq,                    // 108 比较栈顶两个值，相等则结果为true。
+    BinOpNe,                    // 109 比较栈顶两个值，不相等则结果为true。
+    BinOpOr,                    // 110 栈顶两个值或。
+    BinOpAnd,                   // 111 栈顶两个值与。
+    BinOpMod,                   // 112 栈顶两个值取余，结果的符号与被除数相同。
+    // 跳转的地址
+    Jmp(usize),                 // 10  无条件跳转到指定位置。
+    JmpTrue(usize),             // 11  如果栈顶值为true，跳转到指定位置。
+    JmpFalse(usize),            // 12  如果栈顶值为false，跳转到指定位置。
+    
+    PrintItem,                  // 13  打印栈顶的值。
+    PrintNewline,               // 14  打印一个换行符。
+    GetInt,                     // 15  输入一个整数
+    Pop,                        // 16  弹出栈顶值
+    UniOpNot,                   // 17  对栈顶的布尔值取反。
+    UniOpNeg,                   // 18  对栈顶的值取负。
+    StorePC,                    // 19  存储当前 PC。
+    LoadPC,                     // 20  从 PC 栈中加载。
+    // 栈帧中的槽位
+    StoreVar(usize),            // 21  将栈顶的值存储到当前栈帧的局部变量中。
+    // 栈帧中的槽位
+    LoadVar(usize),             // 22  从当前栈帧的局部变量中加载一个变量到栈顶。
+    // 槽位个数
+    EnterFrame(usize),          // 23  函数入口，为当前栈帧分配全部局部变量的槽位。
+    MakeClosure,                // 25  创建一个闭包。
+    // 函数表中的编号, args个数
+    Call(usize, usize),         // 26  调用一个函数。
+    Ret,                        // 27  从当前函数返回。
+    // 元素个数
+    NewArray(usize),            // 28  用栈顶的 n 个值创建一个数组。
+    ArrayGet,                   // 29  弹出下标和数组，压入对应的元素。
+    ArraySet,                   // 30  弹出值、下标和数组，设置对应的元素。
+    ArrayLen,                   // 31  弹出数组，压入数组的长度。
+    // 字段个数
+    NewStruct(usize),           // 32  用栈顶的 n 个值创建一个结构体。
+    // 字段序号
+    GetField(usize),            // 33  弹出结构体，压入对应的字段。
+    SetField(usize),            // 34  弹出值和结构体，设置对应的字段。
+    // 以下为窥孔优化生成的超级指令
+    AddConst(i32),              // 35  栈顶的值加上一个常数。
+    // 比较方式, 跳转的地址
+    CmpJmp(Cmp, usize),         // 36  比较栈顶两个值，结果为false则跳转到指定位置。
+    // 栈帧中的槽位, 常数
+    IncVar(usize, i32),         // 37  当前栈帧的局部变量加上一个常数。
+    // 栈帧中的槽位
+    TeeVar(usize),              // 38  将栈顶的值存储到局部变量中，不弹出。
+    // 原生函数编号, args个数
+    CallNative(usize, usize),   // 39  调用一个原生函数，返回值压入栈顶，没有返回值时压入 null。
+    // catch 块的地址
+    PushHandler(usize),         // 40  进入 try 块，登记异常处理程序。
+    PopHandler,                 // 41  离开 try 块，移除最近登记的异常处理程序。
+    Throw,                      // 42  弹出栈顶的值作为异常抛出。
+}
+
+// 异常处理程序，记录进入 try 块时的状态，抛出异常时恢复
+#[derive(Debug, Clone, Copy)]
+struct Handler {
+    catch: usize,       // catch 块的地址
+    stack_len: usize,
+    depth: usize,       // pc_stack 的长度
+    fp: usize,
+    locals_len: usize,
+}
+
+// run 结束时的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Finished,       // 程序执行完毕
+    OutOfFuel,      // 执行的指令条数达到上限
+    WaitingInput,   // 在 GetInt 之前暂停
+}
+
+// CmpJmp 的比较方式，编码与对应的 BinOp 指令相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl Cmp {
+    pub fn from_opcode(op: &OpCode) -> Option<Cmp> {
+        match op {
+            OpCode::BinOpGt => Some(Cmp::Gt),
+            OpCode::BinOpGe => Some(Cmp::Ge),
+            OpCode::BinOpLt => Some(Cmp::Lt),
+            OpCode::BinOpLe => Some(Cmp::Le),
+            OpCode::BinOpEq => Some(Cmp::Eq),
+            OpCode::BinOpNe => Some(Cmp::Ne),
+            _ => None,
+        }
+    }
+    pub fn opcode(self) -> OpCode {
+        match self {
+            Cmp::Gt => OpCode::BinOpGt,
+            Cmp::Ge => OpCode::BinOpGe,
+            Cmp::Lt => OpCode::BinOpLt,
+            Cmp::Le => OpCode::BinOpLe,
+            Cmp::Eq => OpCode::BinOpEq,
+            Cmp::Ne => OpCode::BinOpNe,
+        }
+    }
+    fn int(self, a: i32, b: i32) -> bool {
+        match self {
+            Cmp::Gt => a > b,
+            Cmp::Ge => a >= b,
+            Cmp::Lt => a < b,
+            Cmp::Le => a <= b,
+            Cmp::Eq => a == b,
+            Cmp::Ne => a != b,
+        }
+    }
+}
+
+impl OpCode {
+    // 不带操作数的指令名
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::LoadConst(_) => "LoadConst",
+            OpCode::LoadTrue => "LoadTrue",
+            OpCode::LoadFalse => "LoadFalse",
+            OpCode::LoadNull => "LoadNull",
+            OpCode::LoadGlobal(_) => "LoadGlobal",
+            OpCode::StoreGlobal(_) => "StoreGlobal",
+            OpCode::BinOpAdd => "BinOpAdd",
+            OpCode::BinOpSub => "BinOpSub",
+            OpCode::BinOpMul => "BinOpMul",
+            OpCode::BinOpDiv => "BinOpDiv",
+            OpCode::BinOpGt => "BinOpGt",
+            OpCode::BinOpGe => "BinOpGe",
+            OpCode::BinOpLt => "BinOpLt",
+            OpCode::BinOpLe => "BinOpLe",
+            OpCode::BinOpEq => "BinOpEq",
+            OpCode::BinOpNe => "BinOpNe",
+            OpCode::BinOpOr => "BinOpOr",
+            OpCode::BinOpAnd => "BinOpAnd",
+            OpCode::BinOpMod => "BinOpMod",
+            OpCode::Jmp(_) => "Jmp",
+            OpCode::JmpTrue(_) => "JmpTrue",
+            OpCode::JmpFalse(_) => "JmpFalse",
+            OpCode::PrintItem => "PrintItem",
+            OpCode::PrintNewline => "PrintNewline",
+            OpCode::GetInt => "GetInt",
+            OpCode::Pop => "Pop",
+            OpCode::UniOpNot => "UniOpNot",
+            OpCode::UniOpNeg => "UniOpNeg",
+            OpCode::StorePC => "StorePC",
+            OpCode::LoadPC => "LoadPC",
+            OpCode::StoreVar(_) => "StoreVar",
+            OpCode::LoadVar(_) => "LoadVar",
+            OpCode::EnterFrame(_) => "EnterFrame",
+            OpCode::MakeClosure => "MakeClosure",
+            OpCode::Call(_, _) => "Call",
+            OpCode::Ret => "Ret",
+            OpCode::NewArray(_) => "NewArray",
+            OpCode::ArrayGet => "ArrayGet",
+            OpCode::ArraySet => "ArraySet",
+            OpCode::ArrayLen => "ArrayLen",
+            OpCode::NewStruct(_) => "NewStruct",
+            OpCode::GetField(_) => "GetField",
+            OpCode::SetField(_) => "SetField",
+            OpCode::AddConst(_) => "AddConst",
+            OpCode::CmpJmp(_, _) => "CmpJmp",
+            OpCode::IncVar(_, _) => "IncVar",
+            OpCode::TeeVar(_) => "TeeVar",
+            OpCode::CallNative(_, _) => "CallNative",
+            OpCode::PushHandler(_) => "PushHandler",
+            OpCode::PopHandler => "PopHandler",
+            OpCode::Throw => "Throw",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VM {
+    stack: Vec<Value>,
+    globals: Vec<Value>,
+    // 所有栈帧的局部变量连续存放，fp 指向当前栈帧的起始位置
+    locals: Vec<Value>,
+    fp: usize,
+    // (返回地址, 调用者的 fp)
+    pc_stack: Vec<(usize, usize)>,
+    handlers: Vec<Handler>,
+    heap: Heap,
+    pc: usize,
+    steps: u64,
+    module: Module,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    threaded: bool,
+    insts: Vec<threaded::Inst>,
+    // 剩余可以执行的指令条数
+    fuel: Option<u64>,
+    pause_at_getint: bool,
+    natives: Registry,
+}
+
+impl VM {
+    pub fn new(module: Module) -> Self {
+        Self {
+            stack: Vec::new(),
+            // 全局变量在初始化之前为 null：全局变量的初始化代码可能调用读取之后才定义的全局变量的函数
+            globals: vec![Value::Null; module.globals.len()],
+            locals: Vec::new(),
+            fp: 0,
+            pc_stack: Vec::new(),
+            handlers: Vec::new(),
+            heap: Heap::new(),
+            pc: 0,
+            steps: 0,
+            module,
+            tracer: None,
+            profiler: None,
+            threaded: false,
+            insts: Vec::new(),
+            fuel: None,
+            pause_at_getint: false,
+            natives: Registry::with_defaults(),
+        }
+    }
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+    // 已分派执行的指令条数
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    // 操作数栈中值的个数
+    pub fn stack_height(&self) -> usize {
+        self.stack.len()
+    }
+    // 堆大小上限（字节）
+    pub fn set_heap_limit(&mut self, limit: usize) {
+        self.heap.set_limit(limit);
+    }
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+    // 使用预解码的线索化分派执行，开启时解码全部指令
+    pub fn set_threaded(&mut self, threaded: bool) {
+        self.threaded = threaded;
+        self.insts = if threaded { threaded::decode(&self.module) } else { Vec::new() };
+    }
+    // 执行前校验模块，CallNative 按当前的原生函数注册表检查
+    pub fn verify(&self) -> Result<()> {
+        verify::verify(&self.module, &self.natives)
+    }
+    // 替换默认的原生函数注册表，编号须与翻译时使用的注册表一致
+    pub fn set_natives(&mut self, natives: Registry) {
+        self.natives = natives;
+    }
+    // 最多再执行 fuel 条指令，用完后暂停
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+    // 执行到 GetInt 时暂停（本次 run 的第一条指令除外，以便恢复后继续读取输入）
+    pub fn set_pause_at_getint(&mut self, pause: bool) {
+        self.pause_at_getint = pause;
+    }
+    pub fn run(&mut self) -> Result<Status> {
+        // 追踪、性能分析和暂停需要逐条观察指令，此时使用普通的分派方式
+        let res = if self.threaded && self.tracer.is_none() && self.profiler.is_none()
+            && self.fuel.is_none() && !self.pause_at_getint {
+            self.run_threaded().map(|_| Status::Finished)
+        } else {
+            self.run_match()
+        };
+        // 执行出错时附上出错的指令和调用栈
+        let status = res.map_err(|e| match e {
+            Error::Uncaught(..) | Error::IoError(_) => e,
+            e => {
+                let mut backtrace = self.backtrace();
+                backtrace[0] = format!("{}: {}", backtrace[0], self.module.describe(self.pc - 1));
+                Error::RuntimeError(Box::new(e), backtrace)
+            },
+        })?;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush()?;
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.finish();
+        }
+        Ok(status)
+    }
+    // 逐条取出 OpCode 并 match 分派
+    fn run_match(&mut self) -> Result<Status> {
+        let start = self.steps;
+        while self.pc < self.module.code.len() {
+            let index = self.module.code[self.pc];
+            if self.pause_at_getint && self.steps != start && matches!(index, OpCode::GetInt) {
+                return Ok(Status::WaitingInput);
+            }
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    return Ok(Status::OutOfFuel);
+                }
+                *fuel -= 1;
+            }
+            self.steps += 1;
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(self.pc, &index, self.stack.last().copied(), self.pc_stack.len())?;
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.step(index.name());
+                match index {
+                    OpCode::Call(func, _) => profiler.enter(&self.module.funcs[func].name),
+                    OpCode::Ret => profiler.leave(),
+                    _ => (),
+                }
+            }
+            self.pc += 1;
+            self.execute(index)?;
+        }
+        Ok(Status::Finished)
+    }
+    // 执行一条指令，pc 已经指向下一条指令
+    fn execute(&mut self, index: OpCode) -> Result<()> {
+        match index {
+            OpCode::LoadConst(v) => {
+                self.push(Value::Int(v));
+            },
+            OpCode::LoadTrue => {
+                self.push(Value::Bool(true));
+            },
+            OpCode::LoadFalse => {
+                self.push(Value::Bool(false));
+            },
+            OpCode::LoadNull => {
+                self.push(Value::Null);
+            },
+            OpCode::LoadGlobal(pos) => {
+                self.push(self.globals[pos]);
+            },
+            OpCode::StoreGlobal(pos) => {
+                let v = self.pop();
+                self.globals[pos] = v;
+            },
+            OpCode::BinOpAdd => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpSub => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpMul => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpDiv => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpGt => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpGe => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpLt => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpLe => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpEq => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpNe => {
+                self.binop(index)?;
+            },
+            OpCode::BinOpOr => {
+                self.binop(index)?;
+            }
+            OpCode::BinOpAnd => {
+                self.binop(index)?;
+            }
+            OpCode::BinOpMod => {
+                self.binop(index)?;
+            },
+            OpCode::Jmp(next) => {
+                self.pc = next;
+            },
+            OpCode::JmpTrue(next) => {
+                let condition = self.pop();
+                if condition.truthy()? {
+                    self.pc = next;
+                }
+            },
+            OpCode::JmpFalse(next) => {
+                let condition = self.pop();
+                if !condition.truthy()? {
+                    self.pc = next;
+                }
+            },
+            OpCode::PrintItem => {
+                let c = self.pop();
+                match c {
+                    Value::Ref(_) => print!("{}", self.heap.display(c)),
+                    _ => print!("{}", c.to_output()),
+                }
+            },
+            OpCode::PrintNewline => {
+                println!();
+            },
+            OpCode::GetInt => {
+                let mut input = String::new();
+                io::stdin().read_line(&mut input).unwrap();
+                let input: i32 = input.trim().parse().unwrap();
+                self.push(Value::Int(input));
+            }
+            OpCode::Pop => {
+                self.pop();
+            },
+            OpCode::UniOpNot => {
+                let v = self.pop().logical_not()?;
+                self.push(v);
+            },
+            OpCode::UniOpNeg => {
+                let v = self.pop().checked_neg()?;
+                self.push(v);
+            },
+            OpCode::StoreVar(pos) => {
+                let v = self.pop();
+                self.locals[self.fp + pos] = v;
+            },
+            OpCode::LoadVar(pos) => {
+                let v = self.locals[self.fp + pos];
+                self.push(v);
+            },
+            OpCode::EnterFrame(size) => {
+                self.locals.resize(self.fp + size, Value::Null);
+            },
+            OpCode::MakeClosure => return Err(Error::VMError(String::from("不支持的指令 MakeClosure"))),
+            OpCode::Call(func, args_count) => {
+                self.call(self.module.funcs[func].entry, args_count);
+            },
+            OpCode::Ret => {
+                self.leave_frame();
+            },
+            OpCode::StorePC => {
+                self.pc_stack.push((self.pc, self.fp));
+            },
+            OpCode::LoadPC => {
+                let (pc, _) = self.pc_stack.pop().unwrap();
+                self.pc = pc;
+            },
+            OpCode::NewArray(len) => {
+                self.new_object(len, Object::Array)?;
+            },
+            OpCode::ArrayGet => {
+                let index = self.pop();
+                let array = self.pop();
+                let v = match self.heap.get(array)? {
+                    Object::Array(items) => *Self::element(items, index)?,
+                    _ => return Err(Error::TypeError(String::from("ArrayGet 的操作数不是数组"))),
+                };
+                self.push(v);
+            },
+            OpCode::ArraySet => {
+                let v = self.pop();
+                let index = self.pop();
+                let array = self.pop();
+                match self.heap.get_mut(array)? {
+                    Object::Array(items) => *Self::element_mut(items, index)? = v,
+                    _ => return Err(Error::TypeError(String::from("ArraySet 的操作数不是数组"))),
+                }
+            },
+            OpCode::ArrayLen => {
+                let array = self.pop();
+                let len = match self.heap.get(array)? {
+                    Object::Array(items) => items.len(),
+                    _ => return Err(Error::TypeError(String::from("ArrayLen 的操作数不是数组"))),
+                };
+                self.push(Value::Int(len as i32));
+            },
+            OpCode::NewStruct(len) => {
+                self.new_object(len, Object::Struct)?;
+            },
+            OpCode::GetField(pos) => {
+                let obj = self.pop();
+                let v = match self.heap.get(obj)? {
+                    Object::Struct(fields) => *Self::element(fields, Value::Int(pos as i32))?,
+                    _ => return Err(Error::TypeError(String::from("GetField 的操作数不是结构体"))),
+                };
+                self.push(v);
+            },
+            OpCode::SetField(pos) => {
+                let v = self.pop();
+                let obj = self.pop();
+                match self.heap.get_mut(obj)? {
+                    Object::Struct(fields) => *Self::element_mut(fields, Value::Int(pos as i32))? = v,
+                    _ => return Err(Error::TypeError(String::from("SetField 的操作数不是结构体"))),
+                }
+            },
+            OpCode::AddConst(k) => {
+                let v = self.pop();
+                let v = Self::add_const(v, k)?;
+                self.push(v);
+            },
+            OpCode::CmpJmp(cmp, next) => {
+                self.cmp_jmp(cmp, next)?;
+            },
+            OpCode::IncVar(pos, k) => {
+                let slot = self.fp + pos;
+                self.locals[slot] = Self::add_const(self.locals[slot], k)?;
+            },
+            OpCode::TeeVar(pos) => {
+                self.locals[self.fp + pos] = *self.stack.last().unwrap();
+            },
+            OpCode::CallNative(index, args_count) => {
+                if self.stack.len() < args_count {
+                    return Err(Error::VMError(format!("栈中的值不足 {} 个", args_count)));
+                }
+                let args = self.stack.split_off(self.stack.len() - args_count);
+                let v = self.natives.call(index, &args)?.unwrap_or(Value::Null);
+                self.push(v);
+            },
+            OpCode::PushHandler(catch) => {
+                self.handlers.push(Handler {
+                    catch,
+                    stack_len: self.stack.len(),
+                    depth: self.pc_stack.len(),
+                    fp: self.fp,
+                    locals_len: self.locals.len(),
+                });
+            },
+            OpCode::PopHandler => {
+                self.handlers.pop();
+            },
+            OpCode::Throw => {
+                let v = self.pop();
+                self.throw(v)?;
+            },
+        }
+        Ok(())
+    }
+    fn call(&mut self, next: usize, args_count: usize) {
+        let fp = self.locals.len();
+        self.locals.resize(fp + args_count, Value::Null);
+        for i in 0..args_count {
+            self.locals[fp + i] = self.pop();
+        }
+        self.pc_stack.push((self.pc, self.fp));
+        self.fp = fp;
+        self.pc = next;
+    }
+    fn cmp_jmp(&mut self, cmp: Cmp, next: usize) -> Result<()> {
+        let b = self.pop();
+        let a = self.pop();
+        let condition = match (a, b) {
+            (Value::Int(a), Value::Int(b)) => cmp.int(a, b),
+            _ => Value::binop(cmp.opcode(), a, b)?.truthy()?,
+        };
+        if !condition {
+            self.pc = next;
+        }
+        Ok(())
+    }
+    fn add_const(v: Value, k: i32) -> Result<Value> {
+        match v {
+            Value::Int(v) => Ok(Value::Int(v.wrapping_add(k))),
+            _ => Value::binop(OpCode::BinOpAdd, v, Value::Int(k)),
+        }
+    }
+    // 丢弃当前栈帧，回到调用者；函数内 try 块登记的处理程序一并移除
+    fn leave_frame(&mut self) {
+        let (pc, fp) = self.pc_stack.pop().unwrap();
+        self.locals.truncate(self.fp);
+        self.pc = pc;
+        self.fp = fp;
+        while self.handlers.last().is_some_and(|h| h.depth > self.pc_stack.len()) {
+            self.handlers.pop();
+        }
+    }
+    // 回退到最近的处理程序，异常的值压入栈顶后跳转到 catch 块；没有处理程序时返回带回溯的错误
+    fn throw(&mut self, v: Value) -> Result<()> {
+        let Some(h) = self.handlers.pop() else {
+            let v = match v {
+                Value::Ref(_) => self.heap.display(v),
+                _ => v.to_output(),
+            };
+            return Err(Error::Uncaught(v, self.backtrace()));
+        };
+        if let Some(profiler) = &mut self.profiler {
+            for _ in h.depth..self.pc_stack.len() {
+                profiler.leave();
+            }
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.unwind(h.depth);
+        }
+        self.stack.truncate(h.stack_len);
+        self.pc_stack.truncate(h.depth);
+        self.fp = h.fp;
+        self.locals.truncate(h.locals_len);
+        self.push(v);
+        self.pc = h.catch;
+        Ok(())
+    }
+    // 当前的调用栈，最内层在前
+    fn backtrace(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        let mut pc = self.pc - 1;
+        for (ret, _) in self.pc_stack.iter().rev() {
+            let func = match ret.checked_sub(1).and_then(|call| self.module.code.get(call)) {
+                Some(OpCode::Call(func, _)) => self.module.funcs[*func].name.clone(),
+                _ => String::from("?"),
+            };
+            res.push(format!("{} ({})", func, self.module.location(pc)));
+            pc = ret.saturating_sub(1);
+        }
+        res.push(format!("<global> ({})", self.module.location(pc)));
+        res
+    }
+    // 用栈顶的 len 个值创建对象；在弹出这些值之前回收，保证它们仍然是根
+    fn new_object(&mut self, len: usize, make: fn(Vec<Value>) -> Object) -> Result<()> {
+        if self.heap.should_collect(Heap::object_size(len)) {
+            self.collect_garbage();
+        }
+        if self.stack.len() < len {
+            return Err(Error::VMError(format!("栈中的值不足 {} 个", len)));
+        }
+        let items = self.stack.split_off(self.stack.len() - len);
+        let r = self.heap.alloc(make(items))?;
+        self.push(r);
+        Ok(())
+    }
+    pub fn collect_garbage(&mut self) {
+        let roots = self.stack.iter().chain(self.locals.iter()).chain(self.globals.iter());
+        self.heap.collect(roots);
+    }
+    fn element(items: &[Value], index: Value) -> Result<&Value> {
+        let len = items.len();
+        match index {
+            Value::Int(i) => items.get(i as usize).ok_or(Error::VMError(format!("下标越界: {} (长度 {})", i, len))),
+            _ => Err(Error::TypeError(format!("下标的类型不能是 {}", index.type_name()))),
+        }
+    }
+    fn element_mut(items: &mut [Value], index: Value) -> Result<&mut Value> {
+        let len = items.len();
+        match index {
+            Value::Int(i) => items.get_mut(i as usize).ok_or(Error::VMError(format!("下标越界: {} (长度 {})", i, len))),
+            _ => Err(Error::TypeError(format!("下标的类型不能是 {}", index.type_name()))),
+        }
+    }
+    fn push(&mut self, x: Value) {
+        self.stack.push(x);
+    }
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+    fn binop(&mut self, op: OpCode) -> Result<()> {
+        let v2 = self.pop();
+        let v1 = self.pop();
+        let v = Value::binop(op, v1, v2)?;
+        self.push(v);
+        Ok(())
+    }
+}
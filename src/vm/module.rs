@@ -0,0 +1,159 @@
+/*!
+ * 编译后的模块：函数表、全局变量表、指令和可选的调试信息。
+ * Call 指令通过函数表中的编号调用函数，入口地址、参数个数等信息都从函数表中取得。
+ * 常量池只存在于二进制文件中（见 bytecode_translation::binary），加载后 LoadConst 仍然带立即数。
+ * 调试信息记录每条指令对应的源文件、源代码行和栈帧槽位对应的局部变量名，用于报告错误的位置。
+ * 带链接信息的是可重定位的目标模块，可以引用其他模块中的函数和全局变量，链接之后才能执行。
+ */
+
+use crate::native::NativeType;
+
+use super::OpCode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub entry: usize,
+    pub arity: usize,
+    pub nlocals: usize,             // 栈帧的槽位个数（参数 + 局部变量）
+    pub ret: Option<NativeType>,    // None 表示没有返回值
+}
+
+// 在 [start, end) 范围内的指令中，栈帧的槽位 slot 保存的是局部变量 name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalVar {
+    pub slot: usize,
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    // (pc, 源文件名)，按 pc 排序；链接多个模块后有多项
+    pub files: Vec<(usize, String)>,
+    // (pc, 行号)，按 pc 排序；从 pc 开始直到下一项之前的指令都属于该行
+    pub lines: Vec<(usize, usize)>,
+    pub locals: Vec<LocalVar>,
+}
+
+impl DebugInfo {
+    pub fn file(&self, pc: usize) -> Option<&str> {
+        let i = self.files.partition_point(|(start, _)| *start <= pc);
+        i.checked_sub(1).map(|i| self.files[i].1.as_str())
+    }
+    pub fn line(&self, pc: usize) -> Option<usize> {
+        let i = self.lines.partition_point(|(start, _)| *start <= pc);
+        i.checked_sub(1).map(|i| self.lines[i].1)
+    }
+    // 槽位可以被不同作用域的变量复用，取包含 pc 的最内层（范围最小）的变量
+    pub fn local(&self, slot: usize, pc: usize) -> Option<&str> {
+        self.locals.iter()
+            .filter(|v| v.slot == slot && v.start <= pc && pc < v.end)
+            .min_by_key(|v| v.end - v.start)
+            .map(|v| v.name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Func,
+    Global,
+}
+
+// 导出的符号，index 为函数表或全局变量表中的编号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub index: usize,
+}
+
+// 导入的符号，arity 为函数的参数个数（全局变量为 0）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub arity: usize,
+}
+
+// 目标模块的链接信息。代码的前 init_len 条指令是全局变量的初始化，之后是各个函数，没有调用 main 的代码。
+// 导入的符号的编号接在本模块定义的之后：第 k 个导入的函数编号为 funcs.len() + k，全局变量同理
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkInfo {
+    pub init_len: usize,
+    pub exports: Vec<Export>,
+    pub imports: Vec<Import>,
+}
+
+impl LinkInfo {
+    pub fn imports_of(&self, kind: SymbolKind) -> impl Iterator<Item = &Import> {
+        self.imports.iter().filter(move |i| i.kind == kind)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub funcs: Vec<Function>,
+    pub globals: Vec<String>,   // 全局变量名，位置即全局变量编号
+    pub code: Vec<OpCode>,
+    pub debug: Option<DebugInfo>,
+    pub link: Option<LinkInfo>, // 只有目标模块有
+}
+
+impl Module {
+    pub fn func_index(&self, name: &str) -> Option<usize> {
+        self.funcs.iter().position(|f| f.name == name)
+    }
+    // pc 在源代码中的位置：有调试信息时为 文件:行号，否则为 pc
+    pub fn location(&self, pc: usize) -> String {
+        match self.debug.as_ref().and_then(|debug| Some((debug.file(pc)?, debug.line(pc)?))) {
+            Some((file, line)) => format!("{}:{}", file, line),
+            None => format!("pc {}", pc),
+        }
+    }
+    // pc 处的指令，变量和函数尽量以名字表示
+    pub fn describe(&self, pc: usize) -> String {
+        let Some(op) = self.code.get(pc) else {
+            return String::from("?");
+        };
+        let local = |slot: usize| match self.debug.as_ref().and_then(|debug| debug.local(slot, pc)) {
+            Some(name) => name.to_string(),
+            None => format!("#{}", slot),
+        };
+        let global = |pos: usize| self.globals.get(pos).cloned().unwrap_or(format!("#{}", pos));
+        match *op {
+            OpCode::LoadVar(slot) | OpCode::StoreVar(slot) | OpCode::TeeVar(slot) => format!("{}({})", op.name(), local(slot)),
+            OpCode::IncVar(slot, k) => format!("IncVar({}, {})", local(slot), k),
+            OpCode::LoadGlobal(pos) | OpCode::StoreGlobal(pos) => format!("{}({})", op.name(), global(pos)),
+            OpCode::Call(func, argc) => match self.funcs.get(func) {
+                Some(f) => format!("Call({}, {})", f.name, argc),
+                None => format!("{:?}", op),
+            },
+            _ => format!("{:?}", op),
+        }
+    }
+}
+
+// 返回类型在文件中的编号：0 表示没有返回值
+pub(crate) fn ret_tag(ret: Option<NativeType>) -> u8 {
+    match ret {
+        None => 0,
+        Some(NativeType::Int) => 1,
+        Some(NativeType::Float) => 2,
+        Some(NativeType::Bool) => 3,
+        Some(NativeType::Any) => 4,
+    }
+}
+
+pub(crate) fn ret_from_tag(tag: u8) -> Option<Option<NativeType>> {
+    let ret = match tag {
+        0 => None,
+        1 => Some(NativeType::Int),
+        2 => Some(NativeType::Float),
+        3 => Some(NativeType::Bool),
+        4 => Some(NativeType::Any),
+        _ => return None,
+    };
+    Some(ret)
+}
@@ -0,0 +1,233 @@
+/*!
+ * 基于寄存器的虚拟机：每个函数在编译期确定寄存器个数，调用时分配一段连续的寄存器作为栈帧，
+ * 指令直接读写寄存器，不再需要逐个压栈、出栈。
+ */
+
+use std::io;
+
+use crate::error::{Error, Result};
+use crate::native::Registry;
+
+use super::value::Value;
+
+// 除特别说明外，操作数都是当前栈帧中的寄存器编号
+#[derive(Debug, Clone, Copy)]
+pub enum RegOp {
+    LoadK(usize, i32),                  // dst, 常数
+    Move(usize, usize),                 // dst, src
+    LoadGlobal(usize, usize),           // dst, 全局变量编号
+    StoreGlobal(usize, usize),          // 全局变量编号, src
+
+    // dst, lhs, rhs
+    Add(usize, usize, usize),
+    Sub(usize, usize, usize),
+    Mul(usize, usize, usize),
+    Div(usize, usize, usize),
+    Mod(usize, usize, usize),
+    Gt(usize, usize, usize),
+    Ge(usize, usize, usize),
+    Lt(usize, usize, usize),
+    Le(usize, usize, usize),
+    Eq(usize, usize, usize),
+    Ne(usize, usize, usize),
+    Or(usize, usize, usize),
+    And(usize, usize, usize),
+    // dst, lhs, 立即数
+    AddI(usize, usize, i32),
+    SubI(usize, usize, i32),
+    // dst, src
+    Not(usize, usize),
+    Neg(usize, usize),
+
+    Jmp(usize),                         // 跳转地址
+    JmpTrue(usize, usize),              // 条件, 跳转地址
+    JmpFalse(usize, usize),             // 条件, 跳转地址
+
+    Print(usize),                       // src
+    GetInt(usize),                      // dst
+    // 函数编号, 第一个参数所在寄存器, 参数个数, dst
+    Call(usize, usize, usize, usize),
+    // 原生函数编号, 第一个参数所在寄存器, 参数个数, dst
+    CallNative(usize, usize, usize, usize),
+    Ret(usize),                         // src
+    RetVoid,
+    Halt,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegFunc {
+    pub name: String,
+    pub entry: usize,
+    pub arity: usize,
+    pub nregs: usize,   // 寄存器个数（参数 + 局部变量 + 临时值）
+}
+
+#[derive(Debug, Clone)]
+pub struct RegProgram {
+    pub code: Vec<RegOp>,
+    pub funcs: Vec<RegFunc>,
+    pub nglobals: usize,
+    pub entry: usize,   // 程序入口（全局变量初始化）的函数编号
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    ret_pc: usize,
+    base: usize,
+    dst: usize,
+}
+
+#[derive(Debug)]
+pub struct RegVM {
+    regs: Vec<i32>,
+    globals: Vec<i32>,
+    frames: Vec<Frame>,
+    base: usize,
+    pc: usize,
+    steps: u64,
+    program: RegProgram,
+    natives: Registry,
+}
+
+impl RegVM {
+    pub fn new(program: RegProgram) -> Self {
+        Self {
+            regs: Vec::new(),
+            globals: vec![0; program.nglobals],
+            frames: Vec::new(),
+            base: 0,
+            pc: 0,
+            steps: 0,
+            program,
+            natives: Registry::with_defaults(),
+        }
+    }
+    pub fn set_natives(&mut self, natives: Registry) {
+        self.natives = natives;
+    }
+    // 已分派执行的指令条数
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+    pub fn run(&mut self) -> Result<()> {
+        let entry = &self.program.funcs[self.program.entry];
+        self.pc = entry.entry;
+        self.base = 0;
+        self.regs = vec![0; entry.nregs];
+        loop {
+            let op = self.program.code[self.pc];
+            self.pc += 1;
+            self.steps += 1;
+            match op {
+                RegOp::LoadK(dst, k) => self.set(dst, k),
+                RegOp::Move(dst, src) => self.set(dst, self.get(src)),
+                RegOp::LoadGlobal(dst, g) => self.set(dst, self.globals[g]),
+                RegOp::StoreGlobal(g, src) => self.globals[g] = self.get(src),
+                RegOp::Add(dst, a, b) => self.set(dst, self.get(a).wrapping_add(self.get(b))),
+                RegOp::Sub(dst, a, b) => self.set(dst, self.get(a).wrapping_sub(self.get(b))),
+                RegOp::Mul(dst, a, b) => self.set(dst, self.get(a).wrapping_mul(self.get(b))),
+                // 与栈虚拟机一致：除数为 0 时报错，i32::MIN 除以 -1 回绕
+                RegOp::Div(dst, a, b) => {
+                    let b = self.divisor(b)?;
+                    self.set(dst, self.get(a).wrapping_div(b));
+                },
+                RegOp::Mod(dst, a, b) => {
+                    let b = self.divisor(b)?;
+                    self.set(dst, self.get(a).wrapping_rem(b));
+                },
+                RegOp::Gt(dst, a, b) => self.set(dst, (self.get(a) > self.get(b)) as i32),
+                RegOp::Ge(dst, a, b) => self.set(dst, (self.get(a) >= self.get(b)) as i32),
+                RegOp::Lt(dst, a, b) => self.set(dst, (self.get(a) < self.get(b)) as i32),
+                RegOp::Le(dst, a, b) => self.set(dst, (self.get(a) <= self.get(b)) as i32),
+                RegOp::Eq(dst, a, b) => self.set(dst, (self.get(a) == self.get(b)) as i32),
+                RegOp::Ne(dst, a, b) => self.set(dst, (self.get(a) != self.get(b)) as i32),
+                RegOp::Or(dst, a, b) => self.set(dst, (self.get(a) != 0 || self.get(b) != 0) as i32),
+                RegOp::And(dst, a, b) => self.set(dst, (self.get(a) != 0 && self.get(b) != 0) as i32),
+                RegOp::AddI(dst, a, k) => self.set(dst, self.get(a).wrapping_add(k)),
+                RegOp::SubI(dst, a, k) => self.set(dst, self.get(a).wrapping_sub(k)),
+                RegOp::Not(dst, a) => self.set(dst, (self.get(a) == 0) as i32),
+                RegOp::Neg(dst, a) => self.set(dst, self.get(a).wrapping_neg()),
+                RegOp::Jmp(next) => self.pc = next,
+                RegOp::JmpTrue(cond, next) => {
+                    if self.get(cond) != 0 {
+                        self.pc = next;
+                    }
+                },
+                RegOp::JmpFalse(cond, next) => {
+                    if self.get(cond) == 0 {
+                        self.pc = next;
+                    }
+                },
+                RegOp::Print(src) => println!("{}", self.get(src)),
+                RegOp::GetInt(dst) => {
+                    let mut input = String::new();
+                    io::stdin().read_line(&mut input)?;
+                    let input: i32 = input.trim().parse().map_err(|_| Error::VMError(format!("非法输入: {}", input.trim())))?;
+                    self.set(dst, input);
+                },
+                RegOp::Call(func, args, argc, dst) => {
+                    let func = &self.program.funcs[func];
+                    let base = self.regs.len();
+                    self.regs.resize(base + func.nregs, 0);
+                    for i in 0..argc {
+                        self.regs[base + i] = self.regs[self.base + args + i];
+                    }
+                    self.frames.push(Frame { ret_pc: self.pc, base: self.base, dst });
+                    self.base = base;
+                    self.pc = func.entry;
+                },
+                RegOp::CallNative(index, args, argc, dst) => {
+                    let args: Vec<Value> = (0..argc).map(|i| Value::Int(self.get(args + i))).collect();
+                    // 寄存器只保存整数，布尔值按 0/1 保存，没有返回值时为 0
+                    let v = match self.natives.call(index, &args)? {
+                        Some(Value::Int(v)) => v,
+                        Some(Value::Bool(b)) => b as i32,
+                        Some(v) => return Err(Error::TypeError(format!("寄存器虚拟机不支持 {} 类型的返回值", v.type_name()))),
+                        None => 0,
+                    };
+                    self.set(dst, v);
+                },
+                RegOp::Ret(src) => {
+                    let v = self.get(src);
+                    if !self.ret(v) {
+                        break;
+                    }
+                },
+                RegOp::RetVoid => {
+                    if !self.ret(0) {
+                        break;
+                    }
+                },
+                RegOp::Halt => break,
+            }
+        }
+        Ok(())
+    }
+    // 返回到调用者，已经是最外层时返回 false
+    fn ret(&mut self, v: i32) -> bool {
+        match self.frames.pop() {
+            Some(frame) => {
+                self.regs.truncate(self.base);
+                self.base = frame.base;
+                self.pc = frame.ret_pc;
+                self.set(frame.dst, v);
+                true
+            },
+            None => false,
+        }
+    }
+    #[inline]
+    fn get(&self, r: usize) -> i32 {
+        self.regs[self.base + r]
+    }
+    #[inline]
+    fn set(&mut self, r: usize, v: i32) {
+        self.regs[self.base + r] = v;
+    }
+    fn divisor(&self, r: usize) -> Result<i32> {
+        match self.get(r) {
+            0 => Err(Error::VMError(String::from("除数为 0"))),
+            v => Ok(v),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+/*!
+ * 虚拟机状态的快照：把模块（二进制字节码）、操作数栈、局部变量、全局变量、PC 栈、异常处理程序、堆、原生函数的状态和 pc 保存为二进制文件，
+ * 之后可以在新的进程中恢复并继续执行。
+ * 文件以魔数和版本号开头，之后的整数均为小端序，usize 按 64 位保存。
+ * 恢复时校验模块，并检查 pc、栈帧、操作数栈的高度、异常处理程序和引用与之一致，不一致时报告 SnapshotError。
+ */
+
+use std::io::Write;
+
+use crate::bytecode_translation::binary;
+use crate::error::{Error, Result};
+
+use super::heap::Heap;
+use super::verify;
+use super::value::Value;
+use super::{Handler, OpCode, VM};
+
+pub const MAGIC: &[u8; 8] = b"CYSNAP\0\0";
+// 格式改变时递增，旧版本的快照不能恢复
+pub const VERSION: u32 = 4;
+
+#[derive(Debug, Default)]
+pub(super) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend(v.to_le_bytes());
+    }
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend(v.to_le_bytes());
+    }
+    pub fn usize(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+    pub fn value(&mut self, v: Value) {
+        match v {
+            Value::Null => self.u8(0),
+            Value::Bool(b) => {
+                self.u8(1);
+                self.u8(b as u8);
+            },
+            Value::Int(i) => {
+                self.u8(2);
+                self.u32(i as u32);
+            },
+            Value::Float(f) => {
+                self.u8(3);
+                self.u64(f.to_bits());
+            },
+            Value::Ref(r) => {
+                self.u8(4);
+                self.usize(r);
+            },
+        }
+    }
+    pub fn values(&mut self, values: &[Value]) {
+        self.usize(values.len());
+        for v in values {
+            self.value(*v);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.buf.len())
+            .ok_or(Error::SnapshotError(String::from("文件不完整")))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+    pub fn usize(&mut self) -> Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| Error::SnapshotError(String::from("数值超出范围")))
+    }
+    pub fn value(&mut self) -> Result<Value> {
+        let v = match self.u8()? {
+            0 => Value::Null,
+            1 => Value::Bool(self.u8()? != 0),
+            2 => Value::Int(self.u32()? as i32),
+            3 => Value::Float(f64::from_bits(self.u64()?)),
+            4 => Value::Ref(self.usize()?),
+            tag => return Err(Error::SnapshotError(format!("未知的值类型 {}", tag))),
+        };
+        Ok(v)
+    }
+    pub fn values(&mut self) -> Result<Vec<Value>> {
+        let len = self.usize()?;
+        // 长度来自文件，不能直接用来预分配
+        let mut values = Vec::new();
+        for _ in 0..len {
+            values.push(self.value()?);
+        }
+        Ok(values)
+    }
+}
+
+impl VM {
+    pub fn save(&self, out: &mut impl Write) -> Result<()> {
+        let mut w = Writer::default();
+        w.buf.extend(MAGIC);
+        w.u32(VERSION);
+        let module = binary::encode(&self.module);
+        w.usize(module.len());
+        w.buf.extend(module);
+        w.usize(self.pc);
+        w.u64(self.steps);
+        w.usize(self.fp);
+        w.values(&self.stack);
+        w.values(&self.locals);
+        w.values(&self.globals);
+        w.usize(self.pc_stack.len());
+        for (pc, fp) in &self.pc_stack {
+            w.usize(*pc);
+            w.usize(*fp);
+        }
+        w.usize(self.handlers.len());
+        for h in &self.handlers {
+            w.usize(h.catch);
+            w.usize(h.stack_len);
+            w.usize(h.depth);
+            w.usize(h.fp);
+            w.usize(h.locals_len);
+        }
+        self.heap.save(&mut w);
+        w.usize(self.natives.len());
+        for i in 0..self.natives.len() {
+            w.values(self.natives.state(i));
+        }
+        out.write_all(&w.buf)?;
+        Ok(())
+    }
+    pub fn load(input: &[u8]) -> Result<VM> {
+        let mut r = Reader { buf: input, pos: 0 };
+        if r.bytes(MAGIC.len())? != MAGIC {
+            return Err(Error::SnapshotError(String::from("不是快照文件")));
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(Error::SnapshotError(format!("不支持的快照版本 {}（当前为 {}）", version, VERSION)));
+        }
+        let len = r.usize()?;
+        let mut vm = VM::new(binary::decode(r.bytes(len)?)?);
+        vm.pc = r.usize()?;
+        vm.steps = r.u64()?;
+        vm.fp = r.usize()?;
+        vm.stack = r.values()?;
+        vm.locals = r.values()?;
+        vm.globals = r.values()?;
+        let len = r.usize()?;
+        for _ in 0..len {
+            let pc = r.usize()?;
+            let fp = r.usize()?;
+            vm.pc_stack.push((pc, fp));
+        }
+        let len = r.usize()?;
+        for _ in 0..len {
+            vm.handlers.push(Handler {
+                catch: r.usize()?,
+                stack_len: r.usize()?,
+                depth: r.usize()?,
+                fp: r.usize()?,
+                locals_len: r.usize()?,
+            });
+        }
+        vm.heap = Heap::load(&mut r)?;
+        // 原生函数的状态按编号恢复到默认的注册表中
+        let len = r.usize()?;
+        if len != vm.natives.len() {
+            return Err(Error::SnapshotError(format!("快照中有 {} 个原生函数，注册表中有 {} 个", len, vm.natives.len())));
+        }
+        for i in 0..len {
+            let state = r.values()?;
+            // 原生函数按默认状态的个数和类型读取状态
+            let default = vm.natives.state(i);
+            if state.len() != default.len() || state.iter().zip(default).any(|(a, b)| a.type_name() != b.type_name()) {
+                return Err(Error::SnapshotError(format!("原生函数 #{} 的状态与注册表不一致", i)));
+            }
+            vm.natives.set_state(i, state);
+        }
+        if r.pos != input.len() {
+            return Err(Error::SnapshotError(String::from("文件末尾有多余的数据")));
+        }
+        vm.check()?;
+        Ok(vm)
+    }
+    // 文件中的状态不可信：检查 pc、各层栈帧、操作数栈、异常处理程序和引用与模块、堆一致，
+    // 保证恢复后按校验过的字节码执行不会越界
+    fn check(&self) -> Result<()> {
+        let err = |msg: String| Err(Error::SnapshotError(msg));
+        let module = &self.module;
+        let len = module.code.len();
+        let heights = verify::stack_heights(module, &self.natives)?;
+        if self.globals.len() != module.globals.len() {
+            return err(format!("快照中有 {} 个全局变量，模块中有 {} 个", self.globals.len(), module.globals.len()));
+        }
+        for values in [&self.stack, &self.locals, &self.globals] {
+            self.heap.check_refs(values)?;
+        }
+        for i in 0..self.natives.len() {
+            self.heap.check_refs(self.natives.state(i))?;
+        }
+
+        // 每层栈帧所在的函数，第 0 层为全局代码；返回地址之前是调用下一层的 Call
+        let mut funcs = vec![None];
+        for (ret, _) in &self.pc_stack {
+            match ret.checked_sub(1).and_then(|call| module.code.get(call)) {
+                Some(OpCode::Call(func, _)) if *func < module.funcs.len() => funcs.push(Some(&module.funcs[*func])),
+                _ => return err(format!("返回地址 {} 之前不是 Call", ret)),
+            }
+        }
+        // 第 k 层栈帧的 pc 和局部变量的范围；第 k 层以下保存在 pc_stack[k] 中
+        let depth = self.pc_stack.len();
+        let mut bases = Vec::with_capacity(depth + 1);
+        let mut stack_len = 0;
+        for (k, func) in funcs.into_iter().enumerate() {
+            let (pc, fp) = if k < depth { self.pc_stack[k] } else { (self.pc, self.fp) };
+            let end = if k < depth { self.pc_stack.get(k + 1).map_or(self.fp, |(_, fp)| *fp) } else { self.locals.len() };
+            let name = func.map_or("<global>", |f| f.name.as_str());
+            let (start, size) = match func {
+                // 刚进入函数，还没有执行 EnterFrame
+                Some(f) if k == depth && pc == f.entry => (f.entry, f.arity),
+                Some(f) => (f.entry, f.nlocals),
+                None => (0, 0),
+            };
+            // 所在的段：从入口到下一个函数的入口；全局代码在第一个函数之前
+            let entries = module.funcs.iter().map(|f| f.entry);
+            let region_end = match func {
+                Some(_) => entries.filter(|entry| *entry > start).min(),
+                None => entries.min(),
+            }.unwrap_or(len);
+            // 程序已经结束时 pc 为代码末尾
+            let finished = depth == 0 && pc == len;
+            if !finished && !(start..region_end).contains(&pc) {
+                return err(format!("第 {} 层栈帧的 pc {} 不在 {} 内", k, pc, name));
+            }
+            // 各层的局部变量从 0 开始首尾相接
+            if (k == 0 && fp != 0) || fp > end || end > self.locals.len() || end - fp != size {
+                return err(format!("第 {} 层栈帧 ({}) 的局部变量 [{}, {}) 与槽位个数 {} 不一致", k, name, fp, end, size));
+            }
+            if finished {
+                break;
+            }
+            let Some(h) = heights[pc] else {
+                return err(format!("第 {} 层栈帧的 pc {} 不可达", k, pc));
+            };
+            bases.push((fp, end, stack_len, start, region_end));
+            // 调用者的栈中还没有压入返回值；返回地址也可能从别处跳转到达，这时之前的 Call 不一定可达
+            if k == depth {
+                stack_len += h;
+            } else if heights[pc - 1].is_some() {
+                stack_len += h - 1;
+            } else {
+                return err(format!("返回地址 {} 之前的 Call 不可达", pc));
+            }
+        }
+        if self.pc < len && stack_len != self.stack.len() {
+            return err(format!("操作数栈的高度应为 {}，实际为 {}", stack_len, self.stack.len()));
+        }
+
+        // 处理程序按登记的顺序，catch 块与登记时的栈帧在同一段中
+        let mut last = 0;
+        for h in &self.handlers {
+            let Some(&(fp, end, base, start, region_end)) = bases.get(h.depth).filter(|_| h.depth >= last) else {
+                return err(format!("处理程序的栈帧层数 {} 无效", h.depth));
+            };
+            last = h.depth;
+            let catch = (start..region_end).contains(&h.catch).then(|| heights[h.catch]).flatten();
+            if catch.is_none_or(|c| c == 0 || h.stack_len != base + c - 1) || h.fp != fp || h.locals_len != end {
+                return err(format!("处理程序 (catch {}) 与第 {} 层栈帧不一致", h.catch, h.depth));
+            }
+        }
+        Ok(())
+    }
+}
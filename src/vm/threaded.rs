@@ -0,0 +1,188 @@
+/*!
+ * 预解码的线索化分派：加载时把每条 OpCode 解码为处理函数指针和操作数，
+ * 执行时直接调用处理函数，省去每条指令的 match 分派和 OpCode 复制。
+ * 常用指令有专门的处理函数，其余指令回退到 VM::execute，语义与普通分派完全相同。
+ */
+
+use std::mem;
+
+use crate::error::Result;
+
+use super::value::Value;
+use super::module::Module;
+use super::{Cmp, OpCode, VM};
+
+// 处理函数，两个参数为解码后的操作数
+type Handler = fn(&mut VM, usize, usize) -> Result<()>;
+
+#[derive(Clone, Copy)]
+pub struct Inst {
+    handler: Handler,
+    a: usize,
+    b: usize,
+}
+
+impl std::fmt::Debug for Inst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inst").field("a", &self.a).field("b", &self.b).finish()
+    }
+}
+
+pub fn decode(module: &Module) -> Vec<Inst> {
+    module.code.iter().enumerate().map(|(pc, op)| {
+        let (handler, a, b): (Handler, usize, usize) = match *op {
+            OpCode::LoadConst(k) => (load_const, k as usize, 0),
+            OpCode::LoadGlobal(pos) => (load_global, pos, 0),
+            OpCode::LoadVar(pos) => (load_var, pos, 0),
+            OpCode::StoreVar(pos) => (store_var, pos, 0),
+            OpCode::TeeVar(pos) => (tee_var, pos, 0),
+            OpCode::IncVar(pos, k) => (inc_var, pos, k as usize),
+            OpCode::AddConst(k) => (add_const, k as usize, 0),
+            OpCode::BinOpAdd => (add, 0, 0),
+            OpCode::BinOpSub => (sub, 0, 0),
+            OpCode::BinOpMul => (mul, 0, 0),
+            OpCode::BinOpLt => (lt, 0, 0),
+            OpCode::BinOpEq => (eq, 0, 0),
+            OpCode::Jmp(next) => (jmp, next, 0),
+            OpCode::JmpTrue(next) => (jmp_true, next, 0),
+            OpCode::JmpFalse(next) => (jmp_false, next, 0),
+            OpCode::CmpJmp(cmp, next) => (cmp_jmp(cmp), next, 0),
+            // 解码时直接取出函数的入口地址
+            OpCode::Call(func, args_count) => (call, module.funcs[func].entry, args_count),
+            OpCode::Ret => (ret, 0, 0),
+            OpCode::Pop => (pop, 0, 0),
+            // 其余指令按原来的 OpCode 执行
+            _ => (generic, pc, 0),
+        };
+        Inst { handler, a, b }
+    }).collect()
+}
+
+impl VM {
+    pub(super) fn run_threaded(&mut self) -> Result<()> {
+        // 执行期间把解码结果取出，处理函数才能获得 VM 的可变引用
+        let insts = mem::take(&mut self.insts);
+        let res = self.dispatch(&insts);
+        self.insts = insts;
+        res
+    }
+    fn dispatch(&mut self, insts: &[Inst]) -> Result<()> {
+        while let Some(inst) = insts.get(self.pc) {
+            self.pc += 1;
+            self.steps += 1;
+            (inst.handler)(self, inst.a, inst.b)?;
+        }
+        Ok(())
+    }
+}
+
+fn generic(vm: &mut VM, pc: usize, _: usize) -> Result<()> {
+    let op = vm.module.code[pc];
+    vm.execute(op)
+}
+
+fn load_const(vm: &mut VM, k: usize, _: usize) -> Result<()> {
+    vm.push(Value::Int(k as i32));
+    Ok(())
+}
+
+fn load_global(vm: &mut VM, pos: usize, _: usize) -> Result<()> {
+    vm.push(vm.globals[pos]);
+    Ok(())
+}
+
+fn load_var(vm: &mut VM, pos: usize, _: usize) -> Result<()> {
+    let v = vm.locals[vm.fp + pos];
+    vm.push(v);
+    Ok(())
+}
+
+fn store_var(vm: &mut VM, pos: usize, _: usize) -> Result<()> {
+    let v = vm.pop();
+    vm.locals[vm.fp + pos] = v;
+    Ok(())
+}
+
+fn tee_var(vm: &mut VM, pos: usize, _: usize) -> Result<()> {
+    vm.locals[vm.fp + pos] = *vm.stack.last().unwrap();
+    Ok(())
+}
+
+fn inc_var(vm: &mut VM, pos: usize, k: usize) -> Result<()> {
+    let slot = vm.fp + pos;
+    vm.locals[slot] = VM::add_const(vm.locals[slot], k as i32)?;
+    Ok(())
+}
+
+fn add_const(vm: &mut VM, k: usize, _: usize) -> Result<()> {
+    let v = vm.pop();
+    let v = VM::add_const(v, k as i32)?;
+    vm.push(v);
+    Ok(())
+}
+
+fn add(vm: &mut VM, _: usize, _: usize) -> Result<()> {
+    vm.binop(OpCode::BinOpAdd)
+}
+
+fn sub(vm: &mut VM, _: usize, _: usize) -> Result<()> {
+    vm.binop(OpCode::BinOpSub)
+}
+
+fn mul(vm: &mut VM, _: usize, _: usize) -> Result<()> {
+    vm.binop(OpCode::BinOpMul)
+}
+
+fn lt(vm: &mut VM, _: usize, _: usize) -> Result<()> {
+    vm.binop(OpCode::BinOpLt)
+}
+
+fn eq(vm: &mut VM, _: usize, _: usize) -> Result<()> {
+    vm.binop(OpCode::BinOpEq)
+}
+
+fn jmp(vm: &mut VM, next: usize, _: usize) -> Result<()> {
+    vm.pc = next;
+    Ok(())
+}
+
+fn jmp_true(vm: &mut VM, next: usize, _: usize) -> Result<()> {
+    if vm.pop().truthy()? {
+        vm.pc = next;
+    }
+    Ok(())
+}
+
+fn jmp_false(vm: &mut VM, next: usize, _: usize) -> Result<()> {
+    if !vm.pop().truthy()? {
+        vm.pc = next;
+    }
+    Ok(())
+}
+
+// 每种比较方式一个处理函数，执行时不用再区分比较方式
+fn cmp_jmp(cmp: Cmp) -> Handler {
+    match cmp {
+        Cmp::Gt => |vm, next, _| vm.cmp_jmp(Cmp::Gt, next),
+        Cmp::Ge => |vm, next, _| vm.cmp_jmp(Cmp::Ge, next),
+        Cmp::Lt => |vm, next, _| vm.cmp_jmp(Cmp::Lt, next),
+        Cmp::Le => |vm, next, _| vm.cmp_jmp(Cmp::Le, next),
+        Cmp::Eq => |vm, next, _| vm.cmp_jmp(Cmp::Eq, next),
+        Cmp::Ne => |vm, next, _| vm.cmp_jmp(Cmp::Ne, next),
+    }
+}
+
+fn call(vm: &mut VM, next: usize, args_count: usize) -> Result<()> {
+    vm.call(next, args_count);
+    Ok(())
+}
+
+fn ret(vm: &mut VM, _: usize, _: usize) -> Result<()> {
+    vm.leave_frame();
+    Ok(())
+}
+
+fn pop(vm: &mut VM, _: usize, _: usize) -> Result<()> {
+    vm.pop();
+    Ok(())
+}
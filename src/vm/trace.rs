@@ -0,0 +1,117 @@
+/*!
+ * 虚拟机执行追踪：逐条记录执行的指令（pc、操作码、栈顶、栈帧深度）
+ */
+
+use std::fmt;
+use std::io::Write;
+
+use crate::error::Result;
+
+use super::value::Value;
+use super::OpCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,   // 便于阅读的文本
+    Json,   // 每行一个 JSON 对象 (JSONL)
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    // 只输出该函数（函数表中的编号）内的指令
+    func: Option<usize>,
+    // 只输出 [lo, hi) 范围内的指令
+    pc_range: Option<(usize, usize)>,
+    // 当前的函数调用链（函数编号）
+    calls: Vec<usize>,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("func", &self.func)
+            .field("pc_range", &self.pc_range)
+            .finish_non_exhaustive()
+    }
+}
+
+// 值在 JSON 中的表示：引用写为 {"ref":n}；NaN 和无穷大不是 JSON 中的数，写为字符串
+fn json(v: Value) -> String {
+    match v {
+        Value::Ref(r) => format!("{{\"ref\":{}}}", r),
+        Value::Float(f) if !f.is_finite() => format!("\"{:?}\"", f),
+        v => v.to_string(),
+    }
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            out,
+            format,
+            func: None,
+            pc_range: None,
+            calls: Vec::new(),
+        }
+    }
+    pub fn with_func(mut self, func: usize) -> Self {
+        self.func = Some(func);
+        self
+    }
+    pub fn with_pc_range(mut self, lo: usize, hi: usize) -> Self {
+        self.pc_range = Some((lo, hi));
+        self
+    }
+    fn current_func(&self) -> Option<usize> {
+        self.calls.last().copied()
+    }
+    fn accept(&self, pc: usize) -> bool {
+        if let Some(func) = self.func {
+            if self.current_func() != Some(func) {
+                return false;
+            }
+        }
+        if let Some((lo, hi)) = self.pc_range {
+            if pc < lo || pc >= hi {
+                return false;
+            }
+        }
+        true
+    }
+    // 在指令执行之前调用
+    pub fn record(&mut self, pc: usize, op: &OpCode, top: Option<Value>, depth: usize) -> Result<()> {
+        if self.accept(pc) {
+            let func = self.current_func();
+            match self.format {
+                TraceFormat::Text => {
+                    let top = top.map_or(String::from("-"), |v| v.to_string());
+                    let func = func.map_or(String::from("-"), |v| v.to_string());
+                    writeln!(self.out, "{:>6}  {:<24} top: {:<12} depth: {:<4} fn: {}", pc, format!("{:?}", op), top, depth, func)?;
+                },
+                TraceFormat::Json => {
+                    let top = top.map_or(String::from("null"), json);
+                    let func = func.map_or(String::from("null"), |v| v.to_string());
+                    writeln!(self.out, "{{\"pc\":{},\"op\":\"{:?}\",\"top\":{},\"depth\":{},\"fn\":{}}}", pc, op, top, depth, func)?;
+                },
+            }
+        }
+        match op {
+            OpCode::Call(func, _) => self.calls.push(*func),
+            OpCode::Ret => {
+                self.calls.pop();
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+    // 抛出异常时回退到 depth 层调用
+    pub fn unwind(&mut self, depth: usize) {
+        self.calls.truncate(depth);
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
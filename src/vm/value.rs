@@ -0,0 +1,170 @@
+/*!
+ * 虚拟机中栈、局部变量和全局变量保存的值，带有类型标记。
+ * 数组、结构体等对象保存在堆上，这里只保存其引用。
+ * 整数之间的运算走快速路径；布尔值参与算术运算时按 0/1 处理，整数与浮点数混合运算时提升为浮点数，
+ * 其他类型组合会产生运行时类型错误。
+ */
+
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+use super::OpCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i32),
+    Float(f64),
+    Ref(usize),     // 堆上对象的引用
+}
+
+// 参与算术运算的数
+#[derive(Clone, Copy)]
+enum Num {
+    Int(i32),
+    Float(f64),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "i32",
+            Value::Float(_) => "f64",
+            Value::Ref(_) => "ref",
+        }
+    }
+    // 作为条件使用时的真假，只接受布尔值和整数
+    pub fn truthy(&self) -> Result<bool> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            Value::Int(v) => Ok(*v != 0),
+            _ => Err(Error::TypeError(format!("条件的类型不能是 {}", self.type_name()))),
+        }
+    }
+    // 参与算术运算时的数。源语言中比较的结果就是整数 0/1（解释器和寄存器虚拟机都如此），
+    // 这里的布尔值只是比较指令的结果，因此同样按 0/1 参与算术和比较；null 和引用不是数
+    fn num(&self) -> Option<Num> {
+        match self {
+            Value::Bool(b) => Some(Num::Int(*b as i32)),
+            Value::Int(v) => Some(Num::Int(*v)),
+            Value::Float(v) => Some(Num::Float(*v)),
+            Value::Null | Value::Ref(_) => None,
+        }
+    }
+    // 输出到标准输出时的形式，布尔值与解释器一致输出 0/1
+    pub fn to_output(&self) -> String {
+        match self {
+            Value::Bool(b) => (*b as i32).to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn checked_neg(self) -> Result<Value> {
+        match self.num() {
+            Some(Num::Int(v)) => Ok(Value::Int(v.wrapping_neg())),
+            Some(Num::Float(v)) => Ok(Value::Float(-v)),
+            None => Err(Error::TypeError(format!("UniOpNeg 不支持 {}", self.type_name()))),
+        }
+    }
+    pub fn logical_not(self) -> Result<Value> {
+        Ok(Value::Bool(!self.truthy()?))
+    }
+
+    // 二元运算，op 为 BinOp* 指令
+    pub fn binop(op: OpCode, lhs: Value, rhs: Value) -> Result<Value> {
+        if let (Value::Int(a), Value::Int(b)) = (lhs, rhs) {
+            return int_binop(op, a, b);
+        }
+        match op {
+            OpCode::BinOpOr => return Ok(Value::Bool(lhs.truthy()? || rhs.truthy()?)),
+            OpCode::BinOpAnd => return Ok(Value::Bool(lhs.truthy()? && rhs.truthy()?)),
+            OpCode::BinOpEq => return Ok(Value::Bool(lhs.equals(&rhs))),
+            OpCode::BinOpNe => return Ok(Value::Bool(!lhs.equals(&rhs))),
+            _ => (),
+        }
+        match (lhs.num(), rhs.num()) {
+            (Some(Num::Int(a)), Some(Num::Int(b))) => int_binop(op, a, b),
+            (Some(a), Some(b)) => float_binop(op, a.to_f64(), b.to_f64()),
+            _ => Err(Error::TypeError(format!("{} 不支持的操作数类型: {} 和 {}", op.name(), lhs.type_name(), rhs.type_name()))),
+        }
+    }
+    fn equals(&self, other: &Value) -> bool {
+        match (self.num(), other.num()) {
+            (Some(Num::Int(a)), Some(Num::Int(b))) => a == b,
+            (Some(a), Some(b)) => a.to_f64() == b.to_f64(),
+            _ => self == other,
+        }
+    }
+}
+
+impl Num {
+    fn to_f64(self) -> f64 {
+        match self {
+            Num::Int(v) => v as f64,
+            Num::Float(v) => v,
+        }
+    }
+}
+
+fn int_binop(op: OpCode, a: i32, b: i32) -> Result<Value> {
+    let v = match op {
+        OpCode::BinOpAdd => Value::Int(a.wrapping_add(b)),
+        OpCode::BinOpSub => Value::Int(a.wrapping_sub(b)),
+        OpCode::BinOpMul => Value::Int(a.wrapping_mul(b)),
+        OpCode::BinOpDiv => {
+            if b == 0 {
+                return Err(Error::VMError(String::from("除数为 0")));
+            }
+            Value::Int(a.wrapping_div(b))
+        },
+        OpCode::BinOpMod => {
+            if b == 0 {
+                return Err(Error::VMError(String::from("除数为 0")));
+            }
+            Value::Int(a.wrapping_rem(b))
+        },
+        OpCode::BinOpGt => Value::Bool(a > b),
+        OpCode::BinOpGe => Value::Bool(a >= b),
+        OpCode::BinOpLt => Value::Bool(a < b),
+        OpCode::BinOpLe => Value::Bool(a <= b),
+        OpCode::BinOpEq => Value::Bool(a == b),
+        OpCode::BinOpNe => Value::Bool(a != b),
+        OpCode::BinOpOr => Value::Bool(a != 0 || b != 0),
+        OpCode::BinOpAnd => Value::Bool(a != 0 && b != 0),
+        _ => return Err(Error::VMError(format!("非法二元运算符 {:?}", op))),
+    };
+    Ok(v)
+}
+
+fn float_binop(op: OpCode, a: f64, b: f64) -> Result<Value> {
+    let v = match op {
+        OpCode::BinOpAdd => Value::Float(a + b),
+        OpCode::BinOpSub => Value::Float(a - b),
+        OpCode::BinOpMul => Value::Float(a * b),
+        OpCode::BinOpDiv => Value::Float(a / b),
+        OpCode::BinOpMod => Value::Float(a % b),
+        OpCode::BinOpGt => Value::Bool(a > b),
+        OpCode::BinOpGe => Value::Bool(a >= b),
+        OpCode::BinOpLt => Value::Bool(a < b),
+        OpCode::BinOpLe => Value::Bool(a <= b),
+        _ => return Err(Error::VMError(format!("非法二元运算符 {:?}", op))),
+    };
+    Ok(v)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::Ref(r) => write!(f, "#{}", r),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+/*!
+ * 字节码校验：在执行之前检查模块，拒绝会让虚拟机越界或行为未定义的代码。
+ *
+ * 代码按函数表划分为若干段：第一个函数入口之前是全局代码，之后每个函数从入口到下一个函数入口。
+ * 对每一段从入口开始沿所有路径模拟操作数栈的高度（函数入口处为 0），检查
+ *   - 跳转目标和 catch 地址在本段内（Jmp 可以跳到代码末尾，表示结束程序），Call 的函数编号和参数个数正确；
+ *   - 函数以 EnterFrame 开始，局部变量的槽位小于栈帧的槽位个数，全局变量的编号小于全局变量表的长度；
+ *   - 栈不会下溢，每个汇合点从不同路径到达时栈高度相同；
+ *   - 每条路径以 Ret 或 Throw 结束，或者执行到代码末尾；不能落入下一个函数；
+ *     Ret 时栈中恰好是返回值（没有返回值的函数为 null），Call 和 CallNative 都压入一个值。
+ *
+ * 编译器不生成的 MakeClosure、StorePC 和 LoadPC 视为错误。不可达的指令不检查。
+ * 每一段只报告遇到的第一个问题（之后的栈高度已不可信），所有段的问题一起返回。
+ */
+
+use crate::error::{Error, Result};
+use crate::native::Registry;
+
+use super::module::Module;
+use super::OpCode;
+
+// 一段代码：[start, end)，func 为函数表中的编号，全局代码为 None
+struct Region {
+    func: Option<usize>,
+    start: usize,
+    end: usize,
+}
+
+pub fn verify(module: &Module, natives: &Registry) -> Result<()> {
+    stack_heights(module, natives).map(|_| ())
+}
+
+// 校验模块，返回每条指令执行前操作数栈在所在栈帧中的高度，不可达的指令为 None；恢复快照时用来检查栈
+pub fn stack_heights(module: &Module, natives: &Registry) -> Result<Vec<Option<usize>>> {
+    if module.link.is_some() {
+        return Err(Error::VerifyError(vec![String::from("这是目标模块，需要先链接")]));
+    }
+    let len = module.code.len();
+    let mut errors = Vec::new();
+
+    let mut entries: Vec<(usize, usize)> = module.funcs.iter().enumerate().map(|(i, f)| (f.entry, i)).collect();
+    entries.sort();
+    for (i, f) in module.funcs.iter().enumerate() {
+        if f.entry >= len {
+            errors.push(format!("函数 {} 的入口地址 {} 超出代码长度 {}", f.name, f.entry, len));
+        }
+        if f.arity > f.nlocals {
+            errors.push(format!("函数 {} 的参数个数 {} 大于栈帧的槽位个数 {}", f.name, f.arity, f.nlocals));
+        }
+        if let Some((_, j)) = entries.iter().find(|(entry, j)| *entry == f.entry && *j < i) {
+            errors.push(format!("函数 {} 和 {} 的入口地址相同: {}", module.funcs[*j].name, f.name, f.entry));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::VerifyError(errors));
+    }
+
+    let mut regions = Vec::new();
+    let first = entries.first().map_or(len, |(entry, _)| *entry);
+    regions.push(Region { func: None, start: 0, end: first });
+    for (i, (entry, func)) in entries.iter().enumerate() {
+        let end = entries.get(i + 1).map_or(len, |(next, _)| *next);
+        regions.push(Region { func: Some(*func), start: *entry, end });
+    }
+    // 各段按地址顺序排列，拼起来覆盖全部代码
+    let mut heights = Vec::with_capacity(len);
+    for region in &regions {
+        match verify_region(module, natives, region) {
+            Ok(region_heights) => heights.extend(region_heights),
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(heights)
+    } else {
+        Err(Error::VerifyError(errors))
+    }
+}
+
+// 校验一段代码，返回段内每条指令执行前的栈高度，或者遇到的第一个问题
+fn verify_region(module: &Module, natives: &Registry, region: &Region) -> std::result::Result<Vec<Option<usize>>, String> {
+    let len = module.code.len();
+    let func = region.func.map(|i| &module.funcs[i]);
+    let nlocals = func.map_or(0, |f| f.nlocals);
+    let name = func.map_or("<global>", |f| f.name.as_str());
+    // 出错的位置：所在的函数、pc、源代码位置和指令
+    let at = |pc: usize| match module.debug {
+        Some(_) => format!("{} pc {} ({}) {}", name, pc, module.location(pc), module.describe(pc)),
+        None => format!("{} pc {} {}", name, pc, module.describe(pc)),
+    };
+
+    // 函数从 EnterFrame 开始，之后局部变量的槽位才有效
+    if func.is_some() && !matches!(module.code[region.start], OpCode::EnterFrame(_)) {
+        return Err(format!("{}: 函数入口必须是 EnterFrame", at(region.start)));
+    }
+
+    let mut heights: Vec<Option<usize>> = vec![None; region.end - region.start];
+    let mut work = Vec::new();
+    if region.start < region.end {
+        heights[0] = Some(0);
+        work.push(region.start);
+    }
+    // 从 pc 以高度 h 到达 next；fall 表示顺序执行到下一条指令，否则为跳转
+    let reach = |heights: &mut Vec<Option<usize>>, work: &mut Vec<usize>, pc: usize, next: usize, h: usize, fall: bool| {
+        // 到达代码末尾，程序结束
+        if next == len && (!fall || region.end == len) {
+            return Ok(());
+        }
+        // 顺序执行或跳到本段的结尾，之后是下一个函数
+        if next == region.end {
+            return Err(if fall {
+                format!("{}: {} 执行完后落入下一个函数，缺少 Ret", at(pc), name)
+            } else {
+                format!("{}: 跳到 {} 的结尾 {}，缺少 Ret", at(pc), name, next)
+            });
+        }
+        if next < region.start || next > region.end {
+            return Err(format!("{}: 目标地址 {} 不在 {} 内 [{}, {})", at(pc), next, name, region.start, region.end));
+        }
+        match heights[next - region.start] {
+            None => {
+                heights[next - region.start] = Some(h);
+                work.push(next);
+                Ok(())
+            },
+            Some(old) if old != h => Err(format!("{}: 到达 pc {} 时栈高度为 {}，另一条路径为 {}", at(pc), next, h, old)),
+            Some(_) => Ok(()),
+        }
+    };
+
+    while let Some(pc) = work.pop() {
+        let h = heights[pc - region.start].unwrap();
+        let op = module.code[pc];
+        let slot = |slot: usize| if slot < nlocals {
+            Ok(())
+        } else {
+            Err(format!("{}: 槽位 {} 超出栈帧的槽位个数 {}", at(pc), slot, nlocals))
+        };
+        let global = |pos: usize| if pos < module.globals.len() {
+            Ok(())
+        } else {
+            Err(format!("{}: 全局变量编号 {} 超出全局变量表的长度 {}", at(pc), pos, module.globals.len()))
+        };
+        // (弹出的个数, 压入的个数)
+        let (pop, push) = match op {
+            OpCode::LoadConst(_) | OpCode::LoadTrue | OpCode::LoadFalse | OpCode::LoadNull | OpCode::GetInt => (0, 1),
+            OpCode::LoadGlobal(pos) => {
+                global(pos)?;
+                (0, 1)
+            },
+            OpCode::StoreGlobal(pos) => {
+                global(pos)?;
+                (1, 0)
+            },
+            OpCode::LoadVar(pos) => {
+                slot(pos)?;
+                (0, 1)
+            },
+            OpCode::StoreVar(pos) => {
+                slot(pos)?;
+                (1, 0)
+            },
+            OpCode::TeeVar(pos) => {
+                slot(pos)?;
+                (1, 1)
+            },
+            OpCode::IncVar(pos, _) => {
+                slot(pos)?;
+                (0, 0)
+            },
+            OpCode::BinOpAdd | OpCode::BinOpSub | OpCode::BinOpMul | OpCode::BinOpDiv | OpCode::BinOpMod | OpCode::BinOpGt
+                | OpCode::BinOpGe | OpCode::BinOpLt | OpCode::BinOpLe | OpCode::BinOpEq | OpCode::BinOpNe
+                | OpCode::BinOpOr | OpCode::BinOpAnd | OpCode::ArrayGet => (2, 1),
+            OpCode::UniOpNot | OpCode::UniOpNeg | OpCode::AddConst(_) | OpCode::ArrayLen | OpCode::GetField(_) => (1, 1),
+            OpCode::Jmp(_) | OpCode::PrintNewline | OpCode::PopHandler => (0, 0),
+            OpCode::JmpTrue(_) | OpCode::JmpFalse(_) | OpCode::PrintItem | OpCode::Pop | OpCode::Throw => (1, 0),
+            OpCode::CmpJmp(..) | OpCode::SetField(_) => (2, 0),
+            OpCode::ArraySet => (3, 0),
+            OpCode::NewArray(n) | OpCode::NewStruct(n) => (n, 1),
+            OpCode::EnterFrame(size) => {
+                if pc != region.start || region.func.is_none() {
+                    return Err(format!("{}: EnterFrame 只能出现在函数入口", at(pc)));
+                }
+                if size != nlocals {
+                    return Err(format!("{}: 槽位个数与函数表中的 {} 不一致", at(pc), nlocals));
+                }
+                (0, 0)
+            },
+            OpCode::Call(callee, argc) => {
+                let Some(callee) = module.funcs.get(callee) else {
+                    return Err(format!("{}: 函数编号 {} 超出函数表的长度 {}", at(pc), callee, module.funcs.len()));
+                };
+                if argc != callee.arity {
+                    return Err(format!("{}: {} 需要 {} 个参数，实际为 {} 个", at(pc), callee.name, callee.arity, argc));
+                }
+                (argc, 1)
+            },
+            OpCode::CallNative(index, argc) => {
+                let Some(native) = natives.get(index) else {
+                    return Err(format!("{}: 原生函数编号 {} 超出注册表的长度 {}", at(pc), index, natives.len()));
+                };
+                if argc != native.arity() {
+                    return Err(format!("{}: {} 需要 {} 个参数，实际为 {} 个", at(pc), native.name, native.arity(), argc));
+                }
+                (argc, 1)
+            },
+            OpCode::Ret => {
+                if region.func.is_none() {
+                    return Err(format!("{}: 全局代码中不能有 Ret", at(pc)));
+                }
+                if h != 1 {
+                    return Err(format!("{}: 返回时栈高度应为 1，实际为 {}", at(pc), h));
+                }
+                (1, 0)
+            },
+            OpCode::PushHandler(_) => (0, 0),
+            // 编译器不生成这些指令；StorePC 和 LoadPC 直接操作 PC 栈，无法保证与 Call/Ret 配对
+            OpCode::MakeClosure | OpCode::StorePC | OpCode::LoadPC => return Err(format!("{}: 不支持的指令", at(pc))),
+        };
+        if h < pop {
+            return Err(format!("{}: 需要 {} 个操作数，栈中只有 {} 个", at(pc), pop, h));
+        }
+        let next_h = h - pop + push;
+        match op {
+            OpCode::Jmp(t) => reach(&mut heights, &mut work, pc, t, next_h, false)?,
+            OpCode::JmpTrue(t) | OpCode::JmpFalse(t) | OpCode::CmpJmp(_, t) => {
+                reach(&mut heights, &mut work, pc, t, next_h, false)?;
+                reach(&mut heights, &mut work, pc, pc + 1, next_h, true)?;
+            },
+            // 抛出异常时栈恢复到进入 try 块时的高度，再压入异常的值
+            OpCode::PushHandler(catch) => {
+                reach(&mut heights, &mut work, pc, catch, h + 1, false)?;
+                reach(&mut heights, &mut work, pc, pc + 1, next_h, true)?;
+            },
+            OpCode::Ret | OpCode::Throw => (),
+            _ => reach(&mut heights, &mut work, pc, pc + 1, next_h, true)?,
+        }
+    }
+    Ok(heights)
+}
@@ -0,0 +1,76 @@
+// 反汇编再汇编是无损的：得到的 .cby 与原来的逐字节相同（包括调试信息和目标模块的链接信息）
+
+mod common;
+
+use common::{TempDir, cilly};
+
+const CORPUS: &[(&str, &str)] = &[
+    ("loops", include_str!("corpus/loops.cil")),
+    ("algebra", include_str!("corpus/algebra.cil")),
+    ("trycatch", include_str!("corpus/trycatch.cil")),
+    ("recurse", include_str!("corpus/recurse.cil")),
+];
+
+// 重名的全局变量只能以编号引用
+const DUPLICATE: &str = r#"
+var a: i32 = 1;
+var a: i32 = 2;
+
+fn main() {
+    print(a);
+}
+"#;
+
+// 分别翻译为目标模块后链接
+const LIB: &str = r#"
+var base: i32 = 5;
+
+fn twice(x: i32) -> i32 {
+    return x * 2 + base;
+}
+"#;
+
+const APP: &str = r#"
+fn main() {
+    print(twice(4));
+}
+"#;
+
+// 反汇编 name.cby 后重新汇编，比较两个文件
+fn round_trip(dir: &TempDir, name: &str) {
+    let original = dir.read(format!("{name}.cby"));
+    let text = cilly(dir, &["--disasm", &format!("{name}.cby")]);
+    dir.write(format!("{name}.cas"), &text);
+    cilly(dir, &["--asm", &format!("{name}.cas")]);
+    assert!(dir.read(format!("{name}.cby")) == original, "{name}");
+    assert_eq!(cilly(dir, &["--disasm", &format!("{name}.cby")]), text, "{name}");
+}
+
+#[test]
+fn asm_round_trip() {
+    let dir = TempDir::new("asm");
+
+    let mut sources = CORPUS.to_vec();
+    sources.push(("dup", DUPLICATE));
+    for (name, source) in sources {
+        let file = format!("{name}.cil");
+        dir.write(&file, source);
+        for flags in [&[][..], &["--no-peephole"][..], &["--object"][..], &["-O2"][..]] {
+            let mut args = vec!["--translate", &file];
+            args.extend(flags);
+            cilly(&dir, &args);
+            round_trip(&dir, name);
+        }
+    }
+
+    // 链接后的模块有多个源文件
+    dir.write("lib.cil", LIB);
+    dir.write("app.cil", APP);
+    cilly(&dir, &["--translate", "lib.cil", "--object"]);
+    cilly(&dir, &["--translate", "app.cil", "--object"]);
+    round_trip(&dir, "lib");
+    round_trip(&dir, "app");
+    cilly(&dir, &["--link", "lib.cby", "app.cby", "-o", "linked.cby"]);
+    round_trip(&dir, "linked");
+    assert_eq!(cilly(&dir, &["--vmrun", "linked.cby"]), "13\n");
+}
@@ -0,0 +1,66 @@
+// tests/corpus 中的程序在栈虚拟机的两种分派方式和寄存器虚拟机上输出相同；
+// 每执行一定步数暂停并保存快照、在新的进程中恢复，拼起来的输出也与不中断的执行相同
+
+use std::path::Path;
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+const CORPUS: &[(&str, &str)] = &[
+    ("loops", include_str!("corpus/loops.cil")),
+    ("algebra", include_str!("corpus/algebra.cil")),
+    ("trycatch", include_str!("corpus/trycatch.cil")),
+    ("recurse", include_str!("corpus/recurse.cil")),
+    ("hoistnull", include_str!("corpus/hoistnull.cil")),
+];
+
+// 寄存器虚拟机不支持异常
+const NO_REGVM: &[&str] = &["trycatch"];
+
+// 每次恢复执行的步数
+const FUEL: &str = "200";
+
+// 每次执行 FUEL 步，暂停后从快照恢复，直到程序结束，返回所有输出
+fn run_in_steps(dir: &Path, object: &str, flags: &[&str]) -> String {
+    let mut args = vec!["--vmrun", object];
+    let mut stdout = String::new();
+    for _ in 0..10000 {
+        args.extend(["--fuel", FUEL, "--snapshot", "vm.snap"]);
+        args.extend(flags);
+        let output = run(dir, &args);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        stdout += &String::from_utf8(output.stdout).unwrap();
+        if !String::from_utf8_lossy(&output.stderr).contains("paused (OutOfFuel)") {
+            return stdout;
+        }
+        args = vec!["--resume", "vm.snap"];
+    }
+    panic!("{object} 没有结束");
+}
+
+#[test]
+fn backends() {
+    let dir = TempDir::new("backends");
+
+    for (name, source) in CORPUS {
+        let file = format!("{name}.cil");
+        let object = format!("{name}.cby");
+        dir.write(&file, source);
+        cilly(&dir, &["--translate", &file]);
+        let expect = cilly(&dir, &["--vmrun", &object]);
+
+        if !NO_REGVM.contains(name) {
+            assert_eq!(cilly(&dir, &["--regrun", &file]), expect, "{name} --regrun");
+        }
+        for flags in [&[][..], &["-O2"][..]] {
+            let mut args = vec!["--translate", &file];
+            args.extend(flags);
+            cilly(&dir, &args);
+            assert_eq!(cilly(&dir, &["--vmrun", &object]), expect, "{name} {flags:?}");
+            assert_eq!(cilly(&dir, &["--vmrun", &object, "--threaded"]), expect, "{name} {flags:?} --threaded");
+            assert_eq!(run_in_steps(&dir, &object, &[]), expect, "{name} {flags:?} --fuel");
+            assert_eq!(run_in_steps(&dir, &object, &["--threaded"]), expect, "{name} {flags:?} --fuel --threaded");
+        }
+    }
+}
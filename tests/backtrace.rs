@@ -0,0 +1,69 @@
+// 运行时错误的调用栈回溯：有调试信息时每一层（包括全局初始化代码调用 main 的一层）都报告 文件:行号，直接翻译和经过中间表示的结果相同
+
+use std::path::Path;
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+const SOURCE: &str = r#"var g: i32 = 3;
+
+fn f(x: i32) -> i32 {
+    return 10 / x;
+}
+
+fn main() {
+    print(g);
+    print(f(g - 3));
+}
+"#;
+
+// 分成两个模块，main 所在的模块在后
+const LIB: &str = r#"fn f(x: i32) -> i32 {
+    return 10 / x;
+}
+"#;
+
+const MAIN: &str = r#"var g: i32 = 0;
+
+fn main() {
+    print(f(g));
+}
+"#;
+
+// 执行失败，返回标准错误中 "at" 开头的行
+fn frames(dir: &Path, args: &[&str]) -> Vec<String> {
+    let output = run(dir, args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("除数为 0"), "{}", stderr);
+    stderr.lines().filter_map(|l| l.trim().strip_prefix("at ")).map(String::from).collect()
+}
+
+#[test]
+fn backtrace() {
+    let dir = TempDir::new("backtrace");
+    dir.write("b.cil", SOURCE);
+
+    // 经过中间表示（包括优化之后）生成的代码也有行号表
+    let expect = ["f (b.cil:4): BinOpDiv", "main (b.cil:9)", "<global> (b.cil:7)"];
+    for flags in [&[][..], &["--ir"][..], &["-O1"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "b.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(frames(&dir, &["--vmrun", "b.cby"]), expect, "{:?}", flags);
+        assert_eq!(frames(&dir, &["--vmrun", "b.cby", "--threaded"]), expect, "{:?}", flags);
+    }
+}
+
+#[test]
+fn linked_backtrace() {
+    let dir = TempDir::new("backtrace-link");
+    dir.write("lib.cil", LIB);
+    dir.write("main.cil", MAIN);
+
+    cilly(&dir, &["--translate", "lib.cil", "--object"]);
+    cilly(&dir, &["--translate", "main.cil", "--object"]);
+    cilly(&dir, &["--link", "lib.cby", "main.cby", "-o", "out.cby"]);
+    assert_eq!(frames(&dir, &["--vmrun", "out.cby"]), ["f (lib.cil:2): BinOpDiv", "main (main.cil:4)", "<global> (main.cil:3)"]);
+}
@@ -0,0 +1,75 @@
+// break 和 continue 跳出 try 块时移除登记的异常处理程序：之后抛出的异常由外层的 try 处理，
+// 不在任何 try 中的异常不会被已经离开的 catch 捕获；循环外的 break 和 continue 是翻译错误
+
+use std::process::Output;
+
+mod common;
+
+use common::{TempDir, run};
+
+// 循环中 continue 和 break 离开内层的 try 共 500 次，之后的 throw 由外层的 try 捕获；
+// 最后的 throw 不在任何 try 中
+const SOURCE: &str = r#"fn main() {
+    var i: i32 = 0;
+    var caught: i32 = 0;
+    try {
+        while(i < 1000) {
+            i = i + 1;
+            try {
+                if(i % 2 == 0) continue;
+                if(i == 999) break;
+            } catch(e) {
+                caught = caught + 1;
+            }
+        }
+        throw i;
+    } catch(e) {
+        print(e, caught);
+    }
+    while(1) {
+        try {
+            break;
+        } catch(e) {
+            print(-1);
+        }
+    }
+    throw 5;
+}
+"#;
+
+#[test]
+fn leave_try() {
+    let dir = TempDir::new("break-try");
+    dir.write("bc.cil", SOURCE);
+    let check = |output: Output| {
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "999\n0\n");
+        assert!(String::from_utf8(output.stderr).unwrap().starts_with("uncaught exception: 5\n"));
+    };
+    check(run(&dir, &["--static", "bc.cil"]));
+    for flags in [&[][..], &["--no-peephole"][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "bc.cil"];
+        args.extend(flags);
+        assert!(run(&dir, &args).status.success());
+        check(run(&dir, &["--vmrun", "bc.cby"]));
+        check(run(&dir, &["--vmrun", "bc.cby", "--threaded"]));
+    }
+}
+
+#[test]
+fn outside_loop() {
+    let dir = TempDir::new("break-outside");
+    for stmt in ["break", "continue"] {
+        dir.write("o.cil", format!("fn main() {{\n    {stmt};\n}}\n"));
+        for flags in [&[][..], &["--ir"][..]] {
+            let mut args = vec!["--translate", "o.cil"];
+            args.extend(flags);
+            let output = run(&dir, &args);
+            assert!(!output.status.success(), "{stmt} {flags:?}");
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.contains(&format!("{stmt} 不在循环中")), "{stmt} {flags:?}: {stderr}");
+        }
+        let output = run(&dir, &["--regrun", "o.cil"]);
+        assert!(String::from_utf8(output.stderr).unwrap().contains(&format!("{stmt} 不在循环中")), "{stmt} --regrun");
+    }
+}
@@ -0,0 +1,105 @@
+// 二进制字节码文件：编码再解码是无损的（包括负数常量）；文件被截断、损坏、版本不兼容或末尾有多余数据时
+// 报告明确的错误，而不是崩溃或执行错误的代码
+
+use cilly::bytecode_translation::binary::{decode, encode, MAGIC, VERSION};
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::error::Error;
+use cilly::vm::module::Module;
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+const SOURCE: &str = r#"
+var base: i32 = -2147483647 - 1;
+
+fn f(x: i32) -> i32 {
+    return x * -7 + 300000;
+}
+
+fn main() {
+    print(f(-5), base, -1);
+}
+"#;
+
+const EXPECT: &str = "300035\n-2147483648\n-1\n";
+
+fn module() -> Module {
+    let ast = CompUnitParser::new().parse(SOURCE).unwrap();
+    translate_module(&mut ast.clone(), &mut Environment::new()).unwrap()
+}
+
+// 解码失败，返回错误信息
+fn error(bytes: &[u8]) -> String {
+    match decode(bytes) {
+        Err(Error::BytecodeError(msg)) => msg,
+        res => panic!("{:?}", res.map(|m| m.code)),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let module = module();
+    let bytes = encode(&module);
+    let decoded = decode(&bytes).unwrap();
+    assert_eq!(format!("{:?}", decoded.code), format!("{:?}", module.code));
+    assert_eq!(encode(&decoded), bytes);
+}
+
+#[test]
+fn corrupt() {
+    let bytes = encode(&module());
+
+    // 任意长度的截断都报错
+    for len in 0..bytes.len() {
+        let msg = error(&bytes[..len]);
+        let expect = if len < MAGIC.len() {
+            "不是字节码文件"
+        } else if len < MAGIC.len() + 6 {
+            "文件头不完整"
+        } else {
+            "文件不完整"
+        };
+        assert!(msg.starts_with(expect), "{len}: {msg}");
+    }
+
+    // 数据中任意一个字节被修改，校验和都不匹配
+    for pos in MAGIC.len() + 6..bytes.len() - 4 {
+        let mut bad = bytes.clone();
+        bad[pos] ^= 0x20;
+        assert_eq!(error(&bad), "校验和不匹配，文件已损坏", "{pos}");
+    }
+
+    let mut bad = bytes.clone();
+    bad[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    assert_eq!(error(&bad), format!("不支持的字节码版本 {}（当前为 {}）", VERSION + 1, VERSION));
+
+    let mut bad = bytes.clone();
+    bad.push(0);
+    assert_eq!(error(&bad), "文件末尾有多余的数据");
+}
+
+#[test]
+fn load() {
+    let dir = TempDir::new("bytecode");
+    dir.write("b.cil", SOURCE);
+
+    // 二进制和文本形式执行的结果相同
+    for flags in [&[][..], &["--text"][..]] {
+        let mut args = vec!["--translate", "b.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "b.cby"]), EXPECT, "{flags:?}");
+    }
+
+    // 被截断的文件不执行任何代码
+    cilly(&dir, &["--translate", "b.cil"]);
+    let bytes = dir.read("b.cby");
+    dir.write("b.cby", &bytes[..bytes.len() - 1]);
+    let output = run(&dir, &["--vmrun", "b.cby"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("文件不完整"));
+}
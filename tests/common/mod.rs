@@ -0,0 +1,65 @@
+// 集成测试共用的辅助函数：在临时目录中执行 cilly
+// 每个测试只用到其中一部分
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+// 临时目录 cilly-<name>-<pid>，离开作用域时删除，断言失败时也不会留下
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("cilly-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+    pub fn write(&self, file: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+        fs::write(self.0.join(file), contents).unwrap();
+    }
+    pub fn read(&self, file: impl AsRef<Path>) -> Vec<u8> {
+        fs::read(self.0.join(file)).unwrap()
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// 在 dir 中执行 cilly，不检查是否成功
+pub fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cilly")).current_dir(dir).args(args).output().unwrap()
+}
+
+// input 为标准输入的内容
+pub fn run_with_input(dir: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cilly")).current_dir(dir).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// 执行成功，返回完整的输出
+pub fn success(dir: &Path, args: &[&str]) -> Output {
+    let output = run(dir, args);
+    assert!(output.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+// 执行成功，返回标准输出
+pub fn cilly(dir: &Path, args: &[&str]) -> String {
+    String::from_utf8(success(dir, args).stdout).unwrap()
+}
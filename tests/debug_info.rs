@@ -0,0 +1,68 @@
+// 调试信息：行号表把每条指令对应到源代码的行，局部变量表按指令范围给出槽位的名字（同名变量遮蔽时各自有效）；
+// 没有调试信息时退回到 pc 和槽位编号；经过中间表示生成的代码只有行号表
+
+use cilly::bytecode_translation::binary::decode;
+use cilly::vm::module::Module;
+use cilly::vm::OpCode;
+
+mod common;
+
+use common::{TempDir, cilly};
+
+const SOURCE: &str = r#"fn v() {
+}
+
+fn main() {
+    var count: i32 = 1;
+    {
+        var count: i32 = v();
+        count = count + 1;
+    }
+    print(count);
+}
+"#;
+
+// 第 8 行 count = count + 1 中的加法
+fn is_add(op: &OpCode) -> bool {
+    matches!(op, OpCode::BinOpAdd | OpCode::AddConst(_) | OpCode::IncVar(..))
+}
+
+// 直接翻译时局部变量都有名字，经过中间表示时只有行号表
+fn check(module: &Module, flags: &[&str]) {
+    let add = module.code.iter().position(is_add).unwrap_or_else(|| panic!("{flags:?} 没有加法"));
+    assert_eq!(module.location(add), "d.cil:8", "{flags:?}");
+    let mut stripped = module.clone();
+    stripped.debug = None;
+    assert_eq!(stripped.location(add), format!("pc {add}"));
+    if flags.contains(&"--ir") || flags.contains(&"-O2") {
+        return;
+    }
+
+    let mut slots = Vec::new();
+    for (pc, op) in module.code.iter().enumerate() {
+        if let OpCode::LoadVar(slot) | OpCode::StoreVar(slot) | OpCode::TeeVar(slot) = op {
+            assert!(module.describe(pc).ends_with("(count)"), "{flags:?} {pc}: {}", module.describe(pc));
+            slots.push(*slot);
+        }
+    }
+    // 两个 count 在不同的槽位
+    slots.sort();
+    slots.dedup();
+    assert_eq!(slots.len(), 2, "{flags:?}");
+
+    // 去掉调试信息后以槽位编号表示
+    let store = module.code.iter().position(|op| matches!(op, OpCode::StoreVar(_))).unwrap();
+    assert!(stripped.describe(store).contains("(#"), "{}", stripped.describe(store));
+}
+
+#[test]
+fn debug_info() {
+    let dir = TempDir::new("debug-info");
+    dir.write("d.cil", SOURCE);
+    for flags in [&[][..], &["--no-peephole"][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "d.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        check(&decode(&dir.read("d.cby")).unwrap(), flags);
+    }
+}
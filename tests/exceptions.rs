@@ -0,0 +1,75 @@
+// 异常：throw 从多层调用中展开到最近的 try，catch 之后调用者的局部变量和循环不受影响；
+// 没有被捕获的异常结束程序，在标准错误中打印异常的值和调用栈，解释器和虚拟机的调用栈一致
+
+use std::path::Path;
+
+mod common;
+
+use common::{TempDir, run};
+
+const SOURCE: &str = r#"fn dive(n: i32) -> i32 {
+    var local: i32 = n * 2;
+    if(n == 0) {
+        throw 7;
+    }
+    return dive(n - 1) + local;
+}
+
+fn safe(n: i32) -> i32 {
+    var keep: i32 = n + 100;
+    try {
+        dive(n);
+    } catch(e) {
+        return keep + e;
+    }
+    return -1;
+}
+
+fn main() {
+    var i: i32 = 0;
+    while(i < 3) {
+        print(safe(i * 5));
+        i = i + 1;
+    }
+    try {
+        throw 1;
+    } catch(e) {
+        print(e);
+    }
+    print(dive(2));
+}
+"#;
+
+// 执行失败，检查已经输出的内容，返回标准错误的各行
+fn uncaught(dir: &Path, args: &[&str]) -> Vec<String> {
+    let output = run(dir, args);
+    assert!(!output.status.success(), "{args:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "107\n112\n117\n1\n", "{args:?}");
+    String::from_utf8(output.stderr).unwrap().lines().map(String::from).collect()
+}
+
+#[test]
+fn exceptions() {
+    let dir = TempDir::new("exceptions");
+    dir.write("e.cil", SOURCE);
+
+    // 调用栈从抛出处开始，到全局代码为止
+    let stderr = uncaught(&dir, &["--static", "e.cil"]);
+    assert_eq!(stderr, ["uncaught exception: 7", "    at dive", "    at dive", "    at dive", "    at main", "    at <global>"]);
+
+    let expect = [
+        "uncaught exception: 7",
+        "    at dive (e.cil:4)",
+        "    at dive (e.cil:6)",
+        "    at dive (e.cil:6)",
+        "    at main (e.cil:30)",
+        "    at <global> (e.cil:19)",
+    ];
+    for flags in [&[][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "e.cil"];
+        args.extend(flags);
+        assert!(run(&dir, &args).status.success());
+        assert_eq!(uncaught(&dir, &["--vmrun", "e.cby"]), expect, "{flags:?}");
+        assert_eq!(uncaught(&dir, &["--vmrun", "e.cby", "--threaded"]), expect, "{flags:?}");
+    }
+}
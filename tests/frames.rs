@@ -0,0 +1,75 @@
+// 局部变量放在每次调用的一个栈帧中：块内同名变量遮蔽外层变量，块结束后槽位可以复用；
+// 递归调用各自有独立的栈帧，从嵌套的块和循环中返回后调用者的变量不受影响
+
+mod common;
+
+use common::{TempDir, cilly};
+
+const SOURCE: &str = r#"
+fn find(n: i32) -> i32 {
+    var i: i32 = 0;
+    while(i < n) {
+        var x: i32 = i * i;
+        {
+            var x: i32 = x + 1;
+            if(x > 20) {
+                var r: i32 = x;
+                return r;
+            }
+        }
+        i = i + 1;
+    }
+    return -1;
+}
+
+fn depth(n: i32) -> i32 {
+    var a: i32 = n;
+    if(n > 0) {
+        var a: i32 = depth(n - 1) + 1;
+        return a;
+    }
+    return a;
+}
+
+fn main() {
+    var x: i32 = 1;
+    {
+        var x: i32 = 2;
+        print(x);
+    }
+    print(x);
+    print(find(10));
+    print(find(3));
+    print(depth(5));
+    var k: i32 = 0;
+    while(k < 3) {
+        print(find(k + 5));
+        k = k + 1;
+    }
+}
+"#;
+
+const EXPECT: &str = "2\n1\n26\n-1\n5\n-1\n26\n26\n";
+
+#[test]
+fn frames() {
+    let dir = TempDir::new("frames");
+    dir.write("f.cil", SOURCE);
+    assert_eq!(cilly(&dir, &["--static", "f.cil"]), EXPECT);
+    assert_eq!(cilly(&dir, &["--regrun", "f.cil"]), EXPECT);
+    for flags in [&[][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "f.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "f.cby"]), EXPECT, "{:?}", flags);
+        assert_eq!(cilly(&dir, &["--vmrun", "f.cby", "--threaded"]), EXPECT, "{:?}", flags);
+    }
+
+    // find 的 n、i、两个 x 和 r 同时存活，需要 5 个槽位；
+    // main 中内层的 x 在块结束后死亡，k 复用它的槽位
+    cilly(&dir, &["--translate", "f.cil"]);
+    let asm = cilly(&dir, &["--disasm", "f.cby"]);
+    assert!(asm.contains(".func find 1 5 int find"), "{asm}");
+    assert!(asm.contains(".func main 0 2 void main"), "{asm}");
+    assert!(asm.contains(".local 1 x ") && asm.contains(".local 1 k "), "{asm}");
+}
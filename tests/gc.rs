@@ -0,0 +1,94 @@
+// 虚拟机的堆和垃圾回收：不再被引用的数组会被回收，--gc-stats 报告回收的统计；
+// 存活的对象超过 --heap-limit 时以 OutOfMemory 结束，而不是崩溃
+
+mod common;
+
+use common::{TempDir, run};
+
+// 循环 10000 次，每次用槽位 FIRST 中的值和 i（槽位 0）创建数组，存到 a（槽位 1）中，最后打印 a[1]。
+// FIRST 为 0 时上一个数组不再被引用；为 1 时新数组引用上一个数组，所有数组都存活
+const SOURCE: &str = r#"
+.func main 0 2 void main
+    Call main 0
+    Pop
+    Jmp end
+
+main:
+    EnterFrame 2
+    LoadConst 0
+    StoreVar 0
+    LoadNull
+    StoreVar 1
+loop:
+    LoadVar 0
+    LoadConst 10000
+    BinOpLt
+    JmpFalse done
+    LoadVar {FIRST}
+    LoadVar 0
+    NewArray 2
+    StoreVar 1
+    LoadVar 0
+    LoadConst 1
+    BinOpAdd
+    StoreVar 0
+    Jmp loop
+done:
+    LoadVar 1
+    LoadConst 1
+    ArrayGet
+    PrintItem
+    PrintNewline
+    LoadNull
+    Ret
+end:
+"#;
+
+// 汇编 first 对应的程序，返回目标文件名
+fn assemble(dir: &TempDir, first: usize) -> String {
+    let name = format!("list{first}");
+    dir.write(format!("{name}.cas"), SOURCE.replace("{FIRST}", &first.to_string()));
+    assert!(run(dir, &["--asm", &format!("{name}.cas")]).status.success());
+    format!("{name}.cby")
+}
+
+// --gc-stats 输出中 key 之后的数
+fn stat(stderr: &str, key: &str) -> usize {
+    let line = stderr.lines().find(|l| l.starts_with("gc: ")).expect("没有回收统计");
+    let rest = &line[line.find(key).unwrap_or_else(|| panic!("{line} 没有 {key}")) + key.len() + 2..];
+    rest.split_whitespace().next().unwrap().parse().unwrap()
+}
+
+#[test]
+fn gc() {
+    let dir = TempDir::new("gc");
+    let garbage = assemble(&dir, 0);
+    let retained = assemble(&dir, 1);
+
+    for threaded in [&[][..], &["--threaded"][..]] {
+        // 不限制堆大小时两个程序都能执行完
+        for object in [&garbage, &retained] {
+            let output = run(&dir, &[&["--vmrun", object.as_str(), "--gc-stats"][..], threaded].concat());
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "9999\n");
+            assert_eq!(stat(&String::from_utf8(output.stderr).unwrap(), "allocations"), 10000);
+        }
+
+        // 限制堆大小后，不再被引用的数组被回收，存活的字节数不超过上限
+        let output = run(&dir, &[&["--vmrun", &garbage, "--gc-stats", "--heap-limit", "4096"][..], threaded].concat());
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "9999\n");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stat(&stderr, "collections") > 0, "{stderr}");
+        assert_eq!(stat(&stderr, "freed") + stat(&stderr, "live objects"), 10000, "{stderr}");
+        assert!(stat(&stderr, "peak bytes") <= 4096, "{stderr}");
+
+        // 所有数组都存活时超过上限
+        let output = run(&dir, &[&["--vmrun", &retained, "--heap-limit", "4096"][..], threaded].concat());
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with("runtime error: OutOfMemory(4096)"), "{stderr}");
+        assert!(stderr.contains("NewArray(2)"), "{stderr}");
+    }
+}
@@ -0,0 +1,82 @@
+// 全局变量：重复定义时之后的代码使用新的定义，解释器和各个后端的结果一致；栈虚拟机中初始化之前读取的全局变量为 null
+
+use std::path::Path;
+
+mod common;
+
+use common::{TempDir, cilly};
+
+const DUPLICATE: &str = r#"
+var a: i32 = 1;
+var a: i32 = 2;
+var b: i32 = 3;
+
+fn main() {
+    print(a);
+    print(b);
+    a = a + 10;
+    print(a + b);
+}
+"#;
+
+// 解释器、寄存器虚拟机和栈虚拟机（直接翻译和经过 IR）的输出都是 expect
+fn check(dir: &Path, file: &str, expect: &str) {
+    let object = file.replace(".cil", ".cby");
+    assert_eq!(cilly(dir, &["--static", file]), expect);
+    assert_eq!(cilly(dir, &["--regrun", file]), expect);
+    for flags in [&[][..], &["--ir"][..]] {
+        let mut args = vec!["--translate", file];
+        args.extend(flags);
+        cilly(dir, &args);
+        assert_eq!(cilly(dir, &["--vmrun", &object]), expect, "{:?}", flags);
+        assert_eq!(cilly(dir, &["--vmrun", &object, "--threaded"]), expect, "{:?}", flags);
+    }
+}
+
+#[test]
+fn duplicate_global() {
+    let dir = TempDir::new("globals-dup");
+    dir.write("dup.cil", DUPLICATE);
+    check(&dir, "dup.cil", "2\n3\n15\n");
+}
+
+// 目标模块只导出重复定义的全局变量的最后一个定义
+#[test]
+fn duplicate_global_object() {
+    let dir = TempDir::new("globals-object");
+    dir.write("a.cil", "var a: i32 = 1;\nvar a: i32 = 2;\n");
+    dir.write("b.cil", "fn main() {\n    print(a);\n}\n");
+    cilly(&dir, &["--translate", "a.cil", "--object"]);
+    cilly(&dir, &["--translate", "b.cil", "--object"]);
+    cilly(&dir, &["--link", "a.cby", "b.cby", "-o", "out.cby"]);
+    assert_eq!(cilly(&dir, &["--vmrun", "out.cby"]), "2\n");
+}
+
+// 全局变量的初始化代码调用的函数读取之后才定义的全局变量，读到的是 null
+const FORWARD: &str = r#"
+var a: i32 = f();
+var b: i32 = 5;
+
+fn f() -> i32 {
+    return b;
+}
+
+fn main() {
+    print(a);
+    print(b);
+    print(f());
+}
+"#;
+
+#[test]
+fn read_before_init() {
+    let dir = TempDir::new("globals-forward");
+    dir.write("fw.cil", FORWARD);
+    for flags in [&[][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "fw.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "fw.cby"]), "null\n5\n5\n", "{:?}", flags);
+        assert_eq!(cilly(&dir, &["--vmrun", "fw.cby", "--threaded"]), "null\n5\n5\n", "{:?}", flags);
+    }
+}
@@ -0,0 +1,105 @@
+// 中间表示校验的诊断：定义不支配使用、phi 的来源与前驱不一致、块的结束指令无效，以及指令位置与指令个数不一致
+
+use cilly::cy::CompUnitParser;
+use cilly::error::Error;
+use cilly::ir::build::build;
+use cilly::ir::verify::verify;
+use cilly::ir::{BlockId, Inst, Program, Term, Value};
+
+// main 的 IR：
+// bb0: %0 = int 0; jmp bb1
+// bb1: %1 = phi [bb0: %0, bb2: %5]; %2 = int 3; %3 = lt %1, %2; br %3, bb2, bb3
+// bb2: %4 = int 1; %5 = add %1, %4; jmp bb1
+// bb3: print %1; %7 = null; %8 = null; ret %8
+const SOURCE: &str = r#"
+fn main() {
+    var i: i32 = 0;
+    while (i < 3) {
+        i = i + 1;
+    }
+    print(i);
+}
+"#;
+
+fn program() -> Program {
+    let program = build(&CompUnitParser::new().parse(SOURCE).unwrap()).unwrap();
+    verify(&program).unwrap();
+    program
+}
+
+// 校验失败，返回所有问题
+fn errors(program: &Program) -> Vec<String> {
+    match verify(program) {
+        Err(Error::VerifyError(errors)) => errors,
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn use_before_def() {
+    // 同一块中先使用后定义
+    let mut p = program();
+    p.funcs[0].blocks[2].insts.swap(0, 1);
+    assert_eq!(errors(&p), ["main: bb2: %5 使用的 %4 的定义不支配使用处"]);
+
+    // 使用另一个不支配它的块中的定义
+    let mut p = program();
+    p.funcs[0].insts[6] = Inst::Print(Value(5));
+    assert_eq!(errors(&p), ["main: bb3: %6 使用的 %5 的定义不支配使用处"]);
+
+    // 终结指令使用的值不在任何块中
+    let mut p = program();
+    p.funcs[0].blocks[3].insts.pop();
+    assert_eq!(errors(&p), ["main: bb3: 使用的 %8 不在任何块中"]);
+}
+
+#[test]
+fn phi_pred_mismatch() {
+    // 缺少回边的来源
+    let mut p = program();
+    p.funcs[0].insts[1] = Inst::Phi(vec![(BlockId(0), Value(0))]);
+    assert_eq!(errors(&p), ["main: bb1: %1 的来源块与前驱不一致"]);
+
+    // 来源块不是前驱
+    let mut p = program();
+    p.funcs[0].insts[1] = Inst::Phi(vec![(BlockId(0), Value(0)), (BlockId(3), Value(5))]);
+    let errs = errors(&p);
+    assert!(errs.contains(&String::from("main: bb1: %1 的来源块与前驱不一致")), "{:?}", errs);
+
+    // phi 不在块的开头
+    let mut p = program();
+    p.funcs[0].blocks[1].insts.swap(0, 1);
+    assert_eq!(errors(&p), ["main: bb1: %1 不在块的开头"]);
+}
+
+#[test]
+fn bad_terminator() {
+    // 每个块都有结束指令，函数至少有一个块
+    let mut p = program();
+    p.funcs[0].blocks.clear();
+    assert_eq!(errors(&p), ["main: 没有基本块"]);
+
+    // 跳转到不存在的块
+    let mut p = program();
+    p.funcs[0].blocks[2].term = Term::Jmp(BlockId(9));
+    assert_eq!(errors(&p), ["main: bb2: 跳转目标 bb9 不存在"]);
+
+    // 条件跳转的两个目标相同
+    let mut p = program();
+    p.funcs[0].blocks[1].term = Term::Br(Value(3), BlockId(2), BlockId(2));
+    let errs = errors(&p);
+    assert!(errs.contains(&String::from("main: bb1: 两个跳转目标相同")), "{:?}", errs);
+
+    // 提前返回，之后的块不可达
+    let mut p = program();
+    p.funcs[0].blocks[0].term = Term::Ret(Value(0));
+    let errs = errors(&p);
+    assert!(errs.contains(&String::from("main: bb1 不可达")), "{:?}", errs);
+}
+
+#[test]
+fn spans() {
+    let mut p = program();
+    p.funcs[0].spans.pop();
+    assert_eq!(errors(&p), ["main: 有 9 条指令，但有 8 个指令位置"]);
+}
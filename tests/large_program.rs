@@ -0,0 +1,88 @@
+// 翻译超过 65536 条指令的程序：跳转地址、函数入口和调试信息都不能被截断
+
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::vm::VM;
+
+mod common;
+
+use common::{TempDir, cilly};
+
+const REPEAT: usize = 15000;
+
+// work 中 if 的两个分支各有 REPEAT 条语句，执行两次循环分别走两个分支；
+// seven 和 main 的入口在 work 之后
+fn source() -> String {
+    let mut src = String::from("var g: i32 = 1;\n\nfn work() -> i32 {\n    var x: i32 = 0;\n    var i: i32 = 0;\n    while(i < 2) {\n        if(i == 0) {\n");
+    for _ in 0..REPEAT {
+        src.push_str("            x = x + 1;\n");
+    }
+    src.push_str("        } else {\n");
+    for _ in 0..REPEAT {
+        src.push_str("            x = x + 2;\n");
+    }
+    src.push_str("        }\n        i = i + 1;\n    }\n    return x;\n}\n\n");
+    src.push_str("fn seven() -> i32 {\n    return 7;\n}\n\n");
+    src.push_str("fn main() {\n    print(work());\n    print(seven() + g);\n}\n");
+    src
+}
+
+// 循环体有 REPEAT 条语句：开头的 continue 向前跳过整个循环体，末尾的 break 跳出循环，
+// 两者的目标与跳转指令相距都超过 65536 条指令
+fn far_jumps() -> String {
+    let mut src = String::from("fn main() {\n    var x: i32 = 0;\n    var i: i32 = 0;\n    while(1) {\n        i = i + 1;\n        if(i % 2 == 0) {\n            continue;\n        }\n");
+    for _ in 0..REPEAT {
+        src.push_str("        x = x + i * 2 - 1;\n");
+    }
+    src.push_str("        if(i >= 5) {\n            break;\n        }\n    }\n    print(x, i);\n}\n");
+    src
+}
+
+#[test]
+fn translate_large_program() {
+    let src = source();
+    let mut ast = CompUnitParser::new().parse(&src).unwrap();
+    let mut env = Environment::new().with_source("large.cil", &src);
+    let module = translate_module(&mut ast, &mut env).unwrap();
+    assert!(module.code.len() > 100_000, "只生成了 {} 条指令", module.code.len());
+    let seven = module.funcs.iter().find(|f| f.name == "seven").unwrap();
+    assert!(seven.entry > 1 << 16);
+    let debug = module.debug.as_ref().unwrap();
+    assert_eq!(module.location(seven.entry), format!("large.cil:{}", 2 * REPEAT + 15));
+    assert!(debug.lines.iter().any(|(pc, _)| *pc > 1 << 16));
+    VM::new(module).verify().unwrap();
+}
+
+#[test]
+fn run_large_program() {
+    let dir = TempDir::new("large");
+    dir.write("large.cil", source());
+    let expect = format!("{}\n8\n", 3 * REPEAT);
+    for flags in [&[][..], &["--no-peephole"][..]] {
+        let mut args = vec!["--translate", "large.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "large.cby"]), expect);
+        assert_eq!(cilly(&dir, &["--vmrun", "large.cby", "--threaded"]), expect);
+    }
+}
+
+#[test]
+fn run_far_jumps() {
+    let dir = TempDir::new("large-jumps");
+    let src = far_jumps();
+    let module = translate_module(&mut CompUnitParser::new().parse(&src).unwrap(), &mut Environment::new()).unwrap();
+    assert!(module.code.len() > 1 << 16, "只生成了 {} 条指令", module.code.len());
+    dir.write("jumps.cil", src);
+    // i 为 1、3、5 时执行循环体，每次 x 增加 2 * i - 1
+    let expect = format!("{}\n5\n", 15 * REPEAT);
+    assert_eq!(cilly(&dir, &["--static", "jumps.cil"]), expect);
+    for flags in [&[][..], &["--no-peephole"][..], &["--ir"][..]] {
+        let mut args = vec!["--translate", "jumps.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "jumps.cby"]), expect, "{:?}", flags);
+        assert_eq!(cilly(&dir, &["--vmrun", "jumps.cby", "--threaded"]), expect, "{:?}", flags);
+    }
+}
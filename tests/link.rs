@@ -0,0 +1,108 @@
+// 链接分别翻译的目标模块：成功时与整个程序一起翻译的结果相同；符号重复定义、找不到和参数个数不一致时报告所有问题
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+// 三个模块互相引用函数和全局变量，全局变量的初始化按模块的顺序执行
+const UTIL: &str = r#"
+var calls: i32 = 0;
+
+fn square(x: i32) -> i32 {
+    calls = calls + 1;
+    return x * x;
+}
+"#;
+
+const MATH: &str = r#"
+var offset: i32 = 100;
+
+fn sum_squares(n: i32) -> i32 {
+    var s: i32 = 0;
+    var i: i32 = 1;
+    while(i <= n) {
+        s = s + square(i);
+        i = i + 1;
+    }
+    return s + offset;
+}
+"#;
+
+const APP: &str = r#"
+var start: i32 = offset + 1;
+
+fn main() {
+    print(sum_squares(4));
+    print(calls);
+    offset = 0;
+    print(sum_squares(2));
+    print(start);
+}
+"#;
+
+const EXPECT: &str = "130\n4\n5\n101\n";
+
+// 把 (文件名, 源代码) 翻译为目标模块后链接，返回链接失败时报告的问题
+fn link_errors(dir: &TempDir, modules: &[(&str, &str)]) -> Vec<String> {
+    let mut args = vec![String::from("--link")];
+    for (name, source) in modules {
+        dir.write(format!("{name}.cil"), source);
+        cilly(dir, &["--translate", &format!("{name}.cil"), "--object"]);
+        args.push(format!("{name}.cby"));
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = run(dir, &args);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("link failed:"), "{}", stderr);
+    stderr.lines().skip(1).map(|l| l.trim().to_string()).collect()
+}
+
+#[test]
+fn link_modules() {
+    let dir = TempDir::new("link");
+    for (name, source) in [("util", UTIL), ("math", MATH), ("app", APP)] {
+        dir.write(format!("{name}.cil"), source);
+        cilly(&dir, &["--translate", &format!("{name}.cil"), "--object"]);
+    }
+    cilly(&dir, &["--link", "util.cby", "math.cby", "app.cby", "-o", "out.cby"]);
+    assert_eq!(cilly(&dir, &["--vmrun", "out.cby"]), EXPECT);
+    assert_eq!(cilly(&dir, &["--vmrun", "out.cby", "--threaded"]), EXPECT);
+
+    // 与整个程序一起翻译的结果相同
+    dir.write("all.cil", format!("{UTIL}{MATH}{APP}"));
+    cilly(&dir, &["--translate", "all.cil"]);
+    assert_eq!(cilly(&dir, &["--vmrun", "all.cby"]), EXPECT);
+
+    // 目标模块不能直接执行
+    let output = run(&dir, &["--vmrun", "app.cby"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("这是目标模块，需要先链接"));
+}
+
+#[test]
+fn duplicate_symbols() {
+    let dir = TempDir::new("link-dup");
+    let other = "var calls: i32 = 1;\n\nfn square(x: i32) -> i32 {\n    return x;\n}\n";
+    assert_eq!(link_errors(&dir, &[("util", UTIL), ("other", other), ("math", MATH), ("app", APP)]), [
+        "符号 square 重复定义: util.cby 和 other.cby",
+        "符号 calls 重复定义: util.cby 和 other.cby",
+    ]);
+}
+
+#[test]
+fn missing_symbols() {
+    let dir = TempDir::new("link-missing");
+    // 缺少 util：square 和 calls 都找不到
+    assert_eq!(link_errors(&dir, &[("math", MATH), ("app", APP)]), [
+        "math.cby: 未定义的符号 square",
+        "app.cby: 未定义的符号 calls",
+    ]);
+    // 缺少 main
+    assert_eq!(link_errors(&dir, &[("util", UTIL), ("math", MATH)]), ["缺少 main 函数"]);
+    // 缺少 main 时仍然报告找不到的符号
+    assert_eq!(link_errors(&dir, &[("math", MATH)]), ["缺少 main 函数", "math.cby: 未定义的符号 square"]);
+    // 参数个数不一致
+    let bad = "fn main() {\n    print(square(1, 2));\n}\n";
+    assert_eq!(link_errors(&dir, &[("util", UTIL), ("bad", bad)]), ["bad.cby: square 需要 1 个参数，实际为 2 个"]);
+}
@@ -0,0 +1,45 @@
+// 取余 %：结果的符号与被除数相同（-7 % 3 = -1，7 % -3 = 1），i32::MIN % -1 为 0，除数为 0 时报错；
+// 解释器、寄存器虚拟机和栈虚拟机（包括常量折叠之后）的结果一致
+
+use std::process::Output;
+
+mod common;
+
+use common::{TempDir, run};
+
+const SOURCE: &str = r#"fn rem(a: i32, b: i32) -> i32 {
+    return a % b;
+}
+
+fn main() {
+    print(rem(-7, 3), rem(7, -3), rem(-7, -3), rem(7, 3), rem(0, 5));
+    print(-7 % 3, 7 % -3);
+    print(rem(-2147483647 - 1, -1));
+    print(rem(1, 0));
+}
+"#;
+
+const EXPECT: &str = "-1\n1\n-1\n1\n0\n-1\n1\n0\n";
+
+// 最后的 rem(1, 0) 出错，之前的输出不受影响
+fn check(output: Output, what: &str) {
+    assert!(!output.status.success(), "{what}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), EXPECT, "{what}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("除数为 0"), "{what}: {stderr}");
+}
+
+#[test]
+fn modulo() {
+    let dir = TempDir::new("modulo");
+    dir.write("m.cil", SOURCE);
+    check(run(&dir, &["--static", "m.cil"]), "--static");
+    check(run(&dir, &["--regrun", "m.cil"]), "--regrun");
+    for flags in [&[][..], &["--no-peephole"][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "m.cil"];
+        args.extend(flags);
+        assert!(run(&dir, &args).status.success());
+        check(run(&dir, &["--vmrun", "m.cby"]), &format!("{flags:?}"));
+        check(run(&dir, &["--vmrun", "m.cby", "--threaded"]), &format!("{flags:?} --threaded"));
+    }
+}
@@ -0,0 +1,49 @@
+// 原生函数：解释器、栈虚拟机和寄存器虚拟机的结果相同，原生函数返回的错误在解释器和虚拟机中都会报告
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+const SOURCE: &str = r#"
+fn main() {
+    print(abs(-7));
+    print(min(3, 9) + max(3, 9));
+    print(sqrt(17));
+    print(rand() % 100);
+}
+"#;
+
+const EXPECT: &str = "7\n12\n4\n57\n";
+
+// 错误出现在被调用的函数中
+const FAILING: &str = r#"
+fn f(x: i32) -> i32 {
+    return sqrt(x) + 1;
+}
+
+fn main() {
+    print(sqrt(16));
+    var a: i32 = f(-1);
+    print(a);
+}
+"#;
+
+#[test]
+fn natives() {
+    let dir = TempDir::new("natives");
+    dir.write("n.cil", SOURCE);
+    dir.write("e.cil", FAILING);
+
+    assert_eq!(cilly(&dir, &["--static", "n.cil"]), EXPECT);
+    assert_eq!(cilly(&dir, &["--regrun", "n.cil"]), EXPECT);
+    cilly(&dir, &["--translate", "n.cil"]);
+    assert_eq!(cilly(&dir, &["--vmrun", "n.cby"]), EXPECT);
+
+    cilly(&dir, &["--translate", "e.cil"]);
+    for args in [&["--static", "e.cil"][..], &["--vmrun", "e.cby"][..]] {
+        let output = run(&dir, args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "4\n", "{:?}", args);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("sqrt 的参数不能为负数: -1"), "{:?}", args);
+    }
+}
@@ -0,0 +1,86 @@
+// -O0/-O1/-O2：tests/corpus 中的程序在每个优化级别下的输出（包括运行时错误）都和默认的翻译结果相同，优化后的指令数不增加
+
+use std::path::Path;
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+// (文件名, 源代码, 输出)
+const CORPUS: &[(&str, &str, &str)] = &[
+    ("loops", include_str!("corpus/loops.cil"), "760\n0\n18\n7\n-516\n111\n2147483396\n-2147483636\n"),
+    ("algebra", include_str!("corpus/algebra.cil"), "5\n5\n5\n5\n5\n5\n0\n0\n0\n-5\n-5\n-5\n-5\n1\n1\n0\n-1\n23\n1\n1\n2\n10\n100\n4\n16\n4\n"),
+    ("trycatch", include_str!("corpus/trycatch.cil"), "41\n10\n2\n10\n3033\n"),
+    ("recurse", include_str!("corpus/recurse.cil"), "610\n21\n1\n111\n16\n392\n"),
+    // 循环一次都不执行时，循环中对 null 做的加法不能提前计算
+    ("hoistnull", include_str!("corpus/hoistnull.cil"), "0\n3\n5\n"),
+];
+
+// 运行时出错的程序：(文件名, 源代码, 出错之前的输出, 错误信息)。
+// 结果没有用到的除法和对 null 做的加法不能删除，每个级别都要在同一处报错
+const FAILING: &[(&str, &str, &str, &str)] = &[
+    ("divzero", include_str!("corpus/divzero.cil"), "5\n", "除数为 0"),
+    ("deadnull", include_str!("corpus/deadnull.cil"), "5\n", "BinOpAdd 不支持的操作数类型: null 和 i32"),
+];
+
+// 翻译并返回报告的指令数 (优化前, 优化后)
+fn translate(dir: &Path, file: &str, flags: &[&str]) -> (usize, usize) {
+    let mut args = vec!["--translate", file];
+    args.extend(flags);
+    let out = cilly(dir, &args);
+    let line = out.lines().find_map(|l| l.strip_prefix("instructions: ")).expect("没有报告指令数");
+    let (before, after) = line.split_once(" -> ").unwrap();
+    (before.parse().unwrap(), after.parse().unwrap())
+}
+
+#[test]
+fn optimize() {
+    let dir = TempDir::new("optimize");
+
+    for (name, source, expect) in CORPUS {
+        let file = format!("{name}.cil");
+        let object = format!("{name}.cby");
+        dir.write(&file, source);
+
+        cilly(&dir, &["--translate", &file]);
+        assert_eq!(cilly(&dir, &["--vmrun", &object]), *expect, "{name}");
+
+        let mut after = Vec::new();
+        for level in ["-O0", "-O1", "-O2"] {
+            for flags in [&[level][..], &[level, "--no-peephole"][..]] {
+                let (b, a) = translate(&dir, &file, flags);
+                assert!(a <= b, "{name} {flags:?}: {b} -> {a}");
+                if level == "-O0" {
+                    assert_eq!(a, b, "{name} {flags:?}");
+                }
+                if flags.len() == 1 {
+                    after.push(a);
+                }
+                assert_eq!(cilly(&dir, &["--vmrun", &object]), *expect, "{name} {flags:?}");
+                assert_eq!(cilly(&dir, &["--vmrun", &object, "--threaded"]), *expect, "{name} {flags:?}");
+            }
+        }
+        assert!(after[2] <= after[1], "{name}: -O1 {} 条，-O2 {} 条", after[1], after[2]);
+    }
+
+    for (name, source, stdout, error) in FAILING {
+        let file = format!("{name}.cil");
+        let object = format!("{name}.cby");
+        dir.write(&file, source);
+        for level in ["", "-O0", "-O1", "-O2"] {
+            if level.is_empty() {
+                cilly(&dir, &["--translate", &file]);
+            } else {
+                translate(&dir, &file, &[level]);
+            }
+            for flags in [&[][..], &["--threaded"][..]] {
+                let mut args = vec!["--vmrun", &object];
+                args.extend(flags);
+                let output = run(&dir, &args);
+                assert!(!output.status.success(), "{name} {level} {flags:?}");
+                assert_eq!(String::from_utf8(output.stdout).unwrap(), *stdout, "{name} {level} {flags:?}");
+                assert!(String::from_utf8_lossy(&output.stderr).contains(error), "{name} {level} {flags:?}");
+            }
+        }
+    }
+}
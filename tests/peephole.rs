@@ -0,0 +1,101 @@
+// 窥孔优化：合并超级指令、删除多余的跳转后指令变少，所有跳转目标和函数入口重新映射，
+// 有大量分支、循环、break/continue 和 try 的程序在优化前后输出相同
+
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::peephole;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::vm::OpCode;
+
+mod common;
+
+use common::{TempDir, cilly};
+
+const SOURCE: &str = r#"
+var hits: i32 = 0;
+
+fn classify(n: i32) -> i32 {
+    if(n % 15 == 0) {
+        return 3;
+    } else if(n % 5 == 0) {
+        return 2;
+    } else if(n % 3 == 0) {
+        return 1;
+    }
+    return 0;
+}
+
+fn main() {
+    var i: i32 = 0;
+    var sum: i32 = 0;
+    while(i < 40) {
+        i = i + 1;
+        if(i % 2 == 0 && i % 7 != 0) continue;
+        var j: i32 = i;
+        while(j > 0) {
+            j = j - 3;
+            if(j == 4 || j == 10) break;
+            sum = sum + classify(j);
+        }
+        if(i > 35) break;
+        hits = hits + 1;
+    }
+    print(sum, hits);
+    try {
+        while(1) {
+            i = i - 1;
+            if(i < 30) throw i;
+        }
+    } catch(e) {
+        print(e);
+    }
+}
+"#;
+
+const EXPECT: &str = "80\n20\n29\n";
+
+fn target(op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::Jmp(t) | OpCode::JmpTrue(t) | OpCode::JmpFalse(t) | OpCode::CmpJmp(_, t) | OpCode::PushHandler(t) => Some(*t),
+        _ => None,
+    }
+}
+
+#[test]
+fn same_output() {
+    let dir = TempDir::new("peephole");
+    dir.write("p.cil", SOURCE);
+    assert_eq!(cilly(&dir, &["--static", "p.cil"]), EXPECT);
+    for flags in [&["--no-peephole"][..], &[][..], &["-O2", "--no-peephole"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "p.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "p.cby"]), EXPECT, "{:?}", flags);
+        assert_eq!(cilly(&dir, &["--vmrun", "p.cby", "--threaded"]), EXPECT, "{:?}", flags);
+    }
+}
+
+#[test]
+fn fix_ups() {
+    let ast = CompUnitParser::new().parse(SOURCE).unwrap();
+    let module = translate_module(&mut ast.clone(), &mut Environment::new()).unwrap();
+    let optimized = peephole::optimize(module.clone());
+    assert!(optimized.code.len() < module.code.len(), "{} >= {}", optimized.code.len(), module.code.len());
+    for op in ["AddConst", "CmpJmp", "IncVar"] {
+        assert!(optimized.code.iter().any(|c| format!("{:?}", c).starts_with(op)), "{op}");
+    }
+
+    let len = optimized.code.len();
+    for (pc, op) in optimized.code.iter().enumerate() {
+        if let Some(t) = target(op) {
+            // 跳转目标在代码范围内，且跳到 Jmp 的跳转已经直接指向最终目标
+            assert!(t <= len, "{pc}: {op:?}");
+            assert!(!matches!(optimized.code.get(t), Some(OpCode::Jmp(_))), "{pc}: {op:?}");
+        }
+    }
+    // 函数入口仍然是 EnterFrame，名字与优化前一一对应
+    for (before, after) in module.funcs.iter().zip(&optimized.funcs) {
+        assert_eq!(before.name, after.name);
+        assert!(matches!(optimized.code[after.entry], OpCode::EnterFrame(_)), "{}", after.name);
+    }
+}
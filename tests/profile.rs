@@ -0,0 +1,88 @@
+// --profile：虚拟机和解释器按函数统计调用次数和执行的指令（语句）数，表格按自身的计数排序；
+// --profile-collapsed 输出 flamegraph 使用的 collapsed-stack 格式，各行的计数之和等于总数
+
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+use common::{TempDir, cilly, success};
+
+const SOURCE: &str = r#"
+fn fib(n: i32) -> i32 {
+    if (n < 2) {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+
+fn main() {
+    print(fib(10));
+}
+"#;
+
+// 执行成功，返回 (标准输出, 标准错误)
+fn profiled(dir: &Path, args: &[&str]) -> (String, String) {
+    let output = success(dir, args);
+    (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+// 表格中函数的 (名字, 调用次数)，按表格的顺序
+fn calls(table: &str) -> Vec<(String, usize)> {
+    table.lines().skip(1).take_while(|l| !l.is_empty()).map(|l| {
+        let cols: Vec<&str> = l.split_whitespace().collect();
+        (cols[0].to_string(), cols[1].parse().unwrap())
+    }).collect()
+}
+
+// 表格最后报告的总数
+fn executed(table: &str) -> usize {
+    let line = table.lines().find_map(|l| l.strip_prefix("executed: ")).expect("没有总数");
+    line.split_whitespace().next().unwrap().parse().unwrap()
+}
+
+// 检查 collapsed-stack 文件，返回各行计数之和
+fn collapsed(path: &Path) -> usize {
+    let text = fs::read_to_string(path).unwrap();
+    let mut total = 0;
+    let mut deepest = 0;
+    for line in text.lines() {
+        let (stack, count) = line.rsplit_once(' ').unwrap();
+        let frames: Vec<&str> = stack.split(';').collect();
+        assert_eq!(frames[0], "<global>", "{line}");
+        deepest = deepest.max(frames.iter().filter(|f| **f == "fib").count());
+        total += count.parse::<usize>().unwrap();
+    }
+    // fib(10) 递归到 fib(1) 和 fib(0)，最多 10 层
+    assert_eq!(deepest, 10);
+    total
+}
+
+#[test]
+fn profile() {
+    let dir = TempDir::new("profile");
+    dir.write("p.cil", SOURCE);
+    cilly(&dir, &["--translate", "p.cil"]);
+
+    let runs = [
+        vec!["--vmrun", "p.cby"],
+        vec!["--vmrun", "p.cby", "--threaded"],
+        vec!["--static", "p.cil"],
+    ];
+    for args in runs {
+        let folded = dir.join("p.folded");
+        let (stdout, table) = profiled(&dir, &[&args[..], &["--profile-collapsed", "p.folded"]].concat());
+        assert_eq!(stdout, "55\n", "{args:?}");
+        let calls = calls(&table);
+        assert_eq!(calls[0], (String::from("fib"), 177), "{args:?}");
+        assert!(calls.contains(&(String::from("main"), 1)), "{args:?}");
+        assert!(calls.contains(&(String::from("<global>"), 1)), "{args:?}");
+        assert_eq!(collapsed(&folded), executed(&table), "{args:?}");
+        fs::remove_file(folded).unwrap();
+    }
+
+    // 不加 --profile 时不输出统计
+    let (stdout, stderr) = profiled(&dir, &["--vmrun", "p.cby"]);
+    assert_eq!(stdout, "55\n");
+    assert!(stderr.is_empty(), "{stderr}");
+}
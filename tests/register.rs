@@ -0,0 +1,82 @@
+// 寄存器虚拟机：递归、嵌套循环、break/continue、全局变量和取模的结果与解释器、栈虚拟机一致；
+// 执行 fact/feb 这类程序分派的指令条数少于栈虚拟机
+
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::register::translate_register;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::vm::register::RegVM;
+use cilly::vm::VM;
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+const SOURCE: &str = r#"
+var total: i32 = 0;
+
+fn fact(n: i32) -> i32 {
+    if(n == 0) return 1;
+    return n * fact(n - 1);
+}
+
+fn feb(n: i32) -> i32 {
+    if(n < 2) {
+        return 1;
+    } else {
+        return feb(n - 1) + feb(n - 2);
+    }
+}
+
+fn main() {
+    print(fact(10));
+    print(feb(15));
+    var i: i32 = 0;
+    while(i < 10) {
+        i = i + 1;
+        if(i % 3 == 0) continue;
+        var j: i32 = 0;
+        while(1) {
+            if(j >= i) break;
+            total = total + j;
+            j = j + 1;
+        }
+    }
+    print(total);
+    print(-7 % 3, 7 % -3);
+}
+"#;
+
+const EXPECT: &str = "3628800\n987\n111\n-1\n1\n";
+
+#[test]
+fn same_output() {
+    let dir = TempDir::new("register");
+    dir.write("r.cil", SOURCE);
+    assert_eq!(cilly(&dir, &["--regrun", "r.cil"]), EXPECT);
+    assert_eq!(cilly(&dir, &["--static", "r.cil"]), EXPECT);
+    cilly(&dir, &["--translate", "r.cil"]);
+    assert_eq!(cilly(&dir, &["--vmrun", "r.cby"]), EXPECT);
+}
+
+// 寄存器虚拟机不支持异常，翻译时报错
+#[test]
+fn no_exceptions() {
+    let dir = TempDir::new("register-try");
+    dir.write("t.cil", "fn main() {\n    throw 1;\n}\n");
+    let output = run(&dir, &["--regrun", "t.cil"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("寄存器虚拟机不支持异常"));
+}
+
+#[test]
+fn fewer_dispatches() {
+    let ast = CompUnitParser::new().parse(SOURCE).unwrap();
+    let code = translate_module(&mut ast.clone(), &mut Environment::new()).unwrap();
+    let mut vm = VM::new(code);
+    vm.run().unwrap();
+
+    let mut reg = RegVM::new(translate_register(&ast).unwrap());
+    reg.run().unwrap();
+    assert!(reg.steps() < vm.steps(), "register {} >= stack {}", reg.steps(), vm.steps());
+}
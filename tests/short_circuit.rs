@@ -0,0 +1,71 @@
+// && 和 || 短路求值：左操作数已经决定结果时不计算右操作数，解释器和各虚拟机的结果一致
+
+mod common;
+
+use common::{TempDir, cilly};
+
+// touch 记录被调用的次数；b 为 0 时计算 a / b 会出错
+const SOURCE: &str = r#"
+var count: i32 = 0;
+
+fn touch(v: i32) -> i32 {
+    count = count + 1;
+    return v;
+}
+
+fn main() {
+    var a: i32 = 10;
+    var b: i32 = 0;
+    if(b != 0 && a / b > 1) {
+        print(1);
+    } else {
+        print(2);
+    }
+    if(b == 0 || a / b > 1) {
+        print(3);
+    }
+    var r: i32 = touch(0) && touch(1);
+    print(r);
+    print(count);
+    r = touch(1) || touch(1);
+    print(r);
+    print(count);
+    r = touch(1) && touch(0);
+    print(r);
+    print(count);
+    r = touch(0) || touch(5);
+    print(r);
+    print(count);
+    r = touch(0) || touch(0) || touch(1) && touch(0);
+    print(r);
+    print(count);
+    while(b < 3 && touch(1)) {
+        b = b + 1;
+    }
+    print(count);
+}
+"#;
+
+const EXPECT: &str = "2\n3\n0\n1\n1\n2\n0\n4\n1\n6\n0\n10\n13\n";
+
+#[test]
+fn short_circuit() {
+    let dir = TempDir::new("short-circuit");
+    dir.write("sc.cil", SOURCE);
+
+    assert_eq!(cilly(&dir, &["--static", "sc.cil"]), EXPECT);
+    assert_eq!(cilly(&dir, &["--regrun", "sc.cil"]), EXPECT);
+    for flags in [&[][..], &["--no-peephole"][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "sc.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "sc.cby"]), EXPECT, "{:?}", flags);
+        assert_eq!(cilly(&dir, &["--vmrun", "sc.cby", "--threaded"]), EXPECT, "{:?}", flags);
+    }
+
+    // 直接翻译时 && 和 || 编译为条件跳转，不再使用 BinOpAnd 和 BinOpOr
+    cilly(&dir, &["--translate", "sc.cil", "--no-peephole"]);
+    let asm = cilly(&dir, &["--disasm", "sc.cby"]);
+    assert!(asm.contains("JmpTrue") && asm.contains("JmpFalse"), "{asm}");
+    assert!(!asm.contains("BinOpAnd") && !asm.contains("BinOpOr"), "{asm}");
+}
@@ -0,0 +1,131 @@
+// 在 getint 处保存快照后恢复执行，输出与不中断的执行相同；rand 的状态也保存在快照中。
+// 截断或改动过的快照在恢复时报告 SnapshotError，不会让虚拟机崩溃
+
+use std::path::Path;
+
+use cilly::bytecode_translation::asm::assemble;
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::error::Error;
+use cilly::vm::module::Module;
+use cilly::vm::{Status, VM};
+
+mod common;
+
+use common::{TempDir, run_with_input};
+
+const SOURCE: &str = r#"
+var total: i32 = 0;
+
+fn main() {
+    print(rand() % 100);
+    print(rand() % 100);
+    total = total + 3;
+    var x: i32 = getint();
+    print(rand() % 100);
+    print(rand() % 100);
+    print(total + x);
+}
+"#;
+
+const EXPECT: &str = "57\n53\n0\n91\n7\n";
+
+// input 为标准输入的内容
+fn cilly(dir: &Path, args: &[&str], input: &str) -> String {
+    let output = run_with_input(dir, args, input);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn snapshot_rand() {
+    let dir = TempDir::new("snapshot");
+    dir.write("r.cil", SOURCE);
+
+    cilly(&dir, &["--translate", "r.cil"], "");
+    assert_eq!(cilly(&dir, &["--vmrun", "r.cby"], "4\n"), EXPECT);
+    let before = cilly(&dir, &["--vmrun", "r.cby", "--snapshot-at-getint", "--snapshot", "r.snap"], "");
+    let after = cilly(&dir, &["--resume", "r.snap"], "4\n");
+    assert_eq!(before + &after, EXPECT);
+}
+
+// 数组互相引用，快照中的堆有对象和引用
+const ARRAYS: &str = r#"
+.func main 0 2 void main
+    Call main 0
+    Pop
+    Jmp end
+
+main:
+    EnterFrame 2
+    LoadConst 0
+    StoreVar 0
+    LoadNull
+    StoreVar 1
+loop:
+    LoadVar 0
+    LoadConst 100
+    BinOpLt
+    JmpFalse done
+    LoadVar 1
+    LoadVar 0
+    NewArray 2
+    StoreVar 1
+    LoadVar 0
+    LoadConst 1
+    BinOpAdd
+    StoreVar 0
+    Jmp loop
+done:
+    LoadVar 1
+    LoadConst 1
+    ArrayGet
+    PrintItem
+    PrintNewline
+    LoadNull
+    Ret
+end:
+"#;
+
+// 执行 fuel 条指令后暂停，返回快照
+fn snapshot(module: Module, fuel: u64) -> Vec<u8> {
+    let mut vm = VM::new(module);
+    vm.set_fuel(fuel);
+    assert_eq!(vm.run().unwrap(), Status::OutOfFuel);
+    let mut buf = Vec::new();
+    vm.save(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn corrupted() {
+    let mut ast = CompUnitParser::new().parse(include_str!("corpus/trycatch.cil")).unwrap();
+    let trycatch = translate_module(&mut ast, &mut Environment::new()).unwrap();
+    // 分别在 check 抛出异常的 try 块中和数组创建到一半时暂停
+    for buf in [snapshot(trycatch, 60), snapshot(assemble(ARRAYS).unwrap(), 300)] {
+        VM::load(&buf).unwrap();
+        // 截断在任何位置都报告文件不完整
+        for len in 0..buf.len() {
+            assert!(matches!(VM::load(&buf[..len]), Err(Error::SnapshotError(_))), "截断为 {len} 字节");
+        }
+        // 改动任意一个字节：恢复失败，或者恢复后执行时报告错误，都不会崩溃
+        let mut rejected = 0;
+        for i in 0..buf.len() {
+            for mask in [0x01, 0x80, 0xff] {
+                let mut bad = buf.clone();
+                bad[i] ^= mask;
+                match VM::load(&bad) {
+                    Ok(mut vm) => {
+                        if vm.verify().is_ok() {
+                            vm.set_fuel(10000);
+                            let _ = vm.run();
+                        }
+                    },
+                    Err(_) => rejected += 1,
+                }
+            }
+        }
+        assert!(rejected > buf.len(), "{rejected}");
+    }
+}
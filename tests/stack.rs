@@ -0,0 +1,143 @@
+// 调用约定：每次调用恰好留下一个值（没有返回值的函数为 null），表达式语句丢弃它，
+// 没有返回值的函数执行到结尾或 return; 时隐式返回，有返回值的函数执行到结尾时返回 null；循环执行多少次操作数栈的高度都不增长
+
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::peephole;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::ir::opt::{OptLevel, PassManager};
+use cilly::vm::module::Module;
+use cilly::vm::{Status, VM};
+
+mod common;
+
+use common::{TempDir, cilly};
+
+// 循环 2000 次，每次执行有返回值和没有返回值的调用、变量和算术表达式语句
+const SOURCE: &str = r#"var calls: i32 = 0;
+
+fn add(a: i32, b: i32) -> i32 {
+    return a + b;
+}
+
+fn fact(n: i32) -> i32 {
+    if(n == 0) return 1;
+    return n * fact(n - 1);
+}
+
+fn touch() {
+    calls = calls + 1;
+}
+
+fn early(n: i32) {
+    if(n % 2 == 0) {
+        return;
+    }
+    touch();
+}
+
+fn main() {
+    var i: i32 = 0;
+    while(i < 2000) {
+        add(1, 2);
+        fact(3);
+        touch();
+        early(i);
+        i;
+        i + 1;
+        i = i + 1;
+    }
+    print(calls, i);
+}
+"#;
+
+const EXPECT: &str = "3000\n2000\n";
+
+// 每执行这么多条指令暂停一次，检查栈的高度
+const FUEL: u64 = 37;
+
+fn modules() -> Vec<(&'static str, Module)> {
+    let ast = CompUnitParser::new().parse(SOURCE).unwrap();
+    let env = Environment::new();
+    let direct = translate_module(&mut ast.clone(), &mut Environment::new()).unwrap();
+    let mut program = cilly::ir::build::build(&ast).unwrap();
+    let ir = cilly::ir::lower::lower(&program, &env);
+    PassManager::new(OptLevel::O2).run(&mut program).unwrap();
+    let optimized = cilly::ir::lower::lower(&program, &env);
+    vec![
+        ("direct", direct.clone()),
+        ("peephole", peephole::optimize(direct)),
+        ("ir", ir),
+        ("O2", peephole::optimize(optimized)),
+    ]
+}
+
+#[test]
+fn constant_height() {
+    for (name, module) in modules() {
+        let mut vm = VM::new(module);
+        vm.verify().unwrap();
+        let mut highest = 0;
+        let mut pauses = 0;
+        loop {
+            vm.set_fuel(FUEL);
+            let status = vm.run().unwrap();
+            highest = highest.max(vm.stack_height());
+            if status == Status::Finished {
+                break;
+            }
+            pauses += 1;
+        }
+        assert!(pauses > 2000, "{name}: {pauses}");
+        // fact(3) 递归时栈中最多有几个值；如果有值留在栈中，会随循环次数增长
+        assert!(highest < 16, "{name}: {highest}");
+        // 全局代码丢弃 main 的返回值，结束时栈为空
+        assert_eq!(vm.stack_height(), 0, "{name}");
+    }
+}
+
+#[test]
+fn same_output() {
+    let dir = TempDir::new("stack");
+    dir.write("s.cil", SOURCE);
+    assert_eq!(cilly(&dir, &["--static", "s.cil"]), EXPECT);
+    assert_eq!(cilly(&dir, &["--regrun", "s.cil"]), EXPECT);
+    for flags in [&[][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "s.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "s.cby"]), EXPECT, "{:?}", flags);
+    }
+}
+
+// 有返回值的函数在 x <= 0 时执行到结尾，所有后端都能执行，调用者丢弃返回的 null
+const FALL_OFF: &str = r#"fn f(x: i32) -> i32 {
+    if (x > 0) {
+        return 1;
+    }
+}
+
+fn main() {
+    var i: i32 = 0;
+    while(i < 3) {
+        f(i - 1);
+        i = i + 1;
+    }
+    print(f(1));
+}
+"#;
+
+#[test]
+fn fall_off_end() {
+    let dir = TempDir::new("stack-fall-off");
+    dir.write("f.cil", FALL_OFF);
+    assert_eq!(cilly(&dir, &["--static", "f.cil"]), "1\n");
+    assert_eq!(cilly(&dir, &["--regrun", "f.cil"]), "1\n");
+    for flags in [&[][..], &["--no-peephole"][..], &["--ir"][..], &["-O2"][..]] {
+        let mut args = vec!["--translate", "f.cil"];
+        args.extend(flags);
+        cilly(&dir, &args);
+        assert_eq!(cilly(&dir, &["--vmrun", "f.cby"]), "1\n", "{:?}", flags);
+        assert_eq!(cilly(&dir, &["--vmrun", "f.cby", "--threaded"]), "1\n", "{:?}", flags);
+    }
+}
@@ -0,0 +1,61 @@
+// 线索化分派与逐条 match 分派执行同一模块：分派的指令条数相同，运行时错误、未捕获的异常和调用栈也相同
+
+use cilly::bytecode_translation::environment::Environment;
+use cilly::bytecode_translation::peephole;
+use cilly::bytecode_translation::translate::translate_module;
+use cilly::cy::CompUnitParser;
+use cilly::vm::module::Module;
+use cilly::vm::VM;
+
+const CORPUS: &[(&str, &str)] = &[
+    ("loops", include_str!("corpus/loops.cil")),
+    ("algebra", include_str!("corpus/algebra.cil")),
+    ("trycatch", include_str!("corpus/trycatch.cil")),
+    ("recurse", include_str!("corpus/recurse.cil")),
+    ("divzero", include_str!("corpus/divzero.cil")),
+    ("deadnull", include_str!("corpus/deadnull.cil")),
+];
+
+// 在 f 中抛出，没有被捕获
+const UNCAUGHT: &str = r#"
+fn f(n: i32) -> i32 {
+    if(n == 0) throw 42;
+    return f(n - 1);
+}
+
+fn main() {
+    print(f(3));
+}
+"#;
+
+fn module(source: &str) -> Module {
+    let ast = CompUnitParser::new().parse(source).unwrap();
+    translate_module(&mut ast.clone(), &mut Environment::new()).unwrap()
+}
+
+// 执行结束时的结果（出错时为错误的调试输出）和分派的指令条数
+fn run(module: &Module, threaded: bool) -> (String, u64) {
+    let mut vm = VM::new(module.clone());
+    vm.set_threaded(threaded);
+    let res = format!("{:?}", vm.run());
+    (res, vm.steps())
+}
+
+#[test]
+fn same_as_match() {
+    let mut sources = CORPUS.to_vec();
+    sources.push(("uncaught", UNCAUGHT));
+    for (name, source) in sources {
+        let code = module(source);
+        for code in [peephole::optimize(code.clone()), code] {
+            let (res, steps) = run(&code, false);
+            assert_eq!(run(&code, true), (res.clone(), steps), "{name}");
+            assert!(steps > 0, "{name}");
+            match name {
+                "divzero" | "deadnull" => assert!(res.starts_with("Err(RuntimeError("), "{name}: {res}"),
+                "uncaught" => assert!(res.starts_with("Err(Uncaught(") && res.contains("42"), "{name}: {res}"),
+                _ => assert!(res.starts_with("Ok("), "{name}: {res}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+// --trace：每条执行的指令写到标准错误或文件，可以按函数和 pc 范围过滤，--trace-json 每行一个 JSON 对象；程序的输出不受影响
+
+use std::path::Path;
+
+mod common;
+
+use common::{TempDir, success};
+
+const SOURCE: &str = r#"
+fn add(a: i32, b: i32) -> i32 {
+    return a + b;
+}
+
+fn main() {
+    print(add(1, 2));
+}
+"#;
+
+// 不做窥孔优化时 add 的代码
+const ADD: &[&str] = &["EnterFrame(2)", "LoadVar(0)", "LoadVar(1)", "BinOpAdd", "Ret"];
+
+// 执行 t.cby，检查程序的输出，返回标准错误中的跟踪记录
+fn traced(dir: &Path, flags: &[&str]) -> Vec<String> {
+    let mut args = vec!["--vmrun", "t.cby"];
+    args.extend(flags);
+    let output = success(dir, &args);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n", "{flags:?}");
+    String::from_utf8(output.stderr).unwrap().lines().map(String::from).collect()
+}
+
+// 最小的 JSON 解析器，用来检查 --trace-json 的每一行都是合法的 JSON
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Json {
+        let mut p = Parser { s: text.as_bytes(), pos: 0 };
+        let v = p.value();
+        p.ws();
+        assert_eq!(p.pos, p.s.len(), "{text}: 多余的内容");
+        v
+    }
+    // 对象中字段 key 的值
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => &fields.iter().find(|(k, _)| k == key).unwrap_or_else(|| panic!("没有 {key}")).1,
+            v => panic!("{v:?} 不是对象"),
+        }
+    }
+    fn str(&self) -> &str {
+        match self {
+            Json::Str(s) => s,
+            v => panic!("{v:?} 不是字符串"),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn ws(&mut self) {
+        while self.pos < self.s.len() && b" \t\r\n".contains(&self.s[self.pos]) {
+            self.pos += 1;
+        }
+    }
+    fn peek(&self) -> u8 {
+        *self.s.get(self.pos).unwrap_or_else(|| panic!("意外的结尾"))
+    }
+    fn expect(&mut self, c: u8) {
+        assert_eq!(self.peek() as char, c as char, "位置 {}", self.pos);
+        self.pos += 1;
+    }
+    fn literal(&mut self, word: &str, v: Json) -> Json {
+        assert!(self.s[self.pos..].starts_with(word.as_bytes()), "位置 {}: 应为 {word}", self.pos);
+        self.pos += word.len();
+        v
+    }
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+    fn value(&mut self) -> Json {
+        self.ws();
+        match self.peek() {
+            b'n' => self.literal("null", Json::Null),
+            b't' => self.literal("true", Json::Bool(true)),
+            b'f' => self.literal("false", Json::Bool(false)),
+            b'"' => Json::Str(self.string()),
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.ws();
+                if self.peek() == b']' {
+                    self.pos += 1;
+                    return Json::Array(items);
+                }
+                loop {
+                    items.push(self.value());
+                    self.ws();
+                    if self.peek() == b']' {
+                        self.pos += 1;
+                        return Json::Array(items);
+                    }
+                    self.expect(b',');
+                }
+            },
+            b'{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.ws();
+                if self.peek() == b'}' {
+                    self.pos += 1;
+                    return Json::Object(fields);
+                }
+                loop {
+                    self.ws();
+                    let key = self.string();
+                    self.ws();
+                    self.expect(b':');
+                    fields.push((key, self.value()));
+                    self.ws();
+                    if self.peek() == b'}' {
+                        self.pos += 1;
+                        return Json::Object(fields);
+                    }
+                    self.expect(b',');
+                }
+            },
+            _ => self.number(),
+        }
+    }
+    // -?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn number(&mut self) -> Json {
+        let start = self.pos;
+        if self.peek() == b'-' {
+            self.pos += 1;
+        }
+        let first = self.peek();
+        let n = self.digits();
+        assert!(n == 1 || (n > 1 && first != b'0'), "位置 {start}: 不是数");
+        if self.pos < self.s.len() && self.s[self.pos] == b'.' {
+            self.pos += 1;
+            assert!(self.digits() > 0, "位置 {start}: 小数点后没有数字");
+        }
+        if self.pos < self.s.len() && matches!(self.s[self.pos], b'e' | b'E') {
+            self.pos += 1;
+            if matches!(self.peek(), b'+' | b'-') {
+                self.pos += 1;
+            }
+            assert!(self.digits() > 0, "位置 {start}: 指数没有数字");
+        }
+        let text = std::str::from_utf8(&self.s[start..self.pos]).unwrap();
+        Json::Num(text.parse().unwrap())
+    }
+    fn string(&mut self) -> String {
+        self.expect(b'"');
+        let mut bytes = Vec::new();
+        loop {
+            let c = self.peek();
+            self.pos += 1;
+            match c {
+                b'"' => return String::from_utf8(bytes).unwrap(),
+                b'\\' => {
+                    let e = self.peek();
+                    self.pos += 1;
+                    let c = match e {
+                        b'"' | b'\\' | b'/' => e as char,
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hex = std::str::from_utf8(&self.s[self.pos..self.pos + 4]).unwrap();
+                            self.pos += 4;
+                            char::from_u32(u32::from_str_radix(hex, 16).unwrap()).unwrap()
+                        },
+                        _ => panic!("位置 {}: 无效的转义", self.pos),
+                    };
+                    bytes.extend(c.to_string().as_bytes());
+                },
+                c if c < 0x20 => panic!("位置 {}: 字符串中的控制字符", self.pos),
+                c => bytes.push(c),
+            }
+        }
+    }
+}
+
+#[test]
+fn trace() {
+    let dir = TempDir::new("trace");
+    dir.write("t.cil", SOURCE);
+    success(&dir, &["--translate", "t.cil", "--no-peephole"]);
+
+    for threaded in [&[][..], &["--threaded"][..]] {
+        // 不过滤时执行的每条指令都有一行，第一条是全局代码调用 main
+        let all = traced(&dir, &[&["--trace"][..], threaded].concat());
+        assert!(all[0].trim_start().starts_with("0  Call(1, 0)"), "{}", all[0]);
+        for op in ADD.iter().chain(&["PrintItem", "PrintNewline"]) {
+            assert!(all.iter().any(|l| l.contains(op)), "{op}");
+        }
+
+        // 只跟踪 add，JSON 格式写到文件
+        let lines = traced(&dir, &[&["--trace-json", "--trace-fn", "add", "--trace-out", "t.jsonl"][..], threaded].concat());
+        assert!(lines.is_empty(), "{lines:?}");
+        let json: Vec<Json> = dir.read("t.jsonl").split(|&b| b == b'\n').filter(|l| !l.is_empty())
+            .map(|l| Json::parse(std::str::from_utf8(l).unwrap())).collect();
+        let ops: Vec<&str> = json.iter().map(|l| l.get("op").str()).collect();
+        assert_eq!(ops, ADD);
+        for l in &json {
+            assert_eq!(l.get("fn"), &Json::Num(0.0));
+            assert_eq!(l.get("depth"), &Json::Num(2.0));
+        }
+        // BinOpAdd 执行前栈顶是第二个参数，Ret 执行前栈顶是结果
+        let tops: Vec<&Json> = json.iter().map(|l| l.get("top")).collect();
+        assert_eq!(tops, [&Json::Null, &Json::Null, &Json::Num(1.0), &Json::Num(2.0), &Json::Num(3.0)]);
+
+        // 函数编号和 pc 范围 [lo, hi) 一起过滤
+        let lines = traced(&dir, &[&["--trace-fn", "0", "--trace-pc", "4..6"][..], threaded].concat());
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert!(lines[0].contains("LoadVar(0)") && lines[1].contains("LoadVar(1)"), "{lines:?}");
+    }
+}
+
+// 创建数组，栈顶为引用的指令
+const ARRAY: &str = r#"
+.func main 0 1 void main
+    Call main 0
+    Pop
+    Jmp end
+
+main:
+    EnterFrame 1
+    LoadConst 1
+    LoadConst 2
+    NewArray 2
+    StoreVar 0
+    LoadVar 0
+    LoadConst 1
+    ArrayGet
+    PrintItem
+    PrintNewline
+    LoadNull
+    Ret
+end:
+"#;
+
+#[test]
+fn json_refs() {
+    let dir = TempDir::new("trace-json");
+    dir.write("a.cas", ARRAY);
+    success(&dir, &["--asm", "a.cas"]);
+    let output = success(&dir, &["--vmrun", "a.cby", "--trace-json"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let json: Vec<Json> = stderr.lines().map(Json::parse).collect();
+    assert_eq!(json.len(), 15, "{stderr}");
+    // StoreVar 和 LoadConst 1 执行前栈顶是数组的引用
+    let refs: Vec<&str> = json.iter().filter(|l| matches!(l.get("top"), Json::Object(_))).map(|l| l.get("op").str()).collect();
+    assert_eq!(refs, ["StoreVar(0)", "LoadConst(1)"]);
+    let store = json.iter().find(|l| l.get("op").str() == "StoreVar(0)").unwrap();
+    let Json::Object(fields) = store.get("top") else { unreachable!() };
+    assert!(matches!(fields[..], [(ref key, Json::Num(_))] if key == "ref"), "{fields:?}");
+}
@@ -0,0 +1,78 @@
+// 虚拟机中值的类型：布尔值在算术运算中按 0/1 参与，与比较运算的结果一致；
+// null、引用和类型不符的对象参与运算时报告 TypeError，两种分派方式的信息相同
+
+mod common;
+
+use common::{TempDir, cilly, run};
+
+// main 的代码为 BODY
+const TEMPLATE: &str = r#"
+.func main 0 0 void main
+    Call main 0
+    Pop
+    Jmp end
+
+main:
+    EnterFrame 0
+{BODY}
+    LoadNull
+    Ret
+end:
+"#;
+
+// (main 中的指令, TypeError 的信息)
+const ERRORS: &[(&str, &str)] = &[
+    ("LoadNull\nLoadConst 1\nBinOpAdd\nPop", "BinOpAdd 不支持的操作数类型: null 和 i32"),
+    ("LoadConst 1\nNewArray 1\nLoadConst 1\nBinOpAdd\nPop", "BinOpAdd 不支持的操作数类型: ref 和 i32"),
+    ("LoadConst 1\nLoadNull\nBinOpLt\nPop", "BinOpLt 不支持的操作数类型: i32 和 null"),
+    ("LoadNull\nAddConst 1\nPop", "BinOpAdd 不支持的操作数类型: null 和 i32"),
+    ("LoadNull\nUniOpNeg\nPop", "UniOpNeg 不支持 null"),
+    ("LoadNull\nUniOpNot\nPop", "条件的类型不能是 null"),
+    ("LoadConst 1\nLoadConst 0\nArrayGet\nPop", "需要引用类型，实际为 i32"),
+    ("LoadConst 1\nNewArray 1\nLoadTrue\nArrayGet\nPop", "下标的类型不能是 bool"),
+    ("LoadConst 1\nNewStruct 1\nArrayLen\nPop", "ArrayLen 的操作数不是数组"),
+    ("LoadConst 1\nNewStruct 1\nLoadConst 0\nLoadConst 5\nArraySet", "ArraySet 的操作数不是数组"),
+    ("LoadConst 1\nNewArray 1\nGetField 0\nPop", "GetField 的操作数不是结构体"),
+    ("LoadConst 1\nNewArray 1\nLoadConst 5\nSetField 0", "SetField 的操作数不是结构体"),
+];
+
+// (main 中的指令, 输出)
+const BOOLS: &[(&str, &str)] = &[
+    ("LoadTrue\nLoadConst 2\nBinOpAdd\nPrintItem", "3"),
+    ("LoadTrue\nLoadFalse\nBinOpMul\nPrintItem", "0"),
+    ("LoadTrue\nUniOpNeg\nPrintItem", "-1"),
+    ("LoadTrue\nLoadConst 1\nBinOpEq\nPrintItem", "1"),
+    ("LoadFalse\nAddConst 5\nPrintItem", "5"),
+];
+
+// 汇编 body 对应的程序，返回目标文件名
+fn assemble(dir: &TempDir, name: &str, body: &str) -> String {
+    dir.write(format!("{name}.cas"), TEMPLATE.replace("{BODY}", body));
+    cilly(dir, &["--asm", &format!("{name}.cas")]);
+    format!("{name}.cby")
+}
+
+#[test]
+fn type_errors() {
+    let dir = TempDir::new("values");
+    for (i, (body, message)) in ERRORS.iter().enumerate() {
+        let object = assemble(&dir, &format!("e{i}"), body);
+        for threaded in [&[][..], &["--threaded"][..]] {
+            let output = run(&dir, &[&["--vmrun", &object][..], threaded].concat());
+            assert!(!output.status.success(), "{body:?}");
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert!(stderr.starts_with(&format!("runtime error: TypeError({message:?})")), "{body:?} {threaded:?}: {stderr}");
+        }
+    }
+}
+
+#[test]
+fn bools_as_ints() {
+    let dir = TempDir::new("values-bool");
+    for (i, (body, expect)) in BOOLS.iter().enumerate() {
+        let object = assemble(&dir, &format!("b{i}"), &format!("{body}\nPrintNewline"));
+        for threaded in [&[][..], &["--threaded"][..]] {
+            assert_eq!(cilly(&dir, &[&["--vmrun", &object][..], threaded].concat()), format!("{expect}\n"), "{body:?}");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// 字节码校验的诊断：栈下溢、跳转目标越界、缺少 Ret、汇合点栈高度不一致、函数编号越界和不支持的指令
+
+use cilly::error::Error;
+use cilly::native::Registry;
+use cilly::vm::module::{Function, Module};
+use cilly::vm::verify::verify;
+use cilly::vm::OpCode::{self, *};
+
+// 全局代码调用 main，之后是 main 的代码
+fn module(main: Vec<OpCode>, nlocals: usize) -> Module {
+    let mut code = vec![Call(0, 0), Pop, Jmp(3 + main.len())];
+    code.extend(main);
+    Module {
+        funcs: vec![Function { name: String::from("main"), entry: 3, arity: 0, nlocals, ret: None }],
+        globals: Vec::new(),
+        code,
+        debug: None,
+        link: None,
+    }
+}
+
+// 校验失败，返回所有问题
+fn errors(module: &Module) -> Vec<String> {
+    match verify(module, &Registry::with_defaults()) {
+        Err(Error::VerifyError(errors)) => errors,
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn valid() {
+    let m = module(vec![EnterFrame(1), LoadConst(1), StoreVar(0), LoadVar(0), PrintItem, LoadNull, Ret], 1);
+    verify(&m, &Registry::with_defaults()).unwrap();
+}
+
+#[test]
+fn stack_underflow() {
+    let m = module(vec![EnterFrame(0), LoadConst(1), BinOpAdd, LoadNull, Ret], 0);
+    assert_eq!(errors(&m), ["main pc 5 BinOpAdd: 需要 2 个操作数，栈中只有 1 个"]);
+}
+
+#[test]
+fn bad_jump_target() {
+    // 跳到全局代码中
+    let m = module(vec![EnterFrame(0), Jmp(1), LoadNull, Ret], 0);
+    assert_eq!(errors(&m), ["main pc 4 Jmp(1): 目标地址 1 不在 main 内 [3, 7)"]);
+    let mut m = module(vec![EnterFrame(0), LoadNull, Ret], 0);
+    m.code[2] = Jmp(99);
+    assert_eq!(errors(&m), ["<global> pc 2 Jmp(99): 目标地址 99 不在 <global> 内 [0, 3)"]);
+}
+
+#[test]
+fn missing_ret() {
+    // main 之后还有函数 f，跳到或执行到 main 的结尾都会落入 f
+    let mut m = module(vec![EnterFrame(0), LoadTrue, JmpTrue(8), LoadNull, Ret], 0);
+    let entry = m.code.len();
+    m.funcs.push(Function { name: String::from("f"), entry, arity: 0, nlocals: 0, ret: None });
+    m.code.extend([EnterFrame(0), LoadNull, Ret]);
+    m.code[2] = Jmp(entry + 3);
+    assert_eq!(errors(&m), ["main pc 5 JmpTrue(8): 跳到 main 的结尾 8，缺少 Ret"]);
+    m.code[5] = JmpTrue(6);
+    m.code[7] = Pop;
+    assert_eq!(errors(&m), ["main pc 7 Pop: main 执行完后落入下一个函数，缺少 Ret"]);
+    // 全局代码之后是 main
+    let mut m = module(vec![EnterFrame(0), LoadNull, Ret], 0);
+    m.code[2] = Jmp(3);
+    assert_eq!(errors(&m), ["<global> pc 2 Jmp(3): 跳到 <global> 的结尾 3，缺少 Ret"]);
+}
+
+#[test]
+fn inconsistent_depth() {
+    // 一条路径在栈中多留了一个值
+    let m = module(vec![EnterFrame(0), LoadTrue, JmpFalse(7), LoadConst(1), LoadNull, Ret], 0);
+    assert_eq!(errors(&m), ["main pc 6 LoadConst(1): 到达 pc 7 时栈高度为 1，另一条路径为 0"]);
+}
+
+#[test]
+fn bad_function_index() {
+    let m = module(vec![EnterFrame(0), Call(5, 0), Ret], 0);
+    assert_eq!(errors(&m), ["main pc 4 Call(5, 0): 函数编号 5 超出函数表的长度 1"]);
+    let m = module(vec![EnterFrame(0), Call(0, 2), Ret], 0);
+    assert_eq!(errors(&m), ["main pc 4 Call(main, 2): main 需要 0 个参数，实际为 2 个"]);
+}
+
+#[test]
+fn bad_slot_and_ret() {
+    let m = module(vec![EnterFrame(1), LoadVar(3), Ret], 1);
+    assert_eq!(errors(&m), ["main pc 4 LoadVar(#3): 槽位 3 超出栈帧的槽位个数 1"]);
+    let m = module(vec![EnterFrame(0), LoadNull, LoadNull, Ret], 0);
+    assert_eq!(errors(&m), ["main pc 6 Ret: 返回时栈高度应为 1，实际为 2"]);
+}
+
+#[test]
+fn unsupported() {
+    // PC 栈只能由 Call 和 Ret 维护
+    for op in [LoadPC, StorePC, MakeClosure] {
+        let m = module(vec![EnterFrame(0), op, LoadNull, Ret], 0);
+        assert_eq!(errors(&m), [format!("main pc 4 {:?}: 不支持的指令", op)]);
+    }
+}
+
+#[test]
+fn reports_every_function() {
+    let mut m = module(vec![EnterFrame(0), Pop, LoadNull, Ret], 0);
+    m.code[1] = BinOpSub;
+    assert_eq!(errors(&m).len(), 2);
+}